@@ -0,0 +1,261 @@
+use crate::credentials::{self, CredentialContext};
+use crate::error::{self, AppError};
+use crate::identity;
+use crate::options::{self, Options};
+use std::path::Path;
+
+/// One `[[account]]` entry of an `--accounts-file`.
+#[derive(Debug, Clone)]
+pub struct AccountEntry {
+    pub account_id: String,
+    /// Overrides `--external-id` for this account's role.
+    pub external_id: Option<String>,
+    /// Stamped on records as `account_name`; the account id if not given.
+    pub label: String,
+    /// Where the entry starts in the file, for messages about it.
+    pub line: usize,
+    /// Scanned instead of every region, narrowed to the positional region
+    /// when that isn't `all`.
+    pub regions: Option<Vec<String>>,
+    pub role_arn: String
+}
+
+/// Reads an `--accounts-file`, a TOML file of `[[account]]` tables:
+///
+/// ```toml
+/// [[account]]
+/// account_id = "123456789012"
+/// role = "InventoryAudit"        # or role_arn = "arn:aws:iam::...:role/..."
+/// external_id = "optional"
+/// regions = ["eu-west-1", "us-east-1"]
+/// label = "payments-prod"
+/// ```
+///
+/// Only strings and one-line arrays of strings are understood. Every entry
+/// is checked before any is scanned, and problems name the line.
+pub fn load(path: &Path) -> Result<Vec<AccountEntry>, AppError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    let at = |line: usize, message: String| AppError::usage(format!("{}:{}: {}", path.display(), line, message));
+    let mut entries: Vec<AccountEntry> = Vec::new();
+    let mut current: Option<RawEntry> = None;
+    for (n, raw) in text.lines().enumerate() {
+        let line = n + 1;
+        let trimmed = raw.trim();
+        if trimmed.is_empty() || trimmed.starts_with('#') {
+            continue;
+        }
+        if trimmed == "[[account]]" {
+            if let Some(entry) = current.take() {
+                let start = entry.line;
+                entries.push(entry.validate().map_err(|why| at(start, why))?);
+            }
+            current = Some(RawEntry { line: line, ..RawEntry::default() });
+            continue;
+        }
+        let entry = match current.as_mut() {
+            Some(e) => e,
+            None => return Err(at(line, "expected [[account]] before any settings".to_string()))
+        };
+        let (key, value) = match trimmed.split_once('=') {
+            Some((k, v)) => (k.trim(), v.trim()),
+            None => return Err(at(line, format!("expected key = value, found '{}'", trimmed)))
+        };
+        match key {
+            "account_id" => entry.account_id = Some(string(value).map_err(|why| at(line, why))?),
+            "external_id" => entry.external_id = Some(string(value).map_err(|why| at(line, why))?),
+            "label" => entry.label = Some(string(value).map_err(|why| at(line, why))?),
+            "regions" => entry.regions = Some(array(value).map_err(|why| at(line, why))?),
+            "role" => entry.role = Some(string(value).map_err(|why| at(line, why))?),
+            "role_arn" => entry.role_arn = Some(string(value).map_err(|why| at(line, why))?),
+            other => return Err(at(line, format!("unknown setting '{}', expected one of: account_id, external_id, label, regions, role, role_arn", other)))
+        }
+    }
+    if let Some(entry) = current.take() {
+        let start = entry.line;
+        entries.push(entry.validate().map_err(|why| at(start, why))?);
+    }
+    for (i, entry) in entries.iter().enumerate() {
+        if let Some(first) = entries[..i].iter().find(|e| e.account_id == entry.account_id) {
+            return Err(at(entry.line, format!("account {} is already listed on line {}", entry.account_id, first.line)));
+        }
+    }
+    match entries.is_empty() {
+        true => Err(AppError::usage(format!("{} has no [[account]] entries", path.display()))),
+        false => Ok(entries)
+    }
+}
+
+/// One context per entry, assuming its role with the base credentials
+/// (after `--role-chain` and `--assume-role`, when given). Entries whose
+/// role can't be set up are reported and left out.
+pub fn contexts(options: &Options) -> Vec<CredentialContext> {
+    let base = match credentials::contexts(options).into_iter().next() {
+        Some(ctx) => ctx,
+        None => {
+            error::report(&AppError::usage("--accounts-file needs working base credentials".to_string()), options.error_format);
+            return Vec::new()
+        }
+    };
+    let hops = options.role_chain.len() + options.assume_role.iter().count();
+    let mut contexts = Vec::new();
+    for entry in options.accounts.iter() {
+        let external_id = entry.external_id.clone().or_else(|| options.external_id.clone());
+        match base.assume_role(&entry.role_arn, external_id, hops, options) {
+            Ok(ctx) => contexts.push(CredentialContext {
+                account_id: Some(entry.account_id.clone()),
+                account_name: Some(entry.label.clone()),
+                regions: entry.regions.clone(),
+                ..ctx
+            }),
+            Err(why) => error::report(&why, options.error_format)
+        }
+    }
+    contexts
+}
+
+#[derive(Default)]
+struct RawEntry {
+    account_id: Option<String>,
+    external_id: Option<String>,
+    label: Option<String>,
+    line: usize,
+    regions: Option<Vec<String>>,
+    role: Option<String>,
+    role_arn: Option<String>
+}
+
+impl RawEntry {
+    fn validate(self) -> Result<AccountEntry, String> {
+        let account_id = match self.account_id {
+            Some(id) if id.len() == 12 && id.chars().all(|c: char| c.is_ascii_digit()) => id,
+            Some(id) => return Err(format!("account_id '{}' is not a 12 digit account id", id)),
+            None => return Err("[[account]] entry has no account_id".to_string())
+        };
+        if let Some(regions) = &self.regions {
+            if let Some(bad) = regions.iter().find(|r| !identity::is_region_name(r)) {
+                return Err(format!("unknown region '{}' for account {}", bad, account_id));
+            }
+        }
+        let role_arn = match (self.role, self.role_arn) {
+            (Some(_), Some(_)) => return Err(format!("account {} sets both role and role_arn", account_id)),
+            (Some(name), None) => {
+                let partition = self.regions.as_ref()
+                    .and_then(|r| r.first())
+                    .map_or("aws", |r| identity::region_partition(r));
+                format!("arn:{}:iam::{}:role/{}", partition, account_id, name)
+            },
+            (None, Some(arn)) => match options::account_id(&arn) {
+                Some(id) if id == account_id && arn.contains(":role/") => arn,
+                Some(id) if id != account_id => return Err(format!("role_arn {} is in account {}, not {}", arn, id, account_id)),
+                _ => return Err(format!("invalid role_arn '{}', expected arn:aws:iam::<account-id>:role/<name>", arn))
+            },
+            (None, None) => return Err(format!("account {} has neither role nor role_arn", account_id))
+        };
+        Ok(AccountEntry {
+            account_id: account_id.clone(),
+            external_id: self.external_id,
+            label: self.label.unwrap_or(account_id),
+            line: self.line,
+            regions: self.regions,
+            role_arn: role_arn
+        })
+    }
+}
+
+/// A double quoted string, optionally followed by a comment.
+fn string(value: &str) -> Result<String, String> {
+    let rest = match value.strip_prefix('"') {
+        Some(r) => r,
+        None => return Err(format!("expected a quoted string, found '{}'", value))
+    };
+    match rest.find('"') {
+        Some(end) if is_comment(&rest[end + 1..]) => Ok(rest[..end].to_string()),
+        Some(_) => Err(format!("unexpected text after the string in '{}'", value)),
+        None => Err(format!("unterminated string '{}'", value))
+    }
+}
+
+/// `["a", "b"]` on a single line, optionally followed by a comment.
+fn array(value: &str) -> Result<Vec<String>, String> {
+    let inner = match value.strip_prefix('[').and_then(|v| v.find(']').map(|end| (&v[..end], &v[end + 1..]))) {
+        Some((inner, rest)) if is_comment(rest) => inner,
+        _ => return Err(format!("expected an array of strings on one line, found '{}'", value))
+    };
+    inner.split(',')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .map(string)
+        .collect()
+}
+
+fn is_comment(rest: &str) -> bool {
+    let rest = rest.trim();
+    rest.is_empty() || rest.starts_with('#')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn manifest(name: &str, text: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("ec2-monitoring-{}-{}.toml", std::process::id(), name));
+        std::fs::write(&path, text).unwrap();
+        path
+    }
+
+    /// The `line: message` part of the error loading `text`.
+    fn failure(name: &str, text: &str) -> String {
+        let path = manifest(name, text);
+        let err = load(&path).unwrap_err();
+        std::fs::remove_file(&path).unwrap();
+        err.message.strip_prefix(&format!("{}:", path.display())).unwrap().to_string()
+    }
+
+    #[test]
+    fn reads_a_manifest() {
+        let path = manifest("accounts", "# scanned nightly\n[[account]]\naccount_id = \"123456789012\"\nrole = \"InventoryAudit\"\nexternal_id = \"s3cret\" # from the vendor\nregions = [\"eu-west-1\", \"us-east-1\"]\nlabel = \"payments-prod\"\n\n[[account]]\naccount_id = \"210987654321\"\nrole_arn = \"arn:aws:iam::210987654321:role/Audit\"\n");
+        let entries = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert_eq!(entries[0].account_id, "123456789012");
+        assert_eq!(entries[0].role_arn, "arn:aws:iam::123456789012:role/InventoryAudit");
+        assert_eq!(entries[0].external_id.as_deref(), Some("s3cret"));
+        assert_eq!(entries[0].regions, Some(vec!["eu-west-1".to_string(), "us-east-1".to_string()]));
+        assert_eq!(entries[0].label, "payments-prod");
+        assert_eq!(entries[0].line, 2);
+        assert_eq!(entries[1].role_arn, "arn:aws:iam::210987654321:role/Audit");
+        assert_eq!(entries[1].external_id, None);
+        assert_eq!(entries[1].regions, None);
+        assert_eq!(entries[1].label, "210987654321");
+        assert_eq!(entries[1].line, 9);
+    }
+
+    #[test]
+    fn rejects_an_entry_without_account_id() {
+        assert_eq!(failure("no-id", "[[account]]\naccount_id = \"123456789012\"\nrole = \"Audit\"\n\n[[account]]\nrole = \"Audit\"\n"), "5: [[account]] entry has no account_id");
+    }
+
+    #[test]
+    fn rejects_a_bad_role_arn() {
+        assert_eq!(failure("bad-arn", "[[account]]\naccount_id = \"123456789012\"\nrole_arn = \"arn:aws:iam::123456789012:user/Audit\"\n"), "1: invalid role_arn 'arn:aws:iam::123456789012:user/Audit', expected arn:aws:iam::<account-id>:role/<name>");
+    }
+
+    #[test]
+    fn rejects_a_duplicate_account() {
+        assert_eq!(failure("duplicate", "[[account]]\naccount_id = \"123456789012\"\nrole = \"Audit\"\n[[account]]\naccount_id = \"123456789012\"\nrole = \"Other\"\n"), "4: account 123456789012 is already listed on line 1");
+    }
+
+    #[test]
+    fn rejects_an_unterminated_string() {
+        assert_eq!(failure("unterminated", "[[account]]\naccount_id = \"123456789012\"\nrole = \"Audit\n"), "3: unterminated string '\"Audit'");
+    }
+
+    #[test]
+    fn rejects_an_unknown_key() {
+        assert_eq!(failure("unknown", "[[account]]\naccount_id = \"123456789012\"\nrole = \"Audit\"\nregion = \"eu-west-1\"\n"), "4: unknown setting 'region', expected one of: account_id, external_id, label, regions, role, role_arn");
+    }
+}
@@ -0,0 +1,258 @@
+use crate::query::{self, Expr, Op, Operand, QueryError};
+use crate::Details;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+
+/// How bad a failed assertion is, mapped to Nagios-style exit codes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Severity {
+    Warning,
+    Critical
+}
+
+impl Severity {
+    pub fn exit_code(self) -> i32 {
+        match self {
+            Severity::Warning => 1,
+            Severity::Critical => 2
+        }
+    }
+
+    fn label(self) -> &'static str {
+        match self {
+            Severity::Warning => "WARNING",
+            Severity::Critical => "CRITICAL"
+        }
+    }
+}
+
+/// A parsed `--assert`, e.g.
+/// `warning: count(state=running, region=eu-west-1) <= 50`.
+///
+/// The optional `warning:` or `critical:` prefix sets the severity, which
+/// defaults to critical. `count(...)` takes comma separated conditions that
+/// must all hold: `field=value` (an empty value matches a missing field) or
+/// any `--query` expression, such as `age_days > 90`. The count is then
+/// compared against a number with `== != < <= > >=`.
+#[derive(Debug, Clone)]
+pub struct Assertion {
+    pub text: String,
+    pub severity: Severity,
+    filter: Option<Expr>,
+    op: Op,
+    threshold: f64
+}
+
+pub fn parse(input: &str) -> Result<Assertion, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut pos = skip_whitespace(&chars, 0);
+    let mut severity = Severity::Critical;
+    let word = identifier(&chars, pos);
+    let after_word = skip_whitespace(&chars, pos + word.chars().count());
+    if chars.get(after_word) == Some(&':') {
+        severity = match word.as_str() {
+            "warning" => Severity::Warning,
+            "critical" => Severity::Critical,
+            _ => return Err(error(pos, "expected 'warning' or 'critical' before ':'"))
+        };
+        pos = skip_whitespace(&chars, after_word + 1);
+    }
+    if identifier(&chars, pos) != "count" {
+        return Err(error(pos, "expected count(...)"))
+    }
+    let open = skip_whitespace(&chars, pos + "count".len());
+    if chars.get(open) != Some(&'(') {
+        return Err(error(open, "expected '(' after count"))
+    }
+    let close = closing_paren(&chars, open)?;
+    let filter = conditions(&chars, open + 1, close)?;
+    pos = skip_whitespace(&chars, close + 1);
+    let rest: String = chars[pos.min(chars.len())..].iter().collect();
+    let (op, width) = match operator(&rest) {
+        Some(found) => found,
+        None => return Err(error(pos, "expected a comparison operator after count(...)"))
+    };
+    pos = skip_whitespace(&chars, pos + width);
+    let number: String = chars[pos.min(chars.len())..].iter().collect();
+    let threshold = match number.trim().parse::<f64>() {
+        Ok(n) => n,
+        Err(_) if number.trim().is_empty() => return Err(error(pos, "expected a number to compare the count with")),
+        Err(_) => return Err(error(pos, &format!("invalid number '{}'", number.trim())))
+    };
+    Ok(Assertion { text: input.trim().to_string(), severity: severity, filter: filter, op: op, threshold: threshold })
+}
+
+/// `None` when the assertion holds, otherwise a message describing it.
+pub fn check(assertion: &Assertion, details: &[Details], now: DateTime<Utc>) -> Option<String> {
+    let count = match &assertion.filter {
+        Some(filter) => details.iter().filter(|d| query::matches(filter, d, now)).count(),
+        None => details.len()
+    };
+    let n = count as f64;
+    let holds = match assertion.op {
+        Op::Eq => n == assertion.threshold,
+        Op::Ne => n != assertion.threshold,
+        Op::Lt => n < assertion.threshold,
+        Op::Le => n <= assertion.threshold,
+        Op::Gt => n > assertion.threshold,
+        Op::Ge => n >= assertion.threshold
+    };
+    match holds {
+        true => None,
+        false => Some(format!("{}: {} failed, count is {}", assertion.severity.label(), assertion.text, count))
+    }
+}
+
+fn conditions(chars: &[char], start: usize, end: usize) -> Result<Option<Expr>, QueryError> {
+    let mut parts = Vec::new();
+    let (mut depth, mut quote, mut part_start) = (0, None, start);
+    for (i, c) in chars.iter().enumerate().take(end).skip(start) {
+        match (*c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {},
+            ('\'', None) | ('"', None) => quote = Some(*c),
+            ('(', None) => depth += 1,
+            (')', None) => depth -= 1,
+            (',', None) if depth == 0 => {
+                parts.push((part_start, i));
+                part_start = i + 1;
+            },
+            _ => {}
+        }
+    }
+    parts.push((part_start, end));
+    if parts.len() == 1 && chars[start..end].iter().all(|c| c.is_whitespace()) {
+        return Ok(None)
+    }
+    let mut filter: Option<Expr> = None;
+    for (from, to) in parts.into_iter() {
+        let condition = condition(chars, from, to)?;
+        filter = Some(match filter {
+            Some(left) => Expr::And(Box::new(left), Box::new(condition)),
+            None => condition
+        });
+    }
+    Ok(filter)
+}
+
+/// A single condition: the `field=value` shorthand, or a `--query`
+/// expression with error positions shifted to the whole assertion.
+fn condition(chars: &[char], from: usize, to: usize) -> Result<Expr, QueryError> {
+    let text: String = chars[from..to].iter().collect();
+    if text.trim().is_empty() {
+        return Err(error(from, "empty condition in count(...)"))
+    }
+    if let Some((field, value)) = shorthand(&text) {
+        return Ok(Expr::Compare(Operand::Field(field), Op::Eq, Operand::Literal(value)))
+    }
+    query::parse(&text).map_err(|e| QueryError { position: e.position + from, message: e.message })
+}
+
+fn shorthand(text: &str) -> Option<(String, Value)> {
+    let at = text.find('=')?;
+    let (field, value) = (text[..at].trim(), text[at + 1..].trim());
+    let bare = !field.is_empty() && field.chars().all(|c: char| c.is_alphanumeric() || "_.:-".contains(c));
+    if !bare || value.starts_with('=') {
+        return None
+    }
+    let unquoted = value.trim_matches(|c: char| c == '\'' || c == '"');
+    let literal = match (value.is_empty(), unquoted.parse::<f64>()) {
+        (true, _) => Value::Null,
+        (false, Ok(n)) if !value.starts_with(|c: char| c == '\'' || c == '"') => Value::from(n),
+        _ => Value::String(unquoted.to_string())
+    };
+    Some((field.to_string(), literal))
+}
+
+fn closing_paren(chars: &[char], open: usize) -> Result<usize, QueryError> {
+    let (mut depth, mut quote) = (0, None);
+    for (i, c) in chars.iter().enumerate().skip(open) {
+        match (*c, quote) {
+            (c, Some(q)) if c == q => quote = None,
+            (_, Some(_)) => {},
+            ('\'', None) | ('"', None) => quote = Some(*c),
+            ('(', None) => depth += 1,
+            (')', None) => {
+                depth -= 1;
+                if depth == 0 {
+                    return Ok(i)
+                }
+            },
+            _ => {}
+        }
+    }
+    Err(error(open, "unclosed '(' in count(...)"))
+}
+
+fn operator(text: &str) -> Option<(Op, usize)> {
+    let ops = [("==", Op::Eq), ("!=", Op::Ne), ("<=", Op::Le), (">=", Op::Ge), ("<", Op::Lt), (">", Op::Gt)];
+    ops.iter().find(|(symbol, _)| text.starts_with(symbol)).map(|(symbol, op)| (*op, symbol.len()))
+}
+
+fn identifier(chars: &[char], from: usize) -> String {
+    chars.iter().skip(from).take_while(|c| c.is_alphabetic()).collect()
+}
+
+fn skip_whitespace(chars: &[char], mut pos: usize) -> usize {
+    while pos < chars.len() && chars[pos].is_whitespace() {
+        pos += 1;
+    }
+    pos
+}
+
+fn error(position: usize, message: &str) -> QueryError {
+    QueryError { position: position, message: message.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(state: &str, region: &str) -> Details {
+        Details { region: region.to_string(), state: Some(state.to_string()), ..Default::default() }
+    }
+
+    fn rejected(input: &str) -> (usize, String) {
+        let e = parse(input).unwrap_err();
+        (e.position, e.message)
+    }
+
+    #[test]
+    fn parses_severity_conditions_and_threshold() {
+        let details = vec![instance("running", "eu-west-1"), instance("running", "eu-west-1"), instance("stopped", "eu-west-1")];
+        let assertion = parse("warning: count(state=running, region=eu-west-1) <= 1").unwrap();
+        assert_eq!(assertion.severity, Severity::Warning);
+        assert_eq!((assertion.op, assertion.threshold), (Op::Le, 1.0));
+        assert_eq!(
+            check(&assertion, &details, Utc::now()),
+            Some("WARNING: warning: count(state=running, region=eu-west-1) <= 1 failed, count is 2".to_string())
+        );
+        assert_eq!(check(&parse("count(state == 'stopped' || state == 'running') == 3").unwrap(), &details, Utc::now()), None);
+    }
+
+    #[test]
+    fn defaults_to_critical_and_counts_everything() {
+        let assertion = parse("count() > 0").unwrap();
+        assert_eq!(assertion.severity, Severity::Critical);
+        assert!(assertion.filter.is_none());
+        assert_eq!(check(&assertion, &[], Utc::now()), Some("CRITICAL: count() > 0 failed, count is 0".to_string()));
+    }
+
+    #[test]
+    fn empty_shorthand_value_matches_a_missing_field() {
+        let details = vec![instance("running", "eu-west-1")];
+        assert_eq!(check(&parse("count(tags.Owner=) == 1").unwrap(), &details, Utc::now()), None);
+    }
+
+    #[test]
+    fn malformed_assertions_report_where() {
+        assert_eq!(rejected("notice: count() > 1"), (0, "expected 'warning' or 'critical' before ':'".to_string()));
+        assert_eq!(rejected("total(state=running) > 1"), (0, "expected count(...)".to_string()));
+        assert_eq!(rejected("count(state=running"), (5, "unclosed '(' in count(...)".to_string()));
+        assert_eq!(rejected("count(state=running,) > 1"), (20, "empty condition in count(...)".to_string()));
+        assert_eq!(rejected("count(state=running) 5"), (21, "expected a comparison operator after count(...)".to_string()));
+        assert_eq!(rejected("count(state=running) >"), (22, "expected a number to compare the count with".to_string()));
+        assert_eq!(rejected("count() > many"), (10, "invalid number 'many'".to_string()));
+        assert_eq!(rejected("count(age_days >) > 1").0, 16);
+    }
+}
@@ -0,0 +1,68 @@
+use regex::Regex;
+use rusoto_core::RusotoError;
+use std::error::Error;
+use std::sync::OnceLock;
+
+const REQUEST_ID_HEADER: &str = "x-amzn-requestid";
+
+/// Compiled on first use and shared by every later call.
+static INSTANCE_ID: OnceLock<Regex> = OnceLock::new();
+
+/// EC2 reports API failures as `RusotoError::Unknown` with an XML body, so the
+/// error code and message have to be picked out of it by hand.
+pub fn error_code<E>(err: &RusotoError<E>) -> Option<String> {
+    match err {
+        RusotoError::Unknown(res) => xml_element(&res.body_as_str(), "Code"),
+        _ => None
+    }
+}
+
+/// A one-line description of the error, preferring the API's own message.
+pub fn describe<E: Error + 'static>(err: &RusotoError<E>) -> String {
+    match err {
+        RusotoError::Unknown(res) => {
+            let body = res.body_as_str();
+            match (xml_element(&body, "Code"), xml_element(&body, "Message")) {
+                (Some(code), Some(message)) => format!("{}: {}", code, message),
+                _ => err.to_string()
+            }
+        },
+        _ => err.to_string()
+    }
+}
+
+/// The AWS request id of a failed call, for quoting in support cases. Taken
+/// from the response header where present, else from the EC2 error body.
+pub fn request_id<E>(err: &RusotoError<E>) -> Option<String> {
+    match err {
+        RusotoError::Unknown(res) => res.headers.get(REQUEST_ID_HEADER)
+            .cloned()
+            .or_else(|| xml_element(&res.body_as_str(), "RequestID")),
+        _ => None
+    }
+}
+
+/// Whether the credentials could not be obtained because STS refused the
+/// AssumeRole call, which points at the role's trust policy or ExternalId
+/// rather than at the caller's own credentials.
+pub fn assume_role_denied<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::Credentials(why) => why.message.contains("AccessDenied"),
+        _ => false
+    }
+}
+
+/// Instance ids mentioned in an error message, such as the ids listed by
+/// `InvalidInstanceID.NotFound`.
+pub fn instance_ids(message: &str) -> Vec<String> {
+    let re = INSTANCE_ID.get_or_init(|| Regex::new(r"i-[0-9a-f]+").unwrap());
+    re.find_iter(message).map(|m| m.as_str().to_string()).collect()
+}
+
+fn xml_element(body: &str, name: &str) -> Option<String> {
+    let open = format!("<{}>", name);
+    let close = format!("</{}>", name);
+    let start = body.find(&open)? + open.len();
+    let end = body[start..].find(&close)? + start;
+    Some(body[start..end].to_string())
+}
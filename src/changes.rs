@@ -0,0 +1,129 @@
+use crate::Details;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Instance id to where and in what state it was, as of one watch or serve
+/// cycle.
+pub type Snapshot = BTreeMap<String, Seen>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Seen {
+    pub region: String,
+    pub state: Option<String>
+}
+
+/// How much an instance changed between two cycles, least to most.
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum Severity {
+    /// Started, stopped or otherwise moved between non-terminal states.
+    State,
+    /// Launched, terminated or gone from the scan.
+    Lifecycle
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct StateChange {
+    pub from: Option<String>,
+    pub instance_id: String,
+    pub to: Option<String>
+}
+
+/// What differs between the previous cycle and this one.
+#[derive(Debug, Clone, Default, Serialize)]
+pub struct Changes {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    pub state_changes: Vec<StateChange>
+}
+
+impl Changes {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.state_changes.is_empty()
+    }
+
+    /// Only the changes at or above `min`. Added and removed instances are
+    /// always lifecycle changes, and so is a move into a terminal state.
+    pub fn at_least(&self, min: Severity) -> Changes {
+        Changes {
+            added: self.added.clone(),
+            removed: self.removed.clone(),
+            state_changes: self.state_changes.iter()
+                .filter(|change| severity(change) >= min)
+                .cloned()
+                .collect()
+        }
+    }
+
+    /// "N added, M removed, K state changes".
+    pub fn summary(&self) -> String {
+        format!("{} added, {} removed, {} state changes", self.added.len(), self.removed.len(), self.state_changes.len())
+    }
+}
+
+pub fn snapshot(details: &[Details]) -> Snapshot {
+    details.iter()
+        .filter_map(|d| Some((d.instance_id.clone()?, Seen { region: d.region.clone(), state: d.state.clone() })))
+        .collect()
+}
+
+/// Adds the instances `previous` had in the `failed` regions that `current`
+/// is missing. A region that couldn't be scanned says nothing about its
+/// instances, which would otherwise be reported as removed and then as
+/// added again once it answers.
+pub fn carry_forward(previous: &Snapshot, current: &mut Snapshot, failed: &BTreeSet<String>) {
+    for (id, seen) in previous.iter() {
+        if failed.contains(&seen.region) && !current.contains_key(id) {
+            current.insert(id.clone(), seen.clone());
+        }
+    }
+}
+
+pub fn compare(previous: &Snapshot, current: &Snapshot) -> Changes {
+    Changes {
+        added: current.keys().filter(|id| !previous.contains_key(*id)).cloned().collect(),
+        removed: previous.keys().filter(|id| !current.contains_key(*id)).cloned().collect(),
+        state_changes: current.iter()
+            .filter_map(|(id, seen)| match previous.get(id) {
+                Some(before) if before.state != seen.state => Some(StateChange {
+                    from: before.state.clone(),
+                    instance_id: id.clone(),
+                    to: seen.state.clone()
+                }),
+                _ => None
+            })
+            .collect()
+    }
+}
+
+fn severity(change: &StateChange) -> Severity {
+    match change.to.as_deref() {
+        Some("shutting-down") | Some("terminated") => Severity::Lifecycle,
+        _ => Severity::State
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seen(region: &str, state: &str) -> Seen {
+        Seen { region: region.to_string(), state: Some(state.to_string()) }
+    }
+
+    #[test]
+    fn failed_regions_are_carried_forward_not_removed() {
+        let previous: Snapshot = vec![
+            ("i-1".to_string(), seen("eu-west-1", "running")),
+            ("i-2".to_string(), seen("us-east-1", "running")),
+            ("i-3".to_string(), seen("us-east-1", "running"))
+        ].into_iter().collect();
+        let mut current: Snapshot = vec![("i-3".to_string(), seen("us-east-1", "stopped"))].into_iter().collect();
+        carry_forward(&previous, &mut current, &vec!["eu-west-1".to_string()].into_iter().collect());
+        assert_eq!(current.get("i-1"), Some(&seen("eu-west-1", "running")));
+        let changes = compare(&previous, &current);
+        assert!(changes.added.is_empty());
+        assert_eq!(changes.removed, vec!["i-2"]);
+        assert_eq!(changes.state_changes.len(), 1);
+        assert_eq!(changes.state_changes[0].instance_id, "i-3");
+    }
+}
@@ -0,0 +1,99 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError};
+use crate::options::Options;
+use crate::Details;
+use chrono::{Duration as ChronoDuration, Utc};
+use log::warn;
+use rusoto_cloudwatch::{CloudWatch, CloudWatchClient, Dimension, GetMetricStatisticsInput, MetricDatum, PutMetricDataInput};
+use rusoto_core::Region;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+
+/// One datapoint per hour keeps a 60 day window under CloudWatch's 1440
+/// datapoint limit for a single request.
+const PERIOD_SECS: i64 = 3600;
+
+/// Namespace for `--emit-cloudwatch` when `--namespace` isn't given.
+const DEFAULT_NAMESPACE: &str = "EC2Inventory";
+
+/// The most datums PutMetricData accepts in one call.
+const PUT_BATCH: usize = 20;
+
+/// Fills `cpu_p95` for running instances: the 95th percentile of the hourly
+/// p95 CPUUtilization over `--cpu-window`.
+pub async fn fill_cpu(ctx: &CredentialContext, region: &Region, options: &Options, details: &mut [Details]) {
+    let client = CloudWatchClient::new_with_client(ctx.client_for(region.name()), region.clone());
+    let end = Utc::now();
+    let start = end - ChronoDuration::from_std(options.cpu_window).unwrap_or_else(|_| ChronoDuration::days(14));
+    for d in details.iter_mut().filter(|d| d.state.as_deref() == Some("running")) {
+        let instance_id = match &d.instance_id {
+            Some(id) => id.clone(),
+            None => continue
+        };
+        let request = GetMetricStatisticsInput {
+            dimensions: Some(vec![Dimension { name: "InstanceId".to_string(), value: instance_id.clone() }]),
+            end_time: end.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            extended_statistics: Some(vec!["p95".to_string()]),
+            metric_name: "CPUUtilization".to_string(),
+            namespace: "AWS/EC2".to_string(),
+            period: PERIOD_SECS,
+            start_time: start.format("%Y-%m-%dT%H:%M:%SZ").to_string(),
+            ..Default::default()
+        };
+        match client.get_metric_statistics(request).await {
+            Ok(stats) => {
+                let mut hourly: Vec<f64> = stats.datapoints.unwrap_or_default().into_iter()
+                    .filter_map(|p| p.extended_statistics?.get("p95").cloned())
+                    .collect();
+                d.cpu_p95 = percentile(&mut hourly, 0.95);
+            },
+            Err(why) => warn!("couldn't read CPU metrics for {} in {}: {}", instance_id, d.region, why)
+        }
+    }
+}
+
+/// Publishes an `InstanceCount` metric per region and state for
+/// `--emit-cloudwatch`. Metrics go to `--region`, or the default region when
+/// scanning them all, under the given context's account.
+pub async fn put_counts(ctx: &CredentialContext, options: &Options, details: &[Details]) {
+    let mut counts: BTreeMap<(&str, &str), usize> = BTreeMap::new();
+    for d in details.iter() {
+        *counts.entry((d.region.as_str(), d.state.as_deref().unwrap_or("unknown"))).or_insert(0) += 1;
+    }
+    let region = Region::from_str(&options.region).unwrap_or_default();
+    let client = CloudWatchClient::new_with_client(ctx.client_for(region.name()), region.clone());
+    let timestamp = Utc::now().format("%Y-%m-%dT%H:%M:%SZ").to_string();
+    let data: Vec<MetricDatum> = counts.into_iter()
+        .map(|((instance_region, state), count)| MetricDatum {
+            dimensions: Some(vec![
+                Dimension { name: "Region".to_string(), value: instance_region.to_string() },
+                Dimension { name: "State".to_string(), value: state.to_string() }
+            ]),
+            metric_name: "InstanceCount".to_string(),
+            timestamp: Some(timestamp.clone()),
+            unit: Some("Count".to_string()),
+            value: Some(count as f64),
+            ..Default::default()
+        })
+        .collect();
+    for batch in data.chunks(PUT_BATCH) {
+        let request = PutMetricDataInput {
+            metric_data: batch.to_vec(),
+            namespace: options.namespace.clone().unwrap_or_else(|| DEFAULT_NAMESPACE.to_string())
+        };
+        if let Err(why) = client.put_metric_data(request).await {
+            let err = AppError::aws(region.name(), format!("couldn't publish instance counts to CloudWatch: {}", why));
+            error::report(&err, options.error_format);
+            return;
+        }
+    }
+}
+
+fn percentile(values: &mut [f64], p: f64) -> Option<f64> {
+    if values.is_empty() {
+        return None;
+    }
+    values.sort_by(|a, b| a.partial_cmp(b).unwrap_or(std::cmp::Ordering::Equal));
+    let rank = ((values.len() as f64) * p).ceil() as usize;
+    values.get(rank.saturating_sub(1)).cloned()
+}
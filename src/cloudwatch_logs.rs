@@ -0,0 +1,207 @@
+use crate::credentials;
+use crate::error::AppError;
+use crate::options::Options;
+use chrono::Utc;
+use log::warn;
+use rusoto_core::{Region, RusotoError};
+use rusoto_logs::{
+    CloudWatchLogs, CloudWatchLogsClient, CreateLogStreamError, CreateLogStreamRequest, DescribeLogStreamsRequest,
+    InputLogEvent, PutLogEventsError, PutLogEventsRequest
+};
+use serde_json::Value;
+use std::str::FromStr;
+use std::time::Duration;
+
+/// CloudWatch Logs counts this many bytes on top of each message.
+const EVENT_OVERHEAD: usize = 26;
+const MAX_EVENT_BYTES: usize = 256 * 1024;
+const MAX_BATCH_BYTES: usize = 1024 * 1024;
+const MAX_BATCH_EVENTS: usize = 10_000;
+
+/// Put before the kept part of a record too large for one event.
+const TRUNCATED: &str = "[truncated] ";
+
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_DELAY_MS: u64 = 200;
+
+/// Writes one log event per record, as NDJSON lines, to a new stream in
+/// `--cloudwatch-logs-group` named after the run's time. Records too large
+/// for an event are cut short and marked `[truncated]`. Logs go to
+/// `--region`, or the default region when scanning them all.
+pub async fn put_records(options: &Options, group: &str, records: &[Value]) -> Result<usize, AppError> {
+    let ctx = match credentials::contexts(options).into_iter().next() {
+        Some(ctx) => ctx,
+        None => return Err(AppError::usage("--cloudwatch-logs-group needs working credentials".to_string()))
+    };
+    let region = Region::from_str(&options.region).unwrap_or_default();
+    let client = CloudWatchLogsClient::new_with_client(ctx.client_for(region.name()), region.clone());
+    let stream = Utc::now().format("%Y%m%dT%H%M%SZ").to_string();
+    let failed = |why: String| AppError::aws(region.name(), format!("couldn't write to log group {}: {}", group, why));
+    let mut token = match client.create_log_stream(CreateLogStreamRequest { log_group_name: group.to_string(), log_stream_name: stream.clone() }).await {
+        Ok(_) => None,
+        Err(RusotoError::Service(CreateLogStreamError::ResourceAlreadyExists(_))) => sequence_token(&client, group, &stream).await.map_err(failed)?,
+        Err(why) => return Err(failed(why.to_string()))
+    };
+    let timestamp = Utc::now().timestamp_millis();
+    let (events, truncated) = events(records, timestamp);
+    if truncated > 0 {
+        warn!("{} records were too large for a log event and were truncated", truncated);
+    }
+    for batch in batches(events).into_iter() {
+        let mut attempt = 1;
+        loop {
+            let request = PutLogEventsRequest {
+                log_events: batch.clone(),
+                log_group_name: group.to_string(),
+                log_stream_name: stream.clone(),
+                sequence_token: token.clone()
+            };
+            match client.put_log_events(request).await {
+                Ok(accepted) => {
+                    token = accepted.next_sequence_token;
+                    break;
+                },
+                // Another writer moved the stream on; its error names the
+                // token to use next.
+                Err(RusotoError::Service(PutLogEventsError::InvalidSequenceToken(message))) if attempt < MAX_ATTEMPTS => {
+                    token = expected_token(&message);
+                },
+                Err(why) if is_throttled(&why) && attempt < MAX_ATTEMPTS => {
+                    warn!("PutLogEvents throttled, retrying");
+                    tokio::time::sleep(Duration::from_millis(BASE_DELAY_MS * 2u64.pow(attempt - 1))).await;
+                },
+                Err(why) => return Err(failed(why.to_string()))
+            }
+            attempt += 1;
+        }
+    }
+    Ok(records.len())
+}
+
+/// The sequence token of an existing stream, needed to append to it.
+async fn sequence_token(client: &CloudWatchLogsClient, group: &str, stream: &str) -> Result<Option<String>, String> {
+    let request = DescribeLogStreamsRequest {
+        log_group_name: group.to_string(),
+        log_stream_name_prefix: Some(stream.to_string()),
+        ..Default::default()
+    };
+    match client.describe_log_streams(request).await {
+        Ok(found) => Ok(found.log_streams.unwrap_or_default().into_iter()
+            .find(|s| s.log_stream_name.as_deref() == Some(stream))
+            .and_then(|s| s.upload_sequence_token)),
+        Err(why) => Err(why.to_string())
+    }
+}
+
+/// The token an InvalidSequenceToken message says to use next, which is
+/// `null` for a stream nothing has been written to yet.
+fn expected_token(message: &str) -> Option<String> {
+    match message.rsplit(' ').next() {
+        Some("null") | Some("") | None => None,
+        Some(token) => Some(token.to_string())
+    }
+}
+
+/// One event per record, and how many of them had to be truncated.
+fn events(records: &[Value], timestamp: i64) -> (Vec<InputLogEvent>, usize) {
+    let limit = MAX_EVENT_BYTES - EVENT_OVERHEAD;
+    let mut truncated = 0;
+    let events = records.iter()
+        .map(|record| {
+            let mut message = record.to_string();
+            if message.len() > limit {
+                let mut end = limit - TRUNCATED.len();
+                while !message.is_char_boundary(end) {
+                    end -= 1;
+                }
+                message = format!("{}{}", TRUNCATED, &message[..end]);
+                truncated += 1;
+            }
+            InputLogEvent { message: message, timestamp: timestamp }
+        })
+        .collect();
+    (events, truncated)
+}
+
+/// Splits events into PutLogEvents calls within the byte and count limits.
+fn batches(events: Vec<InputLogEvent>) -> Vec<Vec<InputLogEvent>> {
+    let mut batches = Vec::new();
+    let mut batch: Vec<InputLogEvent> = Vec::new();
+    let mut bytes = 0;
+    for event in events.into_iter() {
+        let size = event.message.len() + EVENT_OVERHEAD;
+        if !batch.is_empty() && (bytes + size > MAX_BATCH_BYTES || batch.len() == MAX_BATCH_EVENTS) {
+            batches.push(std::mem::take(&mut batch));
+            bytes = 0;
+        }
+        bytes += size;
+        batch.push(event);
+    }
+    if !batch.is_empty() {
+        batches.push(batch);
+    }
+    batches
+}
+
+fn is_throttled<E>(err: &RusotoError<E>) -> bool {
+    match err {
+        RusotoError::Unknown(res) => res.body_as_str().contains("ThrottlingException"),
+        _ => false
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn event(bytes: usize) -> InputLogEvent {
+        InputLogEvent { message: "x".repeat(bytes), timestamp: 0 }
+    }
+
+    fn sizes(batches: &[Vec<InputLogEvent>]) -> Vec<usize> {
+        batches.iter().map(Vec::len).collect()
+    }
+
+    #[test]
+    fn takes_the_expected_token_from_the_error() {
+        assert_eq!(expected_token("The given sequenceToken is invalid. The next expected sequenceToken is: 49590302375936548713098412"), Some("49590302375936548713098412".to_string()));
+        assert_eq!(expected_token("The given sequenceToken is invalid. The next expected sequenceToken is: null"), None);
+    }
+
+    #[test]
+    fn small_records_are_written_whole() {
+        let record = json!({ "instance_id": "i-1", "state": "running" });
+        let (events, truncated) = events(&[record.clone()], 1_700_000_000_000);
+        assert_eq!(truncated, 0);
+        assert_eq!(events[0].message, record.to_string());
+        assert_eq!(events[0].timestamp, 1_700_000_000_000);
+    }
+
+    #[test]
+    fn oversized_records_are_truncated_on_a_char_boundary() {
+        let limit = MAX_EVENT_BYTES - EVENT_OVERHEAD;
+        // The quote puts every two-byte char at an odd offset, so the cut
+        // point, which is even, has to step back a byte.
+        let record = json!("é".repeat(limit));
+        let (events, truncated) = events(&[record, json!("small")], 0);
+        assert_eq!(truncated, 1);
+        assert!(events[0].message.starts_with(TRUNCATED));
+        assert_eq!(events[0].message.len(), limit - 1);
+        assert_eq!(events[1].message, "\"small\"");
+    }
+
+    #[test]
+    fn batches_count_the_per_event_overhead() {
+        let exact = MAX_BATCH_BYTES / 4 - EVENT_OVERHEAD;
+        assert_eq!(sizes(&batches(vec![event(exact), event(exact), event(exact), event(exact)])), vec![4]);
+        let over = exact + 1;
+        assert_eq!(sizes(&batches(vec![event(over), event(over), event(over), event(over)])), vec![3, 1]);
+    }
+
+    #[test]
+    fn batches_hold_at_most_ten_thousand_events() {
+        let events: Vec<InputLogEvent> = (0..MAX_BATCH_EVENTS * 2 + 1).map(|_| event(10)).collect();
+        assert_eq!(sizes(&batches(events)), vec![MAX_BATCH_EVENTS, MAX_BATCH_EVENTS, 1]);
+    }
+}
@@ -0,0 +1,357 @@
+use crate::error::AppError;
+use crate::identity;
+use crate::options::Options;
+use crate::sso;
+use async_trait::async_trait;
+use chrono::Duration as ChronoDuration;
+use log::{info, warn};
+use rusoto_core::{Client, HttpClient, Region};
+use rusoto_credential::{AutoRefreshingProvider, AwsCredentials, CredentialsError, ProfileProvider, ProvideAwsCredentials};
+use rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient, WebIdentityProvider};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::io::{self, IsTerminal, Write};
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::{env, fs};
+
+/// The credentials a scan runs under and the labels stamped onto the
+/// records it produces. Every service client for the scan is built from
+/// `client`, so a context resolves its credentials once for all regions.
+#[derive(Clone)]
+pub struct CredentialContext {
+    /// Filled in from ListAccountAliases once the scan starts, when the
+    /// credentials are allowed to call it.
+    pub account_alias: Option<String>,
+    /// Filled in from GetCallerIdentity when the contexts are built.
+    pub account_id: Option<String>,
+    /// The account's name in AWS Organizations, for `--org` scans, or its
+    /// label in an `--accounts-file`.
+    pub account_name: Option<String>,
+    pub client: Client,
+    /// The credentials' partition, resolved along with `account_id`.
+    pub partition: Option<String>,
+    pub profile: Option<String>,
+    /// Clients for the regions `--region-profiles` maps to another profile.
+    pub region_clients: BTreeMap<String, Client>,
+    /// The regions an `--accounts-file` entry limits its account to.
+    pub regions: Option<Vec<String>>
+}
+
+impl CredentialContext {
+    /// The client for calls in `region`: its `--region-profiles` profile's
+    /// if it has one, otherwise the context's own.
+    pub fn client_for(&self, region: &str) -> Client {
+        self.region_clients.get(region).unwrap_or(&self.client).clone()
+    }
+
+    /// The standard rusoto credential chain, or a web identity when
+    /// `AWS_WEB_IDENTITY_TOKEN_FILE` and `AWS_ROLE_ARN` are set, as they are
+    /// for IAM Roles for Service Accounts on EKS. Rusoto's chain doesn't
+    /// look for a web identity itself, nor read an SSO default profile. The
+    /// token file is re-read whenever the credentials are refreshed, so a
+    /// rotated token is picked up.
+    pub fn default_chain() -> Result<CredentialContext, AppError> {
+        let client = match web_identity(env::var("AWS_WEB_IDENTITY_TOKEN_FILE").ok(), env::var("AWS_ROLE_ARN").ok())? {
+            Some((token_file, role_arn)) => {
+                info!("using web identity credentials for {} from {}", role_arn, token_file);
+                let provider = match AutoRefreshingProvider::new(WebIdentityProvider::from_k8s_env()) {
+                    Ok(p) => p,
+                    Err(why) => return Err(AppError::usage(format!("couldn't use the web identity token {}: {}", token_file, why)))
+                };
+                Client::new_with(provider, http_client()?)
+            },
+            None => {
+                let profile = env::var("AWS_PROFILE").unwrap_or_else(|_| "default".to_string());
+                if sso::provider(&profile)?.is_some() {
+                    return Ok(CredentialContext { profile: None, ..CredentialContext::profile(&profile)? })
+                }
+                info!("using the default credential chain");
+                Client::shared()
+            }
+        };
+        Ok(CredentialContext { account_alias: None, account_id: None, account_name: None, client: client, partition: None, profile: None, region_clients: BTreeMap::new(), regions: None })
+    }
+
+    /// A named profile from the shared credentials file, or an IAM Identity
+    /// Center profile from the config file, which rusoto can't read itself.
+    pub fn profile(name: &str) -> Result<CredentialContext, AppError> {
+        if let Some(sso) = sso::provider(name)? {
+            info!("using IAM Identity Center credentials from profile {}", name);
+            let provider = match AutoRefreshingProvider::new(sso) {
+                Ok(p) => p,
+                Err(why) => return Err(AppError::usage(format!("couldn't load profile {}: {}", name, why)))
+            };
+            return Ok(CredentialContext {
+                account_alias: None,
+                account_id: None,
+                account_name: None,
+                client: Client::new_with(provider, http_client()?),
+                partition: None,
+                profile: Some(name.to_string()),
+                region_clients: BTreeMap::new(),
+                regions: None
+            })
+        }
+        let mut provider = match ProfileProvider::new() {
+            Ok(p) => p,
+            Err(why) => return Err(AppError::usage(format!("couldn't load profile {}: {}", name, why)))
+        };
+        provider.set_profile(name);
+        info!("using credentials from profile {}", name);
+        Ok(CredentialContext {
+            account_alias: None,
+            account_id: None,
+            account_name: None,
+            client: Client::new_with(provider, http_client()?),
+            partition: None,
+            profile: Some(name.to_string()),
+            region_clients: BTreeMap::new(),
+            regions: None
+        })
+    }
+
+    /// Temporary credentials for `role_arn`, assumed with this context's
+    /// credentials. They are re-assumed shortly before they expire, so a
+    /// long scan never runs on a dead session, and shared by every region.
+    /// `hop` counts the roles already assumed to get here: each hop gets its
+    /// own session name, and only the first, made with the caller's own
+    /// credentials, uses `--mfa-serial`. A code can't be reused, so a run
+    /// that outlives an MFA session fails at the re-assume instead.
+    pub fn assume_role(&self, role_arn: &str, external_id: Option<String>, hop: usize, options: &Options) -> Result<CredentialContext, AppError> {
+        let sts = StsClient::new_with_client(self.client.clone(), Region::default());
+        let duration = options.session_duration.and_then(|d| ChronoDuration::from_std(d).ok());
+        let (session_name, mfa_serial) = match hop {
+            0 => (options.session_name.clone(), options.mfa_serial.clone()),
+            _ => (format!("{}-{}", options.session_name, hop + 1), None)
+        };
+        let mut provider = StsAssumeRoleSessionCredentialsProvider::new(sts, role_arn.to_string(), session_name, external_id, duration, None, mfa_serial.clone());
+        if let Some(serial) = &mfa_serial {
+            provider.set_mfa_code(mfa_code(serial, options)?);
+        }
+        let provider = LoggedSession { assumed: AtomicBool::new(false), hop: hop + 1, inner: provider, role_arn: role_arn.to_string() };
+        let provider = match AutoRefreshingProvider::new(provider) {
+            Ok(p) => p,
+            Err(why) => return Err(AppError::usage(format!("couldn't assume role {}: {}", role_arn, why)))
+        };
+        Ok(CredentialContext {
+            account_alias: None,
+            account_id: None,
+            account_name: None,
+            client: Client::new_with(provider, http_client()?),
+            partition: None,
+            profile: self.profile.clone(),
+            region_clients: BTreeMap::new(),
+            regions: None
+        })
+    }
+}
+
+/// Logs each AssumeRole made for a session, so the re-assumes that keep a
+/// long run going show up in the log. A failed re-assume only fails the
+/// requests still to be made with this context; what it already collected
+/// is kept.
+struct LoggedSession<P> {
+    assumed: AtomicBool,
+    hop: usize,
+    inner: P,
+    role_arn: String
+}
+
+#[async_trait]
+impl<P: ProvideAwsCredentials + Send + Sync> ProvideAwsCredentials for LoggedSession<P> {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        let refresh = self.assumed.swap(true, Ordering::SeqCst);
+        match self.inner.credentials().await {
+            Ok(credentials) => {
+                let action = match refresh {
+                    true => "refreshed",
+                    false => "assumed"
+                };
+                let until = credentials.expires_at().map_or("unknown".to_string(), |t| t.to_rfc3339());
+                info!("{} session for {} (hop {}), valid until {}", action, self.role_arn, self.hop, until);
+                Ok(credentials)
+            },
+            Err(why) => {
+                // Asking for more than the role's maximum is refused outright
+                // rather than capped.
+                match why.message.contains("MaxSessionDuration") {
+                    true => warn!("couldn't assume {} (hop {}): --session-duration is longer than the role allows", self.role_arn, self.hop),
+                    false => warn!("couldn't assume {} (hop {}): {}", self.role_arn, self.hop, why)
+                }
+                Err(CredentialsError::new(format!("assuming {} (hop {}) failed: {}", self.role_arn, self.hop, why.message)))
+            }
+        }
+    }
+}
+
+/// The contexts to scan, one per `--profiles` entry or just the default
+/// chain, each passed through `--role-chain` and switched to `--assume-role`
+/// when given. The chain is resolved once per context and its credentials
+/// shared by every region, except those `--region-profiles` sends to a
+/// profile's own credentials. Profiles that fail to load are reported and
+/// skipped so the others still run.
+pub fn contexts(options: &Options) -> Vec<CredentialContext> {
+    let profiles = match options.all_profiles {
+        true => all_profiles(),
+        false => options.profiles.clone()
+    };
+    let base = match profiles.is_empty() {
+        true => vec![CredentialContext::default_chain()],
+        false => profiles.iter().map(|p| CredentialContext::profile(p)).collect()
+    };
+    base.into_iter()
+        .map(|ctx| {
+            let mut ctx = ctx?;
+            for (hop, role) in options.role_chain.iter().enumerate() {
+                ctx = ctx.assume_role(&role.role_arn, role.external_id.clone(), hop, options)?;
+            }
+            match &options.assume_role {
+                Some(role) => ctx.assume_role(role, options.external_id.clone(), options.role_chain.len(), options),
+                None => Ok(ctx)
+            }
+        })
+        .map(|ctx| {
+            let mut ctx = ctx?;
+            for (region, profile) in options.region_profiles.iter() {
+                ctx.region_clients.insert(region.clone(), CredentialContext::profile(profile)?.client);
+            }
+            Ok(ctx)
+        })
+        .filter_map(|ctx| match ctx {
+            Ok(ctx) => Some(ctx),
+            Err(why) => {
+                crate::error::report(&why, options.error_format);
+                None
+            }
+        })
+        .collect()
+}
+
+/// Reads a `--region-profiles` file: a JSON object of region to the profile
+/// whose credentials are used there, e.g. `{"cn-north-1": "china"}`.
+pub fn load_region_profiles(path: &Path) -> Result<BTreeMap<String, String>, AppError> {
+    let text = match fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    let object = match serde_json::from_str(&text) {
+        Ok(Value::Object(o)) => o,
+        Ok(_) => return Err(AppError::usage(format!("{} is not an object of region to profile", path.display()))),
+        Err(why) => return Err(AppError::usage(format!("couldn't parse {}: {}", path.display(), why)))
+    };
+    object.into_iter()
+        .map(|(region, profile)| match (identity::is_region_name(&region), profile.as_str()) {
+            (true, Some(p)) => Ok((region, p.to_string())),
+            (false, _) => Err(AppError::usage(format!("{}: unknown region {}", path.display(), region))),
+            (_, None) => Err(AppError::usage(format!("{}: profile for {} is not a string", path.display(), region)))
+        })
+        .collect()
+}
+
+/// The token file and role of a web identity, when both are set. The token
+/// is read once up front so a missing or empty file stops the run with its
+/// path, rather than failing every region's calls later.
+fn web_identity(token_file: Option<String>, role_arn: Option<String>) -> Result<Option<(String, String)>, AppError> {
+    let (token_file, role_arn) = match (token_file, role_arn) {
+        (Some(t), Some(r)) if !t.is_empty() && !r.is_empty() => (t, r),
+        _ => return Ok(None)
+    };
+    match fs::read_to_string(&token_file) {
+        Ok(token) if !token.trim().is_empty() => Ok(Some((token_file, role_arn))),
+        Ok(_) => Err(AppError::usage(format!("the web identity token {} is empty", token_file))),
+        Err(why) => Err(AppError::usage(format!("couldn't read the web identity token {}: {}", token_file, why)))
+    }
+}
+
+/// `--mfa-token`, or a code read from the terminal when stdin is one.
+fn mfa_code(serial: &str, options: &Options) -> Result<String, AppError> {
+    if let Some(token) = &options.mfa_token {
+        return Ok(token.clone())
+    }
+    if !io::stdin().is_terminal() {
+        return Err(AppError::usage("--mfa-serial needs --mfa-token when stdin is not a terminal".to_string()))
+    }
+    eprint!("MFA code for {}: ", serial);
+    let _ = io::stderr().flush();
+    let mut code = String::new();
+    match io::stdin().read_line(&mut code) {
+        Ok(_) if !code.trim().is_empty() => Ok(code.trim().to_string()),
+        Ok(_) => Err(AppError::usage("no MFA code was entered".to_string())),
+        Err(why) => Err(AppError::io(format!("couldn't read the MFA code: {}", why)))
+    }
+}
+
+/// Every profile named in the shared config and credentials files, which
+/// use `[profile name]` and `[name]` section headers respectively.
+fn all_profiles() -> Vec<String> {
+    let home = env::var("HOME").unwrap_or_default();
+    let config = config_file();
+    let credentials = env::var("AWS_SHARED_CREDENTIALS_FILE").unwrap_or_else(|_| format!("{}/.aws/credentials", home));
+    let mut names = BTreeSet::new();
+    for path in [config, credentials].iter() {
+        let text = fs::read_to_string(path).unwrap_or_default();
+        for line in text.lines().map(str::trim) {
+            if let Some(section) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+                let name = section.trim().strip_prefix("profile ").unwrap_or(section).trim();
+                if !name.is_empty() && !name.starts_with("sso-session ") {
+                    names.insert(name.to_string());
+                }
+            }
+        }
+    }
+    names.into_iter().collect()
+}
+
+/// The shared config file, `AWS_CONFIG_FILE` or `~/.aws/config`.
+pub fn config_file() -> String {
+    let home = env::var("HOME").unwrap_or_default();
+    env::var("AWS_CONFIG_FILE").unwrap_or_else(|_| format!("{}/.aws/config", home))
+}
+
+fn http_client() -> Result<HttpClient, AppError> {
+    match HttpClient::new() {
+        Ok(c) => Ok(c),
+        Err(why) => Err(AppError::io(format!("couldn't create HTTP client: {}", why)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ROLE: &str = "arn:aws:iam::123456789012:role/scanner";
+
+    fn token_file(name: &str, contents: &str) -> String {
+        let path = env::temp_dir().join(format!("ec2-monitoring-{}-{}", std::process::id(), name));
+        fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    #[test]
+    fn web_identity_uses_a_readable_token_file() {
+        let path = token_file("token", "eyJhbGciOiJSUzI1NiJ9.fake.token\n");
+        assert_eq!(web_identity(Some(path.clone()), Some(ROLE.to_string())).unwrap(), Some((path.clone(), ROLE.to_string())));
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn web_identity_needs_both_variables() {
+        let path = token_file("unused", "token");
+        assert_eq!(web_identity(Some(path.clone()), None).unwrap(), None);
+        assert_eq!(web_identity(Some(path.clone()), Some(String::new())).unwrap(), None);
+        assert_eq!(web_identity(None, Some(ROLE.to_string())).unwrap(), None);
+        fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn web_identity_rejects_a_missing_or_empty_token_file() {
+        let missing = env::temp_dir().join(format!("ec2-monitoring-{}-missing", std::process::id()));
+        let err = web_identity(Some(missing.to_string_lossy().into_owned()), Some(ROLE.to_string())).unwrap_err();
+        assert!(err.message.starts_with("couldn't read the web identity token"));
+        let empty = token_file("empty", " \n");
+        let err = web_identity(Some(empty.clone()), Some(ROLE.to_string())).unwrap_err();
+        assert_eq!(err.message, format!("the web identity token {} is empty", empty));
+        fs::remove_file(&empty).unwrap();
+    }
+}
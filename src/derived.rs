@@ -0,0 +1,60 @@
+use crate::options::Options;
+use crate::Details;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+
+/// Synonyms folded together unless `--tag-synonyms` maps them differently.
+const DEFAULT_SYNONYMS: [(&str, &str); 5] = [
+    ("development", "dev"),
+    ("prd", "prod"),
+    ("production", "prod"),
+    ("staging", "stage"),
+    ("stg", "stage")
+];
+
+/// Fills in the optional derived fields requested on the command line.
+pub fn apply(options: &Options, details: &mut [Details]) {
+    for d in details.iter_mut() {
+        if options.billing_notes {
+            d.billing_note = billing_note(d.state.as_deref());
+        }
+        if options.nat_candidates {
+            d.is_nat_candidate = d.source_dest_check.map(|check| !check);
+        }
+        if options.normalize_environment {
+            d.environment_normalized = d.environment.as_deref().map(|e| normalize_tag_value(e, &options.tag_synonyms));
+        }
+    }
+}
+
+/// Stopped instances stop accruing compute charges but their EBS volumes are
+/// still billed, which is easy to miss when reading an inventory.
+fn billing_note(state: Option<&str>) -> Option<String> {
+    let note = match state? {
+        "running" => "compute and EBS storage billed while running",
+        "stopped" => "EBS storage billed while stopped",
+        "terminated" => "no longer billed",
+        _ => return None
+    };
+    Some(note.to_string())
+}
+
+/// `launch_time` as a timestamp, or `None` if it is absent or malformed.
+pub fn launched_at(details: &Details) -> Option<DateTime<Utc>> {
+    let launch_time = details.launch_time.as_deref()?;
+    DateTime::parse_from_rfc3339(launch_time).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// Trims and case-folds a tag value, then maps it through `synonyms` (keys
+/// lowercase) falling back to the built-in ones, so "Prod", " PROD" and
+/// "production" all become "prod".
+pub fn normalize_tag_value(value: &str, synonyms: &BTreeMap<String, String>) -> String {
+    let folded = value.trim().to_lowercase();
+    match synonyms.get(&folded) {
+        Some(canonical) => canonical.clone(),
+        None => match DEFAULT_SYNONYMS.iter().find(|(variant, _)| *variant == folded) {
+            Some((_, canonical)) => canonical.to_string(),
+            None => folded
+        }
+    }
+}
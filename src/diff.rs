@@ -0,0 +1,209 @@
+use crate::error::AppError;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+use std::path::Path;
+
+#[derive(Serialize, Default)]
+pub struct Diff {
+    pub added: Vec<Value>,
+    pub modified: Vec<Modified>,
+    pub removed: Vec<Value>
+}
+
+#[derive(Serialize)]
+pub struct Modified {
+    pub changes: Vec<Change>,
+    pub instance_id: String
+}
+
+#[derive(Serialize)]
+pub struct Change {
+    pub after: Value,
+    pub before: Value,
+    pub field: String
+}
+
+impl Diff {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty() && self.removed.is_empty() && self.modified.is_empty()
+    }
+
+    /// One row per added/removed instance or changed field, for the tabular
+    /// output formats.
+    pub fn rows(&self) -> Vec<Value> {
+        let mut rows = Vec::new();
+        for (change, records) in [("added", &self.added), ("removed", &self.removed)].iter() {
+            for r in records.iter() {
+                rows.push(row(change, r.get("instance_id").cloned().unwrap_or(Value::Null), None));
+            }
+        }
+        for m in self.modified.iter() {
+            for c in m.changes.iter() {
+                rows.push(row("modified", Value::String(m.instance_id.clone()), Some(c)));
+            }
+        }
+        rows
+    }
+}
+
+fn row(change: &str, instance_id: Value, field: Option<&Change>) -> Value {
+    let mut row = Map::new();
+    row.insert("change".to_string(), Value::String(change.to_string()));
+    row.insert("instance_id".to_string(), instance_id);
+    row.insert("field".to_string(), field.map_or(Value::Null, |c| Value::String(c.field.clone())));
+    row.insert("before".to_string(), field.map_or(Value::Null, |c| c.before.clone()));
+    row.insert("after".to_string(), field.map_or(Value::Null, |c| c.after.clone()));
+    Value::Object(row)
+}
+
+/// Reads a results file written by this tool, accepting both the bare array
+/// of instances and an object wrapping them under `instances`.
+pub fn load(path: &Path) -> Result<Vec<Value>, AppError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    match serde_json::from_str(&text) {
+        Ok(Value::Array(records)) => Ok(records),
+        Ok(Value::Object(mut wrapper)) => match wrapper.remove("instances") {
+            Some(Value::Array(records)) => Ok(records),
+            _ => Err(AppError::usage(format!("{} has no instances array", path.display())))
+        },
+        Ok(_) => Err(AppError::usage(format!("{} is not an instance results file", path.display()))),
+        Err(why) => Err(AppError::usage(format!("couldn't parse {}: {}", path.display(), why)))
+    }
+}
+
+/// Matches records by `instance_id`. Fields in `ignore` are left out of the
+/// comparison, so `--ignore-fields state` hides stop/start churn. Object
+/// fields such as `tags` are compared key by key as `tags.<Key>`.
+pub fn diff(old: &[Value], new: &[Value], ignore: &[String]) -> Diff {
+    let old = by_id(old);
+    let new = by_id(new);
+    let mut result = Diff::default();
+    for (id, record) in new.iter() {
+        match old.get(id) {
+            None => result.added.push((*record).clone()),
+            Some(before) => {
+                let changes = changes(before, record, ignore);
+                if !changes.is_empty() {
+                    result.modified.push(Modified { changes: changes, instance_id: id.clone() });
+                }
+            }
+        }
+    }
+    for (id, record) in old.iter() {
+        if !new.contains_key(id) {
+            result.removed.push((*record).clone());
+        }
+    }
+    result
+}
+
+fn by_id(records: &[Value]) -> BTreeMap<String, &Value> {
+    records.iter()
+        .filter_map(|r| Some((r.get("instance_id")?.as_str()?.to_string(), r)))
+        .collect()
+}
+
+fn changes(before: &Value, after: &Value, ignore: &[String]) -> Vec<Change> {
+    let before = flatten(before);
+    let after = flatten(after);
+    let mut fields: Vec<&String> = before.keys().chain(after.keys()).collect();
+    fields.sort();
+    fields.dedup();
+    fields.into_iter()
+        .filter(|f| !ignore.iter().any(|i| *i == **f || f.starts_with(&format!("{}.", i))))
+        .filter_map(|f| {
+            let b = before.get(f).cloned().unwrap_or(Value::Null);
+            let a = after.get(f).cloned().unwrap_or(Value::Null);
+            match a == b {
+                true => None,
+                false => Some(Change { after: a, before: b, field: f.clone() })
+            }
+        })
+        .collect()
+}
+
+fn flatten(record: &Value) -> BTreeMap<String, Value> {
+    let mut flat = BTreeMap::new();
+    if let Value::Object(fields) = record {
+        for (key, value) in fields.iter() {
+            match value {
+                Value::Object(inner) => {
+                    for (k, v) in inner.iter() {
+                        flat.insert(format!("{}.{}", key, k), v.clone());
+                    }
+                },
+                _ => {
+                    flat.insert(key.clone(), value.clone());
+                }
+            }
+        }
+    }
+    flat
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn results(name: &str, contents: &Value) -> Vec<Value> {
+        let path = std::env::temp_dir().join(format!("ec2-monitoring-{}-{}.json", std::process::id(), name));
+        std::fs::write(&path, contents.to_string()).unwrap();
+        let records = load(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        records
+    }
+
+    fn instance(id: &str, state: &str, instance_type: &str, env: &str) -> Value {
+        json!({ "instance_id": id, "instance_type": instance_type, "state": state, "tags": { "Env": env, "Team": "payments" } })
+    }
+
+    fn fields(modified: &Modified) -> Vec<&str> {
+        modified.changes.iter().map(|c| c.field.as_str()).collect()
+    }
+
+    #[test]
+    fn loads_a_bare_array_or_an_instances_wrapper() {
+        let records = vec![instance("i-1", "running", "t3.micro", "prod")];
+        assert_eq!(results("bare", &json!(records)), records);
+        assert_eq!(results("wrapped", &json!({ "generated_at": "2026-03-20T12:00:00Z", "instances": records })), records);
+    }
+
+    #[test]
+    fn finds_added_removed_and_modified_instances() {
+        let old = vec![instance("i-1", "running", "t3.micro", "prod"), instance("i-2", "running", "t3.micro", "prod")];
+        let new = vec![instance("i-1", "running", "t3.large", "prod"), instance("i-3", "running", "t3.micro", "prod")];
+        let diff = diff(&old, &new, &[]);
+        assert_eq!(diff.added, vec![new[1].clone()]);
+        assert_eq!(diff.removed, vec![old[1].clone()]);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].instance_id, "i-1");
+        let change = &diff.modified[0].changes[0];
+        assert_eq!((change.field.as_str(), &change.before, &change.after), ("instance_type", &json!("t3.micro"), &json!("t3.large")));
+        assert_eq!(diff.rows().len(), 3);
+    }
+
+    #[test]
+    fn compares_tags_key_by_key() {
+        let old = vec![instance("i-1", "running", "t3.micro", "staging")];
+        let new = vec![instance("i-1", "running", "t3.micro", "prod")];
+        let diff = diff(&old, &new, &[]);
+        assert_eq!(fields(&diff.modified[0]), vec!["tags.Env"]);
+        assert_eq!(diff.modified[0].changes[0].before, json!("staging"));
+    }
+
+    #[test]
+    fn ignored_fields_hide_only_their_own_changes() {
+        let old = vec![instance("i-1", "running", "t3.micro", "prod"), instance("i-2", "running", "t3.micro", "prod")];
+        let new = vec![instance("i-1", "stopped", "t3.micro", "prod"), instance("i-2", "stopped", "m5.large", "prod")];
+        let ignore = vec!["state".to_string()];
+        let diff = diff(&old, &new, &ignore);
+        assert_eq!(diff.modified.len(), 1);
+        assert_eq!(diff.modified[0].instance_id, "i-2");
+        assert_eq!(fields(&diff.modified[0]), vec!["instance_type"]);
+    }
+}
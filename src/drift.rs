@@ -0,0 +1,49 @@
+use crate::error::AppError;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeSet;
+use std::path::Path;
+
+/// How the scanned instances differ from an `--expect` list.
+#[derive(Serialize)]
+pub struct Drift {
+    /// Listed but not found by the scan.
+    pub missing: Vec<String>,
+    /// Found by the scan but not listed.
+    pub unexpected: Vec<String>
+}
+
+impl Drift {
+    pub fn is_empty(&self) -> bool {
+        self.missing.is_empty() && self.unexpected.is_empty()
+    }
+}
+
+/// Reads an `--expect` file: one instance id per line, with blank lines and
+/// `#` comments ignored.
+pub fn load(path: &Path) -> Result<BTreeSet<String>, AppError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    let mut expected = BTreeSet::new();
+    for (n, line) in text.lines().enumerate() {
+        let id = line.split('#').next().unwrap_or_default().trim().to_lowercase();
+        if id.is_empty() {
+            continue;
+        }
+        match id.strip_prefix("i-") {
+            Some(hex) if !hex.is_empty() && hex.chars().all(|c: char| c.is_ascii_hexdigit()) => expected.insert(id),
+            _ => return Err(AppError::usage(format!("{}:{}: invalid instance id '{}'", path.display(), n + 1, id)))
+        };
+    }
+    Ok(expected)
+}
+
+pub fn compare(expected: &BTreeSet<String>, details: &[Details]) -> Drift {
+    let found: BTreeSet<&str> = details.iter().filter_map(|d| d.instance_id.as_deref()).collect();
+    Drift {
+        missing: expected.iter().filter(|id| !found.contains(id.as_str())).cloned().collect(),
+        unexpected: found.iter().filter(|id| !expected.contains(**id)).map(|id| id.to_string()).collect()
+    }
+}
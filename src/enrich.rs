@@ -0,0 +1,20 @@
+use crate::credentials::CredentialContext;
+use crate::options::{Options, Report};
+use crate::security_groups;
+use crate::Details;
+use rusoto_core::Region;
+
+/// Enrichments that need further AWS calls, run on each page of instances
+/// as it is collected while the region's credentials are at hand.
+#[allow(unused_variables)]
+pub async fn page(ctx: &CredentialContext, region: &Region, options: &Options, details: &mut [Details]) {
+    if options.report == Some(Report::PublicExposure) {
+        security_groups::fill_exposure(ctx, region, options, details).await;
+    }
+    #[cfg(feature = "cloudwatch")]
+    {
+        if options.with_cpu {
+            crate::cloudwatch::fill_cpu(ctx, region, options, details).await;
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use serde::Serialize;
+use std::fmt;
+
+/// A failure reported to the user before exiting, or for a single region
+/// when the rest of the scan can carry on.
+#[derive(Serialize, Debug, Clone)]
+pub struct AppError {
+    pub kind: ErrorKind,
+    pub message: String,
+    pub region: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub request_id: Option<String>
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorKind {
+    Aws,
+    Io,
+    Usage
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ErrorFormat {
+    Json,
+    Text
+}
+
+impl AppError {
+    pub fn new(kind: ErrorKind, message: String) -> AppError {
+        AppError { kind: kind, message: message, region: None, request_id: None }
+    }
+
+    pub fn usage(message: String) -> AppError {
+        AppError::new(ErrorKind::Usage, message)
+    }
+
+    pub fn io(message: String) -> AppError {
+        AppError::new(ErrorKind::Io, message)
+    }
+
+    pub fn aws(region: &str, message: String) -> AppError {
+        AppError { kind: ErrorKind::Aws, message: message, region: Some(region.to_string()), request_id: None }
+    }
+
+    pub fn with_request_id(mut self, request_id: Option<String>) -> AppError {
+        self.request_id = request_id;
+        self
+    }
+}
+
+impl fmt::Display for AppError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if let Some(region) = &self.region {
+            write!(f, " (region {})", region)?;
+        }
+        if let Some(request_id) = &self.request_id {
+            write!(f, " (request id {})", request_id)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for AppError {}
+
+/// Writes the error to stderr, as a single-line JSON object when
+/// `--error-format json` was given.
+pub fn report(err: &AppError, format: ErrorFormat) {
+    match format {
+        ErrorFormat::Json => eprintln!("{}", serde_json::to_string(err).unwrap_or_default()),
+        ErrorFormat::Text => eprintln!("error: {}", err)
+    }
+}
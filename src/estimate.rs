@@ -0,0 +1,64 @@
+use crate::credentials::CredentialContext;
+use crate::error::AppError;
+use crate::options::Options;
+use crate::{aws_error, identity, output, retry};
+use rusoto_ec2::{DescribeInstancesRequest, Ec2, Ec2Client};
+use serde::Serialize;
+
+/// The most instances one DescribeInstances call returns, so a region that
+/// fits in one sample page is counted exactly.
+pub const SAMPLE_SIZE: i64 = 1000;
+
+/// `--estimate-calls` for one region of one account.
+#[derive(Debug, Clone, Serialize)]
+pub struct RegionEstimate {
+    pub account_id: Option<String>,
+    /// DescribeInstances calls a scan would make at the scan page size.
+    pub calls: usize,
+    /// False when the region holds more than the sample, so `instances`
+    /// and `calls` are lower bounds.
+    pub exact: bool,
+    pub instances: usize,
+    pub region: String
+}
+
+/// Reads one page of up to `SAMPLE_SIZE` instances with the scan's filters
+/// and works out how many pages of `page_size` the scan would read.
+pub async fn sample(ctx: &CredentialContext, region: &str, request: DescribeInstancesRequest, page_size: i64) -> Result<RegionEstimate, AppError> {
+    let client = Ec2Client::new_with_client(ctx.client_for(region), identity::ec2_region(region));
+    let result = retry::with_retry(region, request, |req| {
+        let c = client.clone();
+        async move { c.describe_instances(req).await }
+    }).await;
+    let page = match result {
+        Ok(page) => page,
+        Err(why) => {
+            let failure = AppError::aws(region, format!("failed to sample instances: {}", aws_error::describe(&why)));
+            return Err(failure.with_request_id(aws_error::request_id(&why)))
+        }
+    };
+    let instances: usize = page.reservations.iter().flatten()
+        .map(|r| r.instances.as_ref().map_or(0, |i| i.len()))
+        .sum();
+    let page_size = page_size.max(1) as usize;
+    Ok(RegionEstimate {
+        account_id: ctx.account_id.clone(),
+        // An empty region still costs the one call that finds it empty.
+        calls: ((instances + page_size - 1) / page_size).max(1),
+        exact: page.next_token.is_none(),
+        instances: instances,
+        region: region.to_string()
+    })
+}
+
+/// Prints the per-region estimates followed by the total.
+pub fn report(options: &Options, estimates: &[RegionEstimate]) {
+    let rows = serde_json::to_value(estimates).unwrap_or_default();
+    println!("{}", output::render(options.format, &rows));
+    let calls: usize = estimates.iter().map(|e| e.calls).sum();
+    let bounded = estimates.iter().filter(|e| !e.exact).count();
+    match bounded {
+        0 => println!("estimated DescribeInstances calls: {} across {} regions", calls, estimates.len()),
+        n => println!("estimated DescribeInstances calls: at least {} across {} regions ({} regions hold more than the {} instance sample)", calls, estimates.len(), n, SAMPLE_SIZE)
+    }
+}
@@ -0,0 +1,108 @@
+use crate::derived::launched_at;
+use crate::Details;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+
+/// Fields computed at evaluation time rather than stored on `Details`,
+/// usable in `--query` and `--sort-by` alongside the output fields.
+pub const DERIVED: [&str; 1] = ["age_days"];
+
+/// What `--redact` puts in place of a value.
+const REDACTED: &str = "[redacted]";
+
+/// Every selectable output field, in output order. Derived from how
+/// `Details` serializes, so new fields become selectable automatically.
+pub fn names() -> Vec<String> {
+    match serde_json::to_value(Details::default()) {
+        Ok(Value::Object(fields)) => fields.keys().cloned().collect(),
+        _ => Vec::new()
+    }
+}
+
+/// Whether `name` can be looked up on a record: an output field, a derived
+/// field or `tags.<Key>`.
+pub fn is_known(name: &str) -> bool {
+    name.starts_with("tags.") || DERIVED.contains(&name) || names().iter().any(|n| n == name)
+}
+
+/// The instance as a JSON object with the derived fields added. `now` is
+/// the run timestamp that `age_days` is measured from.
+pub fn record(details: &Details, now: DateTime<Utc>) -> Map<String, Value> {
+    let mut record = match serde_json::to_value(details) {
+        Ok(Value::Object(fields)) => fields,
+        _ => Map::new()
+    };
+    let age_days = launched_at(details).map(|t| now.signed_duration_since(t).num_days());
+    record.insert("age_days".to_string(), age_days.map_or(Value::Null, Value::from));
+    record
+}
+
+/// Resolves a field name, or `tags.<Key>` for a single tag, to its value.
+pub fn lookup(record: &Map<String, Value>, path: &str) -> Value {
+    let mut parts = path.splitn(2, '.');
+    let field = record.get(parts.next().unwrap_or_default());
+    match (field, parts.next()) {
+        (Some(value), None) => value.clone(),
+        (Some(Value::Object(inner)), Some(key)) => inner.get(key).cloned().unwrap_or(Value::Null),
+        _ => Value::Null
+    }
+}
+
+/// Serializes the instances, keeping only `fields` in the given order when
+/// `--fields` was used. Records stay objects so JSON output is unchanged in
+/// shape.
+pub fn project(fields: Option<&[String]>, details: &[Details]) -> Vec<Value> {
+    details.iter()
+        .map(|d| {
+            let record = serde_json::to_value(d).unwrap_or_default();
+            match (fields, record) {
+                (Some(fields), Value::Object(mut all)) => {
+                    let mut selected = Map::new();
+                    for f in fields.iter() {
+                        selected.insert(f.clone(), all.remove(f).unwrap_or(Value::Null));
+                    }
+                    Value::Object(selected)
+                },
+                (_, record) => record
+            }
+        })
+        .collect()
+}
+
+/// `--omit-null`: drops the fields that are null from each record, rather
+/// than writing every absent optional field out as `null`.
+pub fn omit_null(records: &mut [Value]) {
+    for record in records.iter_mut() {
+        if let Value::Object(record) = record {
+            record.retain(|_, value| !value.is_null());
+        }
+    }
+}
+
+/// `--redact`: replaces the named fields, or single tags as `tags.<Key>`,
+/// with a placeholder in projected records, or drops them with `omit`.
+/// Null fields stay null, so redacting doesn't invent values.
+pub fn redact(records: &mut [Value], names: &[String], omit: bool) {
+    for record in records.iter_mut() {
+        let record = match record {
+            Value::Object(record) => record,
+            _ => continue
+        };
+        for name in names.iter() {
+            let mut parts = name.splitn(2, '.');
+            let field = parts.next().unwrap_or_default();
+            let (container, key) = match parts.next() {
+                None => (&mut *record, field),
+                Some(key) => match record.get_mut(field) {
+                    Some(Value::Object(inner)) => (inner, key),
+                    _ => continue
+                }
+            };
+            match (omit, container.get_mut(key)) {
+                (true, _) => { container.remove(key); },
+                (false, Some(value)) if !value.is_null() => *value = Value::String(REDACTED.to_string()),
+                _ => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use crate::derived::launched_at;
+use crate::options::{Options, TagFilter};
+use crate::query;
+use crate::Details;
+use std::collections::BTreeMap;
+
+pub struct FilterOutcome {
+    pub kept: Vec<Details>,
+    /// Instances dropped by an age filter because their launch time was
+    /// missing or unparseable, reported so they don't silently disappear.
+    pub undated: usize
+}
+
+/// Client-side filters applied to the collected instances before any output
+/// is written. Every filter must pass for an instance to be kept.
+pub fn apply(options: &Options, details: Vec<Details>) -> FilterOutcome {
+    let mut undated = 0;
+    let kept = details.into_iter()
+        .filter(|d| state_matches(d, &options.states))
+        .filter(|d| type_matches(d, &options.types))
+        .filter(|d| name_matches(options, d))
+        .filter(|d| options.query.as_ref().map_or(true, |q| query::matches(q, d, options.started)))
+        .filter(|d| tags_match(options, &d.tags))
+        .filter(|d| match age_matches(options, d) {
+            Some(keep) => keep,
+            None => {
+                undated += 1;
+                false
+            }
+        })
+        .collect();
+    FilterOutcome { kept: kept, undated: undated }
+}
+
+/// `None` when an age filter is active but the launch time can't be read.
+fn age_matches(options: &Options, details: &Details) -> Option<bool> {
+    if options.older_than.is_none() && options.newer_than.is_none() {
+        return Some(true);
+    }
+    let age = options.started.signed_duration_since(launched_at(details)?).to_std().unwrap_or_default();
+    Some(options.older_than.map_or(true, |o| age > o) && options.newer_than.map_or(true, |n| age < n))
+}
+
+/// `--state` is also sent as a server-side filter; checking it again here
+/// keeps the result right for data that didn't come straight from the API.
+fn state_matches(details: &Details, states: &[String]) -> bool {
+    if states.is_empty() {
+        return true;
+    }
+    match &details.state {
+        Some(state) => states.contains(state),
+        None => false
+    }
+}
+
+/// `--type` entries are exact types or globs such as `m4.*`.
+fn type_matches(details: &Details, types: &[String]) -> bool {
+    if types.is_empty() {
+        return true;
+    }
+    match &details.instance_type {
+        Some(t) => types.iter().any(|p| glob_match(p, t)),
+        None => false
+    }
+}
+
+/// An instance without a Name tag never matches `--name-regex`, so it is
+/// kept when the match is inverted.
+fn name_matches(options: &Options, details: &Details) -> bool {
+    match &options.name_regex {
+        Some(re) => details.name.as_deref().map_or(false, |n| re.is_match(n)) != options.name_regex_invert,
+        None => true
+    }
+}
+
+/// `--tag` and `--tag-not` against any resource's tags, so reports over
+/// volumes, addresses and the like honour the same flags as instances.
+pub fn tags_match(options: &Options, tags: &BTreeMap<String, String>) -> bool {
+    options.tags.iter().all(|f| tag_matches(tags, f)) && !options.tags_not.iter().any(|f| tag_matches(tags, f))
+}
+
+/// Values are compared case-insensitively; any one of the filter's values
+/// matching is enough. Keys are matched exactly.
+fn tag_matches(tags: &BTreeMap<String, String>, filter: &TagFilter) -> bool {
+    match tags.get(&filter.key) {
+        Some(value) => filter.values.iter().any(|v| v.eq_ignore_ascii_case(value)),
+        None => false
+    }
+}
+
+/// Shell-style matching where `*` matches any run of characters and `?`
+/// matches exactly one.
+pub fn glob_match(pattern: &str, text: &str) -> bool {
+    let p: Vec<char> = pattern.chars().collect();
+    let t: Vec<char> = text.chars().collect();
+    let (mut pi, mut ti) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+    while ti < t.len() {
+        if pi < p.len() && (p[pi] == '?' || p[pi] == t[ti]) {
+            pi += 1;
+            ti += 1;
+        }
+        else if pi < p.len() && p[pi] == '*' {
+            backtrack = Some((pi, ti));
+            pi += 1;
+        }
+        else if let Some((star, matched)) = backtrack {
+            pi = star + 1;
+            ti = matched + 1;
+            backtrack = Some((star, matched + 1));
+        }
+        else {
+            return false;
+        }
+    }
+    p[pi..].iter().all(|c| *c == '*')
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn star_matches_a_whole_family() {
+        assert!(glob_match("m4.*", "m4.large"));
+        assert!(glob_match("m4.*", "m4."));
+        assert!(glob_match("*.micro", "t3a.micro"));
+    }
+
+    #[test]
+    fn question_mark_matches_one_character() {
+        assert!(glob_match("t?.micro", "t3.micro"));
+        assert!(!glob_match("t?.micro", "t3a.micro"));
+    }
+
+    #[test]
+    fn exact_types_match_only_themselves() {
+        assert!(glob_match("t2.micro", "t2.micro"));
+        assert!(!glob_match("t2.micro", "t2.micro2"));
+        assert!(!glob_match("t2.micro", "t2.small"));
+    }
+
+    #[test]
+    fn a_family_glob_does_not_match_a_sibling_family() {
+        assert!(!glob_match("t3.*", "t3a.micro"));
+        assert!(!glob_match("m4.*", "m5.large"));
+    }
+}
@@ -0,0 +1,63 @@
+use crate::Details;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// A level of `--group-by` nesting, outermost first.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum GroupKey {
+    Account,
+    Region
+}
+
+impl GroupKey {
+    /// The output field the level groups on, for `summarize`.
+    pub fn field(&self) -> &'static str {
+        match self {
+            GroupKey::Account => "account_id",
+            GroupKey::Region => "region"
+        }
+    }
+
+    fn value(&self, details: &Details) -> String {
+        match self {
+            GroupKey::Account => details.account_id.clone()
+                .or_else(|| details.account_alias.clone())
+                .unwrap_or_else(|| "unknown".to_string()),
+            GroupKey::Region => details.region.clone()
+        }
+    }
+}
+
+/// Nests the projected `records` (one per entry of `details`, in the same
+/// order) as objects keyed by each level in turn, e.g. account, then
+/// region, then the array of instances.
+pub fn nest(keys: &[GroupKey], details: &[Details], records: Vec<Value>) -> Value {
+    let items: Vec<(&Details, Value)> = details.iter().zip(records.into_iter()).collect();
+    nest_items(keys, items)
+}
+
+fn nest_items(keys: &[GroupKey], items: Vec<(&Details, Value)>) -> Value {
+    let (key, rest) = match keys.split_first() {
+        Some(split) => split,
+        None => return Value::Array(items.into_iter().map(|(_, record)| record).collect())
+    };
+    let mut groups: BTreeMap<String, Vec<(&Details, Value)>> = BTreeMap::new();
+    for (d, record) in items.into_iter() {
+        groups.entry(key.value(d)).or_insert_with(Vec::new).push((d, record));
+    }
+    let mut nested = Map::new();
+    for (name, members) in groups.into_iter() {
+        nested.insert(name, nest_items(rest, members));
+    }
+    Value::Object(nested)
+}
+
+/// `--by` with the `--group-by` fields in front, so a summary is broken
+/// down the same way as the grouped instance list.
+pub fn summary_keys(keys: &[GroupKey], by: &[String]) -> Vec<String> {
+    keys.iter()
+        .map(|k| k.field().to_string())
+        .filter(|f| !by.contains(f))
+        .chain(by.iter().cloned())
+        .collect()
+}
@@ -0,0 +1,206 @@
+use crate::error::AppError;
+use crate::options::Options;
+use crate::Details;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use std::path::{Path, PathBuf};
+
+/// Snapshot names are `instances-<timestamp>.json`; nothing else in the
+/// history directory is ever pruned.
+const PREFIX: &str = "instances-";
+const SUFFIX: &str = ".json";
+/// To the millisecond, so runs in the same second don't overwrite each
+/// other. Older snapshots named to the second still parse.
+const TIMESTAMP: &str = "%Y%m%dT%H%M%S%.3fZ";
+const PARSED: &str = "%Y%m%dT%H%M%S%.fZ";
+
+/// Writes this run's instances into `--history-dir` as a timestamped
+/// snapshot, then prunes snapshots beyond `--keep` and `--keep-for`.
+pub fn save(options: &Options, details: &[Details]) -> Result<(), AppError> {
+    let dir = match &options.history_dir {
+        Some(dir) => dir,
+        None => return Ok(())
+    };
+    if let Err(why) = std::fs::create_dir_all(dir) {
+        return Err(AppError::io(format!("couldn't create {}: {}", dir.display(), why)))
+    }
+    let now = Utc::now();
+    let path = dir.join(format!("{}{}{}", PREFIX, now.format(TIMESTAMP), SUFFIX));
+    let text = serde_json::to_string_pretty(details).unwrap_or_default();
+    if let Err(why) = std::fs::write(&path, text) {
+        return Err(AppError::io(format!("couldn't write {}: {}", path.display(), why)))
+    }
+    prune(options, dir, now)
+}
+
+/// Resolves `previous` and `latest` in `diff` arguments to the second
+/// newest and newest snapshots in `--history-dir`; other paths are
+/// returned as they are.
+pub fn resolve(options: &Options, path: &Path) -> Result<PathBuf, AppError> {
+    let back = match path.to_str() {
+        Some("latest") => 0,
+        Some("previous") => 1,
+        _ => return Ok(path.to_path_buf())
+    };
+    let dir = match &options.history_dir {
+        Some(dir) => dir,
+        None => return Ok(path.to_path_buf())
+    };
+    match snapshots(dir)?.into_iter().nth(back) {
+        Some((_, path)) => Ok(path),
+        None => Err(AppError::usage(format!("{} has no {} snapshot", dir.display(), path.display())))
+    }
+}
+
+/// Keeps the newest `--keep` snapshots and those younger than
+/// `--keep-for`; with both, a snapshot has to pass both to be kept.
+fn prune(options: &Options, dir: &Path, now: DateTime<Utc>) -> Result<(), AppError> {
+    if options.keep.is_none() && options.keep_for.is_none() {
+        return Ok(())
+    }
+    for (index, (taken, path)) in snapshots(dir)?.into_iter().enumerate() {
+        let over_count = options.keep.map_or(false, |keep| index >= keep);
+        let too_old = options.keep_for.map_or(false, |keep_for| {
+            now.signed_duration_since(taken).to_std().map_or(false, |age| age > keep_for)
+        });
+        if !over_count && !too_old {
+            continue;
+        }
+        if options.prune_dry_run {
+            println!("would remove {}", path.display());
+            continue;
+        }
+        match std::fs::remove_file(&path) {
+            Ok(_) => println!("removed {}", path.display()),
+            Err(why) => return Err(AppError::io(format!("couldn't remove {}: {}", path.display(), why)))
+        }
+    }
+    Ok(())
+}
+
+/// Snapshots in `dir`, newest first, dated by their names rather than
+/// file times so copies and restores keep their place.
+fn snapshots(dir: &Path) -> Result<Vec<(DateTime<Utc>, PathBuf)>, AppError> {
+    let entries = match std::fs::read_dir(dir) {
+        Ok(e) => e,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", dir.display(), why)))
+    };
+    let mut found: Vec<(DateTime<Utc>, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| entry.file_type().map_or(false, |t| t.is_file()))
+        .filter_map(|entry| Some((taken(entry.file_name().to_str()?)?, entry.path())))
+        .collect();
+    found.sort_by(|a, b| b.0.cmp(&a.0));
+    Ok(found)
+}
+
+fn taken(name: &str) -> Option<DateTime<Utc>> {
+    let stamp = name.strip_prefix(PREFIX)?.strip_suffix(SUFFIX)?;
+    NaiveDateTime::parse_from_str(stamp, PARSED).ok().map(|t| DateTime::from_utc(t, Utc))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use chrono::{Duration, TimeZone};
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2026, 3, 20).and_hms(12, 0, 0)
+    }
+
+    fn name(age: Duration) -> String {
+        format!("{}{}{}", PREFIX, (now() - age).format(TIMESTAMP), SUFFIX)
+    }
+
+    /// A fresh history directory holding snapshots 1 hour, 2 days, 5 days
+    /// and 10 days old (the last named to the second), plus two files that
+    /// aren't snapshots.
+    fn history(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("ec2-monitoring-{}-history-{}", std::process::id(), test));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        let names = vec![name(Duration::hours(1)), name(Duration::days(2)), name(Duration::days(5)), "instances-20260310T120000Z.json".to_string(), "notes.txt".to_string(), "instances-baseline.json".to_string()];
+        for n in names.iter() {
+            std::fs::write(dir.join(n), "[]").unwrap();
+        }
+        dir
+    }
+
+    fn options(dir: &Path, flags: &[&str]) -> Options {
+        let mut args: Vec<String> = vec!["list_servers".to_string(), "eu-west-1".to_string(), "--history-dir".to_string(), dir.to_string_lossy().into_owned()];
+        args.extend(flags.iter().map(|f| f.to_string()));
+        options::parse_args(&args).unwrap()
+    }
+
+    /// Prunes `dir` with `flags` and returns what's left, removing `dir`.
+    fn pruned(test: &str, flags: &[&str]) -> Vec<String> {
+        let dir = history(test);
+        prune(&options(&dir, flags), &dir, now()).unwrap();
+        let mut left: Vec<String> = std::fs::read_dir(&dir).unwrap().map(|e| e.unwrap().file_name().to_string_lossy().into_owned()).collect();
+        left.sort();
+        std::fs::remove_dir_all(&dir).unwrap();
+        left
+    }
+
+    fn others() -> Vec<String> {
+        vec!["instances-baseline.json".to_string(), "notes.txt".to_string()]
+    }
+
+    fn kept(ages: &[Duration]) -> Vec<String> {
+        let mut names: Vec<String> = ages.iter().map(|a| name(*a)).chain(others()).collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn keep_leaves_the_newest_snapshots() {
+        assert_eq!(pruned("keep", &["--keep", "3"]), kept(&[Duration::hours(1), Duration::days(2), Duration::days(5)]));
+    }
+
+    #[test]
+    fn keep_for_leaves_recent_snapshots() {
+        assert_eq!(pruned("keep-for", &["--keep-for", "72h"]), kept(&[Duration::hours(1), Duration::days(2)]));
+    }
+
+    #[test]
+    fn keep_and_keep_for_both_have_to_pass() {
+        assert_eq!(pruned("both", &["--keep", "1", "--keep-for", "72h"]), kept(&[Duration::hours(1)]));
+    }
+
+    #[test]
+    fn a_dry_run_removes_nothing() {
+        let mut everything = kept(&[Duration::hours(1), Duration::days(2), Duration::days(5)]);
+        everything.push("instances-20260310T120000Z.json".to_string());
+        everything.sort();
+        assert_eq!(pruned("dry-run", &["--keep", "1", "--prune-dry-run"]), everything);
+    }
+
+    #[test]
+    fn resolves_latest_and_previous() {
+        let dir = history("resolve");
+        let options = options(&dir, &[]);
+        assert_eq!(resolve(&options, Path::new("latest")).unwrap(), dir.join(name(Duration::hours(1))));
+        assert_eq!(resolve(&options, Path::new("previous")).unwrap(), dir.join(name(Duration::days(2))));
+        assert_eq!(resolve(&options, Path::new("old.json")).unwrap(), PathBuf::from("old.json"));
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn previous_needs_two_snapshots() {
+        let dir = std::env::temp_dir().join(format!("ec2-monitoring-{}-history-single", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::write(dir.join(name(Duration::hours(1))), "[]").unwrap();
+        let err = resolve(&options(&dir, &[]), Path::new("previous")).unwrap_err();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(err.message, format!("{} has no previous snapshot", dir.display()));
+    }
+
+    #[test]
+    fn snapshots_in_the_same_second_get_their_own_names() {
+        let first = name(Duration::zero());
+        let second = name(Duration::milliseconds(-250));
+        assert_ne!(first, second);
+        assert_eq!(taken(&second), Some(now() + Duration::milliseconds(250)));
+        assert_eq!(taken("instances-20260310T120000Z.json"), Some(now() - Duration::days(10)));
+    }
+}
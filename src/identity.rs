@@ -0,0 +1,126 @@
+use crate::credentials::CredentialContext;
+use log::{debug, warn};
+use rusoto_core::Region;
+use rusoto_iam::{Iam, IamClient, ListAccountAliasesRequest};
+use rusoto_sts::{GetCallerIdentityRequest, GetCallerIdentityResponse, Sts, StsClient};
+use std::str::FromStr;
+
+/// One STS and IAM endpoint per partition: commercial, China and GovCloud.
+const PARTITION_PROBES: [Region; 3] = [Region::UsEast1, Region::CnNorth1, Region::UsGovWest1];
+
+/// The partition a region belongs to, matching the second field of an ARN.
+pub fn region_partition(region: &str) -> &'static str {
+    if region.starts_with("cn-") {
+        "aws-cn"
+    }
+    else if region.starts_with("us-gov-") {
+        "aws-us-gov"
+    }
+    else {
+        "aws"
+    }
+}
+
+/// The region for an EC2 client. Region names newer than this build of
+/// rusoto get a custom region pointed at the standard EC2 endpoint, so
+/// the scan keeps up with new regions rather than failing on them.
+pub fn ec2_region(name: &str) -> Region {
+    match Region::from_str(name) {
+        Ok(region) => region,
+        Err(_) => {
+            let domain = match region_partition(name) {
+                "aws-cn" => "amazonaws.com.cn",
+                _ => "amazonaws.com"
+            };
+            let endpoint = format!("https://ec2.{}.{}", name, domain);
+            warn!("region {} is unknown to this build, using {}", name, endpoint);
+            Region::Custom { name: name.to_string(), endpoint: endpoint }
+        }
+    }
+}
+
+/// A region rusoto knows, or a name shaped like one (`xx-word-N`, e.g.
+/// `us-gov-west-1`) that `ec2_region` will build an endpoint for.
+pub fn is_region_name(name: &str) -> bool {
+    if Region::from_str(name).is_ok() {
+        return true;
+    }
+    let parts: Vec<&str> = name.split('-').collect();
+    parts.len() >= 3
+        && parts[0].len() == 2
+        && parts[..parts.len() - 1].iter().all(|p| !p.is_empty() && p.chars().all(|c: char| c.is_ascii_lowercase()))
+        && parts[parts.len() - 1].chars().all(|c: char| c.is_ascii_digit())
+        && !parts[parts.len() - 1].is_empty()
+}
+
+/// Who the credentials act as. Credentials are only valid against STS in
+/// their own partition, so each partition's endpoint is tried in turn until
+/// one accepts them.
+pub async fn caller_identity(ctx: &CredentialContext) -> Option<GetCallerIdentityResponse> {
+    for region in PARTITION_PROBES.iter() {
+        let client = StsClient::new_with_client(ctx.client.clone(), region.clone());
+        match client.get_caller_identity(GetCallerIdentityRequest {}).await {
+            Ok(identity) => return Some(identity),
+            Err(why) => debug!("GetCallerIdentity via {} failed: {}", region.name(), why)
+        }
+    }
+    None
+}
+
+/// Fills in the context's account id and partition from GetCallerIdentity,
+/// once, when the contexts are built. False when no partition's STS accepts
+/// the credentials.
+pub async fn identify(ctx: &mut CredentialContext) -> bool {
+    match caller_identity(ctx).await {
+        Some(identity) => {
+            ctx.account_id = identity.account.or(ctx.account_id.take());
+            ctx.partition = identity.arn.as_deref().and_then(arn_partition);
+            true
+        },
+        None => false
+    }
+}
+
+/// The partition of an ARN, its second field.
+pub fn arn_partition(arn: &str) -> Option<String> {
+    arn.split(':').nth(1).filter(|p| !p.is_empty()).map(|p| p.to_string())
+}
+
+/// The account's IAM alias, if it has one. Plenty of roles can't call
+/// ListAccountAliases, so a refusal just leaves the alias out.
+pub async fn account_alias(ctx: &CredentialContext) -> Option<String> {
+    for region in PARTITION_PROBES.iter() {
+        let client = IamClient::new_with_client(ctx.client.clone(), region.clone());
+        match client.list_account_aliases(ListAccountAliasesRequest::default()).await {
+            Ok(result) => return result.account_aliases.into_iter().next(),
+            Err(why) => debug!("couldn't list account aliases via {}: {}", region.name(), why)
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn future_regions_get_an_endpoint() {
+        assert!(is_region_name("xx-future-9"));
+        assert_eq!(ec2_region("xx-future-9"), Region::Custom { name: "xx-future-9".to_string(), endpoint: "https://ec2.xx-future-9.amazonaws.com".to_string() });
+        assert_eq!(ec2_region("eu-west-1"), Region::EuWest1);
+    }
+
+    #[test]
+    fn partitions_come_from_the_arn() {
+        assert_eq!(arn_partition("arn:aws-cn:sts::123456789012:assumed-role/Audit/run").as_deref(), Some("aws-cn"));
+        assert_eq!(arn_partition("arn:aws:iam::123456789012:user/ops").as_deref(), Some("aws"));
+        assert_eq!(arn_partition("not-an-arn"), None);
+    }
+
+    #[test]
+    fn malformed_region_names_are_rejected() {
+        for bad in ["xx-future", "x-future-9", "xx-Future-9", "xx-future-", "xx--9"].iter() {
+            assert!(!is_region_name(bad), "{}", bad);
+        }
+    }
+}
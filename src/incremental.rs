@@ -0,0 +1,243 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError};
+use crate::options::Options;
+use crate::scan::ScanReport;
+use crate::{aws_error, enrich, identity, retry, state_store, Details};
+use chrono::{DateTime, Utc};
+use futures::{pin_mut, StreamExt};
+use log::{debug, info};
+use rusoto_ec2::{DescribeInstanceStatusRequest, DescribeInstancesRequest, Ec2, Ec2Client};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// The most statuses one DescribeInstanceStatus call returns.
+const STATUS_PAGE_SIZE: i64 = 1000;
+
+/// What `--incremental` remembers of an instance between cycles.
+#[derive(Clone, Serialize, Deserialize)]
+struct Entry {
+    account_id: Option<String>,
+    /// `None` for instances the scan's server-side filters leave out, so
+    /// they aren't fetched again until their state changes.
+    details: Option<Details>,
+    region: String,
+    /// The version the entry was fetched at; a different state in a later
+    /// status check means it is stale.
+    state: Option<String>
+}
+
+/// The inventory `--incremental` keeps between `--watch` or `--schedule`
+/// cycles, keyed by instance id.
+#[derive(Default)]
+pub struct Inventory {
+    entries: BTreeMap<String, Entry>,
+    /// What the inventory is saved under, set by `restore`.
+    key: Option<String>,
+    last_full: Option<Instant>,
+    /// The regions the last full scan covered, checked in later cycles.
+    regions: BTreeSet<String>
+}
+
+/// An `Inventory` as saved between runs. The last full scan is kept as a
+/// time of day, since an `Instant` means nothing to another process.
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    entries: BTreeMap<String, Entry>,
+    last_full: Option<DateTime<Utc>>,
+    regions: BTreeSet<String>
+}
+
+impl Inventory {
+    /// The inventory saved under `key` by an earlier run, so a restarted
+    /// `--watch` or `--schedule` carries on from it instead of beginning
+    /// with a full scan. The next full scan is still due when it would
+    /// have been. Starts empty without `--incremental` or when nothing
+    /// usable was saved.
+    pub fn restore(options: &Options, key: &str) -> Inventory {
+        let mut inventory = Inventory { key: Some(key.to_string()), ..Inventory::default() };
+        if !options.incremental {
+            return inventory
+        }
+        let saved: Saved = match state_store::load_inventory(options, key) {
+            Ok(Some(saved)) => saved,
+            Ok(None) => return inventory,
+            Err(why) => {
+                error::report(&why, options.error_format);
+                return inventory
+            }
+        };
+        let age = saved.last_full.and_then(|at| Utc::now().signed_duration_since(at).to_std().ok());
+        inventory.last_full = age.and_then(|age| Instant::now().checked_sub(age));
+        if inventory.last_full.is_some() {
+            inventory.entries = saved.entries;
+            inventory.regions = saved.regions;
+            info!("restored {} instances in {} regions from the last run", inventory.entries.len(), inventory.regions.len());
+        }
+        inventory
+    }
+
+    fn save(&self, options: &Options) {
+        let key = match &self.key {
+            Some(key) => key,
+            None => return
+        };
+        let saved = Saved {
+            entries: self.entries.clone(),
+            last_full: self.last_full.and_then(|last| chrono::Duration::from_std(last.elapsed()).ok()).map(|age| Utc::now() - age),
+            regions: self.regions.clone()
+        };
+        if let Err(why) = state_store::save_inventory(options, key, &saved) {
+            error::report(&why, options.error_format);
+        }
+    }
+}
+
+/// One `--incremental` collection. A full scan runs first and then every
+/// `--full-refresh-every`. In between, each region is checked with
+/// DescribeInstanceStatus, which lists every instance id and state in a
+/// few cheap calls. Only instances that are new or whose state changed are
+/// described again; gone ones are dropped and the rest kept as they were.
+/// Changes that don't touch the state, such as new tags, wait for the next
+/// full scan. A region whose check fails keeps its instances from before.
+pub async fn collect(options: &Options, contexts: &[CredentialContext], inventory: &mut Inventory) -> ScanReport {
+    let due = inventory.last_full.map_or(true, |last| last.elapsed() >= options.full_refresh_every);
+    if due {
+        let scan = crate::scan_all(options, contexts, Arc::new(Mutex::new(ScanReport::default()))).await;
+        inventory.entries = scan.instances.iter()
+            .filter_map(|d| Some((d.instance_id.clone()?, Entry {
+                account_id: d.account_id.clone(),
+                details: Some(d.clone()),
+                region: d.region.clone(),
+                state: d.state.clone()
+            })))
+            .collect();
+        inventory.regions = scan.per_region_counts.keys().cloned().collect();
+        inventory.last_full = Some(Instant::now());
+        info!("full scan: {} instances in {} regions", inventory.entries.len(), inventory.regions.len());
+        inventory.save(options);
+        return scan;
+    }
+    let mut report = ScanReport::default();
+    for ctx in contexts.iter() {
+        let regions = inventory.regions.iter()
+            .filter(|r| ctx.regions.as_ref().map_or(true, |own| own.contains(r)))
+            .cloned()
+            .collect::<Vec<String>>();
+        for region in regions.into_iter() {
+            report.per_region_counts.entry(region.clone()).or_insert(0);
+            if let Err(why) = update_region(ctx, &region, options, inventory).await {
+                error::report(&why, options.error_format);
+                report.errors.push(why);
+            }
+        }
+    }
+    for entry in inventory.entries.values() {
+        if let Some(d) = &entry.details {
+            report.add_page(&entry.region, vec![d.clone()]);
+        }
+    }
+    inventory.save(options);
+    report.finished(options.started)
+}
+
+async fn update_region(ctx: &CredentialContext, region: &str, options: &Options, inventory: &mut Inventory) -> Result<(), AppError> {
+    let mut current = statuses(ctx, region).await?;
+    if let Some(ids) = &options.instance_ids {
+        current.retain(|id, _| ids.contains(id));
+    }
+    let mine = |e: &Entry| e.region == region && e.account_id == ctx.account_id;
+    inventory.entries.retain(|id, e| !mine(e) || current.contains_key(id));
+    let wanted: BTreeSet<String> = current.iter()
+        .filter(|(id, state)| inventory.entries.get(*id).map_or(true, |e| e.state != **state))
+        .map(|(id, _)| id.clone())
+        .collect();
+    if wanted.is_empty() {
+        return Ok(())
+    }
+    let found = refresh(ctx, region, options, &crate::get_instance_request(None, options), &wanted).await?;
+    debug!("re-read {} of {} instances in {}", found.len(), current.len(), region);
+    for id in wanted.into_iter() {
+        let details = found.iter().find(|d| d.instance_id.as_ref() == Some(&id)).cloned();
+        let entry = Entry {
+            account_id: ctx.account_id.clone(),
+            region: region.to_string(),
+            state: details.as_ref().and_then(|d| d.state.clone()).or_else(|| current.get(&id).cloned().flatten()),
+            details: details
+        };
+        inventory.entries.insert(id, entry);
+    }
+    Ok(())
+}
+
+/// Every instance id in the region with its state. Unlike the default,
+/// stopped instances are included.
+async fn statuses(ctx: &CredentialContext, region: &str) -> Result<BTreeMap<String, Option<String>>, AppError> {
+    let client = Ec2Client::new_with_client(ctx.client_for(region), identity::ec2_region(region));
+    let mut request = DescribeInstanceStatusRequest {
+        include_all_instances: Some(true),
+        max_results: Some(STATUS_PAGE_SIZE),
+        ..Default::default()
+    };
+    let mut states = BTreeMap::new();
+    loop {
+        let result = retry::with_retry(region, request.clone(), |req| {
+            let c = client.clone();
+            async move { c.describe_instance_status(req).await }
+        }).await;
+        let page = match result {
+            Ok(page) => page,
+            Err(why) => {
+                let failure = AppError::aws(region, format!("failed to check instance states: {}", aws_error::describe(&why)));
+                return Err(failure.with_request_id(aws_error::request_id(&why)))
+            }
+        };
+        for status in page.instance_statuses.unwrap_or_default().into_iter() {
+            if let Some(id) = status.instance_id {
+                states.insert(id, status.instance_state.and_then(|s| s.name));
+            }
+        }
+        match page.next_token {
+            Some(token) => request.next_token = Some(token),
+            None => return Ok(states)
+        }
+    }
+}
+
+/// Describes just `ids`, with the filters of `template`. Instances EC2 no
+/// longer knows, or that the filters leave out, are missing from the result.
+pub async fn refresh(ctx: &CredentialContext, region: &str, options: &Options, template: &DescribeInstancesRequest, ids: &BTreeSet<String>) -> Result<Vec<Details>, AppError> {
+    let ec2_region = identity::ec2_region(region);
+    let client = Ec2Client::new_with_client(ctx.client_for(region), ec2_region.clone());
+    let mut wanted: Vec<String> = ids.iter().cloned().collect();
+    'query: loop {
+        let mut found = Vec::new();
+        let request = DescribeInstancesRequest { instance_ids: Some(wanted.clone()), ..template.clone() };
+        let pages = crate::describe_instances(region.to_string(), client.clone(), request);
+        pin_mut!(pages);
+        while let Some(page) = pages.next().await {
+            match page {
+                Ok(details) => found.extend(details.unwrap_or_default()),
+                // Terminated instances age out of EC2; ask again without them.
+                Err(why) if aws_error::error_code(&why).as_deref() == Some("InvalidInstanceID.NotFound") => {
+                    let missing = aws_error::instance_ids(&aws_error::describe(&why));
+                    wanted.retain(|id| !missing.contains(id));
+                    match wanted.is_empty() || missing.is_empty() {
+                        true => return Ok(Vec::new()),
+                        false => continue 'query
+                    }
+                },
+                Err(why) => {
+                    let failure = AppError::aws(region, format!("failed to describe instances: {}", aws_error::describe(&why)));
+                    return Err(failure.with_request_id(aws_error::request_id(&why)))
+                }
+            }
+        }
+        let mut stamped: Vec<Details> = found.into_iter()
+            .map(|d| Details { account_alias: ctx.account_alias.clone(), account_id: ctx.account_id.clone(), account_name: ctx.account_name.clone(), profile: ctx.profile.clone(), ..d })
+            .collect();
+        enrich::page(ctx, &ec2_region, options, &mut stamped).await;
+        return Ok(stamped)
+    }
+}
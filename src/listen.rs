@@ -0,0 +1,227 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError};
+use crate::options::Options;
+use crate::scan::ScanReport;
+use crate::{incremental, Details};
+use log::{debug, info, warn};
+use rusoto_core::Region;
+use rusoto_ec2::DescribeInstancesRequest;
+use rusoto_sqs::{DeleteMessageRequest, Message, ReceiveMessageRequest, Sqs, SqsClient};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use tokio::time::Instant;
+
+/// The longest long poll SQS allows.
+const WAIT_SECS: i64 = 20;
+const MAX_MESSAGES: i64 = 10;
+
+const STATE_CHANGE: &str = "EC2 Instance State-change Notification";
+
+/// An instance EC2 says changed state.
+struct StateChange {
+    account: Option<String>,
+    instance_id: String,
+    region: String
+}
+
+/// `listen`: keeps the inventory current from EC2 state-change events that
+/// EventBridge delivers to `--queue-url`, directly or through SNS. Each
+/// event re-describes just its instance, and the results are rewritten.
+/// A full scan every `--refresh-interval` catches anything the events
+/// missed. A message is deleted only once its instance has been refreshed
+/// and the results written; others are left for SQS to redeliver. Messages
+/// that aren't EC2 state-change events are deleted straight away, while
+/// events from an account no context covers are left on the queue, where
+/// its redrive policy can move them to a dead-letter queue.
+pub async fn run(options: &Options, contexts: &[CredentialContext]) -> Result<i32, AppError> {
+    let queue_url = match &options.queue_url {
+        Some(url) => url.clone(),
+        None => return Err(AppError::usage("listen requires --queue-url".to_string()))
+    };
+    let base = match contexts.first() {
+        Some(ctx) => ctx,
+        None => return Err(AppError::usage("listen needs working credentials".to_string()))
+    };
+    let queue_region = queue_region(&queue_url);
+    let sqs = SqsClient::new_with_client(base.client_for(queue_region.name()), queue_region);
+    let mut inventory: BTreeMap<String, Details> = BTreeMap::new();
+    sweep(options, contexts, &mut inventory).await;
+    write(options, &inventory).await?;
+    let mut next_sweep = Instant::now() + options.refresh_interval;
+    loop {
+        let request = ReceiveMessageRequest {
+            max_number_of_messages: Some(MAX_MESSAGES),
+            queue_url: queue_url.clone(),
+            wait_time_seconds: Some(WAIT_SECS),
+            ..Default::default()
+        };
+        tokio::select! {
+            _ = tokio::time::sleep_until(next_sweep) => {
+                sweep(options, contexts, &mut inventory).await;
+                if let Err(why) = write(options, &inventory).await {
+                    error::report(&why, options.error_format);
+                }
+                next_sweep = Instant::now() + options.refresh_interval;
+            },
+            received = sqs.receive_message(request) => match received {
+                Ok(received) => {
+                    let messages = received.messages.unwrap_or_default();
+                    if !messages.is_empty() {
+                        handle(options, contexts, &sqs, &queue_url, &mut inventory, messages).await;
+                    }
+                },
+                Err(why) => {
+                    warn!("couldn't receive from {}: {}", queue_url, why);
+                    tokio::time::sleep(std::time::Duration::from_secs(WAIT_SECS as u64)).await;
+                }
+            },
+            _ = tokio::signal::ctrl_c() => return Ok(0)
+        }
+    }
+}
+
+async fn handle(options: &Options, contexts: &[CredentialContext], sqs: &SqsClient, queue_url: &str, inventory: &mut BTreeMap<String, Details>, messages: Vec<Message>) {
+    let mut groups: BTreeMap<(Option<String>, String), Vec<(String, Option<String>)>> = BTreeMap::new();
+    for message in messages.into_iter() {
+        match message.body.as_deref().and_then(parse_event) {
+            Some(change) => groups.entry((change.account, change.region)).or_insert_with(Vec::new).push((change.instance_id, message.receipt_handle)),
+            None => {
+                warn!("deleting message {} which isn't an EC2 state-change event", message.message_id.unwrap_or_default());
+                delete(sqs, queue_url, message.receipt_handle).await;
+            }
+        }
+    }
+    let mut done: Vec<Option<String>> = Vec::new();
+    for ((account, region), changes) in groups.into_iter() {
+        let found = match &account {
+            Some(_) => contexts.iter().find(|c| c.account_id == account),
+            None => contexts.first()
+        };
+        let ctx = match found {
+            Some(ctx) => ctx,
+            None => {
+                warn!("leaving {} events from account {} on the queue, no credentials cover that account", changes.len(), account.unwrap_or_default());
+                continue
+            }
+        };
+        let ids: BTreeSet<String> = changes.iter().map(|(id, _)| id.clone()).collect();
+        match incremental::refresh(ctx, &region, options, &DescribeInstancesRequest::default(), &ids).await {
+            Ok(found) => {
+                for id in ids.iter().filter(|id| !found.iter().any(|d| d.instance_id.as_ref() == Some(*id))) {
+                    inventory.remove(id);
+                }
+                for d in found.into_iter() {
+                    if let Some(id) = d.instance_id.clone() {
+                        inventory.insert(id, d);
+                    }
+                }
+                debug!("refreshed {} instances in {} from events", ids.len(), region);
+                done.extend(changes.into_iter().map(|(_, receipt)| receipt));
+            },
+            Err(why) => error::report(&why, options.error_format)
+        }
+    }
+    if done.is_empty() {
+        return;
+    }
+    if let Err(why) = write(options, inventory).await {
+        return error::report(&why, options.error_format);
+    }
+    for receipt in done.into_iter() {
+        delete(sqs, queue_url, receipt).await;
+    }
+}
+
+async fn delete(sqs: &SqsClient, queue_url: &str, receipt: Option<String>) {
+    let receipt_handle = match receipt {
+        Some(r) => r,
+        None => return
+    };
+    let request = DeleteMessageRequest { queue_url: queue_url.to_string(), receipt_handle: receipt_handle };
+    if let Err(why) = sqs.delete_message(request).await {
+        warn!("couldn't delete a message from {}: {}", queue_url, why);
+    }
+}
+
+/// Rescans everything. Instances in regions that failed this time are kept
+/// from before rather than dropped.
+async fn sweep(options: &Options, contexts: &[CredentialContext], inventory: &mut BTreeMap<String, Details>) {
+    let scan = crate::scan_all(options, contexts, Arc::new(Mutex::new(ScanReport::default()))).await;
+    let failed: BTreeSet<String> = scan.errors.iter().filter_map(|e| e.region.clone()).collect();
+    inventory.retain(|_, d| failed.contains(&d.region));
+    for d in scan.instances.into_iter() {
+        if let Some(id) = d.instance_id.clone() {
+            inventory.insert(id, d);
+        }
+    }
+    info!("full sweep: {} instances, {} regions failed", inventory.len(), failed.len());
+}
+
+async fn write(options: &Options, inventory: &BTreeMap<String, Details>) -> Result<i32, AppError> {
+    let scan = ScanReport { instances: inventory.values().cloned().collect(), ..Default::default() };
+    crate::finish(options, scan.finished(options.started)).await
+}
+
+/// The event in a raw EventBridge message, or in the `Message` of an SNS
+/// notification wrapping one.
+fn parse_event(body: &str) -> Option<StateChange> {
+    let mut event: Value = serde_json::from_str(body).ok()?;
+    if event.get("Type").and_then(Value::as_str) == Some("Notification") {
+        event = serde_json::from_str(event.get("Message")?.as_str()?).ok()?;
+    }
+    if event.get("detail-type").and_then(Value::as_str) != Some(STATE_CHANGE) {
+        return None;
+    }
+    Some(StateChange {
+        account: event.get("account").and_then(Value::as_str).map(|a| a.to_string()),
+        instance_id: event.get("detail")?.get("instance-id")?.as_str()?.to_string(),
+        region: event.get("region")?.as_str()?.to_string()
+    })
+}
+
+/// `https://sqs.<region>.amazonaws.com/<account>/<name>`.
+fn queue_region(queue_url: &str) -> Region {
+    let host = queue_url.split("://").nth(1).and_then(|rest| rest.split('/').next()).unwrap_or_default();
+    host.split('.').nth(1).and_then(|r| Region::from_str(r).ok()).unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const EVENT: &str = r#"{"version":"0","id":"7bf73129-1428-4cd3-a780-95db273d1602","detail-type":"EC2 Instance State-change Notification","source":"aws.ec2","account":"123456789012","time":"2026-03-20T12:00:00Z","region":"eu-west-1","resources":["arn:aws:ec2:eu-west-1:123456789012:instance/i-0abc"],"detail":{"instance-id":"i-0abc","state":"stopped"}}"#;
+
+    #[test]
+    fn parses_a_raw_eventbridge_event() {
+        let change = parse_event(EVENT).unwrap();
+        assert_eq!(change.account.as_deref(), Some("123456789012"));
+        assert_eq!(change.instance_id, "i-0abc");
+        assert_eq!(change.region, "eu-west-1");
+    }
+
+    #[test]
+    fn parses_an_event_wrapped_in_an_sns_notification() {
+        let body = serde_json::json!({ "Type": "Notification", "MessageId": "1", "TopicArn": "arn:aws:sns:eu-west-1:123456789012:ec2-events", "Message": EVENT }).to_string();
+        let change = parse_event(&body).unwrap();
+        assert_eq!(change.instance_id, "i-0abc");
+        assert_eq!(change.region, "eu-west-1");
+    }
+
+    #[test]
+    fn ignores_other_events() {
+        let other = EVENT.replace("EC2 Instance State-change Notification", "EC2 Spot Instance Interruption Warning");
+        assert!(parse_event(&other).is_none());
+        assert!(parse_event("not json").is_none());
+        let wrapped = serde_json::json!({ "Type": "Notification", "Message": other }).to_string();
+        assert!(parse_event(&wrapped).is_none());
+    }
+
+    #[test]
+    fn takes_the_region_from_the_queue_url() {
+        assert_eq!(queue_region("https://sqs.eu-west-1.amazonaws.com/123456789012/ec2-events"), Region::EuWest1);
+        assert_eq!(queue_region("https://sqs.cn-north-1.amazonaws.com.cn/123456789012/ec2-events"), Region::CnNorth1);
+        assert_eq!(queue_region("not a url"), Region::default());
+    }
+}
@@ -1,18 +1,102 @@
 extern crate tokio;
 
-use futures::{stream, Stream, StreamExt};
-use rusoto_core::{Region, RusotoError};
-use rusoto_ec2::{Ec2, Ec2Client, DescribeInstancesError, DescribeInstancesRequest, DescribeInstancesResult, Instance, Reservation, Tag};
-use serde::Serialize;
-use std::path::Path;
+mod accounts;
+mod assertions;
+mod aws_error;
+mod changes;
+#[cfg(feature = "cloudwatch")]
+mod cloudwatch;
+#[cfg(feature = "cloudwatch-logs")]
+mod cloudwatch_logs;
+mod credentials;
+mod derived;
+mod diff;
+mod drift;
+mod enrich;
+mod error;
+mod estimate;
+mod fields;
+mod filters;
+mod grouping;
+mod history;
+mod identity;
+mod incremental;
+#[cfg(feature = "sqs")]
+mod listen;
+mod options;
+#[cfg(feature = "organizations")]
+mod organizations;
+mod orphans;
+mod output;
+mod pricing;
+mod prometheus;
+mod query;
+mod readonly;
+mod reports;
+mod reserved;
+mod retry;
+mod scan;
+mod schedule;
+mod security_groups;
+mod serve;
+mod sort;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod sso;
+mod state_store;
+mod summarize;
+mod systemd;
+mod type_specs;
+#[cfg(feature = "webhook")]
+mod webhook;
+
+use credentials::CredentialContext;
+use error::AppError;
+use futures::{pin_mut, stream, Stream, StreamExt};
+use log::debug;
+use options::{Command, Options, Report};
+use rusoto_core::RusotoError;
+use rusoto_ec2::{
+    Ec2, Ec2Client, DescribeInstancesError, DescribeInstancesRequest, DescribeInstancesResult, ElasticGpuAssociation,
+    ElasticInferenceAcceleratorAssociation, Filter, Instance, Reservation, Tag
+};
+use scan::ScanReport;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
 use std::result::Result;
-use std::str::FromStr;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use std::vec::Vec;
 use tokio::fs::File;
 use tokio::io::AsyncWriteExt;
+use tokio::sync::Notify;
+use tokio::time::timeout;
 
 type DetailResult = Result<Option<Vec<Details>>, RusotoError<DescribeInstancesError>>;
 
+/// The scan so far, shared so that a scan cut short by the deadline can
+/// still write out whatever it had gathered.
+type Collected = Arc<Mutex<ScanReport>>;
+
+/// Exit code used when a `--report` mode finds problems, e.g. instances
+/// missing required tags.
+const EXIT_FINDINGS: i32 = 2;
+
+/// Version of the `--envelope` layout, bumped when fields move or change
+/// meaning.
+const SCHEMA_VERSION: u32 = 1;
+
+/// Exit code for `--exit-state` when the instance isn't running; one that
+/// wasn't found at all exits with `EXIT_STATE_UNKNOWN`.
+const EXIT_NOT_RUNNING: i32 = 1;
+const EXIT_STATE_UNKNOWN: i32 = 3;
+
+/// Exit code used when `--deadline-secs` expires before the scan completes.
+const EXIT_DEADLINE: i32 = 124;
+
 fn region_list<'a>() -> Vec<&'a str> {
      [
         "ap-east-1",
@@ -42,82 +126,888 @@ fn region_list<'a>() -> Vec<&'a str> {
 }
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
+async fn main() {
+    // This crate's own progress lines, such as which credentials are in
+    // use, show by default; dependencies only warn. RUST_LOG overrides both.
+    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("warn,list_servers=info")).init();
     let args: Vec<String> = std::env::args().collect();
-    if args.len() == 1 {
-        panic!("no arguments were provided\nPlease provide a valid region or 'all' to get an output from every available region")
+    match try_main(&args).await {
+        Ok(0) => {},
+        Ok(code) => std::process::exit(code),
+        Err(why) => {
+            error::report(&why, options::error_format(&args));
+            std::process::exit(1);
+        }
+    }
+}
+
+async fn try_main(args: &[String]) -> Result<i32, AppError> {
+    let options = options::parse_args(args)?;
+    if options.command == Command::Diff {
+        return run_diff(&options)
+    }
+    // --sqlite, which history requires, is rejected without the feature.
+    #[cfg(feature = "sqlite")]
+    {
+        if options.command == Command::History {
+            return run_history(&options)
+        }
     }
-    let region = &*args[1];
+    let region = &*options.region;
     let regions = region_list();
-    if !regions.contains(&region) && region != "all" {
-        panic!("The supplied region does not match any of the the available options: {},\nall", regions.join(",\n"))
+    // Regions outside the list, such as GovCloud or ones newer than this
+    // build, are accepted when they look like a region name.
+    if !regions.contains(&region) && region != "all" && !identity::is_region_name(region) {
+        return Err(AppError::usage(format!("The supplied region does not match any of the the available options: {},\nall", regions.join(",\n"))))
+    }
+    // Built once, so credentials, MFA codes and assumed roles are set up a
+    // single time however many cycles `--watch`, `--schedule`, serve or
+    // listen go on to run.
+    let contexts = scan_contexts(&options).await;
+    if options.assert_readonly {
+        if contexts.is_empty() {
+            return Err(AppError::usage("--assert-readonly found no credentials to check".to_string()))
+        }
+        for ctx in contexts.iter() {
+            readonly::verify(ctx).await?;
+        }
+        println!("--assert-readonly: {} credential contexts can't change instances", contexts.len());
+    }
+    if options.command == Command::Search {
+        return run_search(&options, &contexts).await
+    }
+    if options.command == Command::Serve {
+        return serve::run(&options, &contexts).await
+    }
+    // --queue-url, which listen requires, is rejected without the feature.
+    #[cfg(feature = "sqs")]
+    {
+        if options.command == Command::Listen {
+            return listen::run(&options, &contexts).await
+        }
+    }
+    if !options.schedules.is_empty() {
+        return schedule::run(&options, &contexts).await
+    }
+    if let Some(interval) = options.watch {
+        return run_watch(&options, &contexts, interval).await
+    }
+    let collected: Collected = Arc::new(Mutex::new(ScanReport::default()));
+    match options.deadline {
+        Some(deadline) => {
+            match timeout(deadline, run(&options, &contexts, collected.clone())).await {
+                Ok(result) => result,
+                Err(_) => {
+                    let partial = collected.lock().unwrap().clone().finished(options.started);
+                    eprintln!("deadline of {}s reached, writing {} partial results", deadline.as_secs(), partial.instances.len());
+                    finish(&options, partial).await?;
+                    std::process::exit(EXIT_DEADLINE);
+                }
+            }
+        },
+        None => run(&options, &contexts, collected).await
     }
-    run(&region).await;
-    Ok(())
 }
 
-async fn run<'a>(region: &'a str) {
-    let path = Path::new("instance_results.json");
-    let display = path.display();
-    let mut file = match File::create(&path).await {
-        Err(why) => panic!("couldn't create {}: {}", display, why),
-        Ok(file) => file,
+async fn run(options: &Options, contexts: &[CredentialContext], collected: Collected) -> Result<i32, AppError> {
+    let scan = scan_all(options, contexts, collected).await;
+    finish(options, scan).await
+}
+
+/// `--watch`: scans and writes the results every `interval`, printing what
+/// changed since the previous cycle. A failed cycle is reported and the
+/// next one still runs. Ctrl-C stops the loop once the cycle in progress
+/// has been written.
+async fn run_watch(options: &Options, contexts: &[CredentialContext], interval: Duration) -> Result<i32, AppError> {
+    let interrupted = Arc::new(Notify::new());
+    let notify = interrupted.clone();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("interrupted, stopping after the current cycle");
+            systemd::stopping();
+            notify.notify_one();
+        }
+    });
+    let mut previous: Option<changes::Snapshot> = None;
+    let mut inventory = incremental::Inventory::restore(options, "watch");
+    loop {
+        watch_cycle(options, contexts, &mut previous, &mut inventory).await;
+        tokio::select! {
+            _ = systemd::idle(interval) => {},
+            _ = interrupted.notified() => return Ok(0)
+        }
+    }
+}
+
+/// One `--watch` or `--schedule` collection: scans, writes the results and
+/// reports what changed since `previous`, which it then replaces. Regions
+/// that failed keep their instances from `previous` in the comparison. With
+/// `--incremental` the scan updates `inventory` rather than starting over.
+async fn watch_cycle(options: &Options, contexts: &[CredentialContext], previous: &mut Option<changes::Snapshot>, inventory: &mut incremental::Inventory) {
+    let scan = match options.incremental {
+        true => incremental::collect(options, contexts, inventory).await,
+        false => scan_all(options, contexts, Arc::new(Mutex::new(ScanReport::default()))).await
+    };
+    let failed: BTreeSet<String> = scan.errors.iter().filter_map(|e| e.region.clone()).collect();
+    let mut current = changes::snapshot(&scan.instances);
+    if let Some(previous) = previous.as_ref() {
+        changes::carry_forward(previous, &mut current, &failed);
+    }
+    let summary = format!("{} instances, {} regions failed at {}", scan.instances.len(), scan.errors.len(), chrono::Utc::now().format("%H:%M:%SZ"));
+    match finish(options, scan).await {
+        Ok(_) => systemd::collected(&summary, true),
+        Err(why) => {
+            error::report(&why, options.error_format);
+            systemd::collected(&summary, false)
+        }
+    }
+    if let Some(previous) = previous.as_ref() {
+        let changes = changes::compare(previous, &current);
+        println!("{}", changes.summary());
+        notify_changes(options, &changes);
+    }
+    *previous = Some(current);
+}
+
+/// Sends `--notify-url` the changes at or above `--notify-min-severity`,
+/// if there are any.
+fn notify_changes(options: &Options, changes: &changes::Changes) {
+    #[cfg(feature = "webhook")]
+    {
+        if let Some(url) = &options.notify_url {
+            let wanted = changes.at_least(options.notify_min_severity);
+            if !wanted.is_empty() {
+                webhook::send(url, &wanted);
+            }
+        }
+    }
+    #[cfg(not(feature = "webhook"))]
+    let _ = (options, changes);
+}
+
+/// Waits up to `within` for notifications still being delivered, so a
+/// shutdown doesn't cut them off.
+async fn flush_notifications(within: Duration) {
+    #[cfg(feature = "webhook")]
+    {
+        let unsent = webhook::flush(within).await;
+        if unsent > 0 {
+            log::warn!("{} notifications were still being delivered at shutdown", unsent);
+        }
+    }
+    #[cfg(not(feature = "webhook"))]
+    let _ = within;
+}
+
+/// Scans every requested region for every credential context, up to
+/// `--account-concurrency` contexts at once.
+async fn scan_all(options: &Options, contexts: &[CredentialContext], collected: Collected) -> ScanReport {
+    stream::iter(contexts.iter().map(|ctx| scan_context(ctx, options, &collected)))
+        .buffer_unordered(options.account_concurrency)
+        .collect::<Vec<()>>()
+        .await;
+    // Looked up once every context is done so each distinct type costs one
+    // call, however many pages and accounts it appeared on.
+    if options.with_type_specs {
+        let mut specs = type_specs::SpecCache::new();
+        for ctx in contexts.iter() {
+            let wanted = {
+                let scan = collected.lock().unwrap();
+                let own: Vec<Details> = scan.instances.iter()
+                    .filter(|d| d.profile == ctx.profile && d.account_id == ctx.account_id)
+                    .cloned()
+                    .collect();
+                type_specs::missing(&specs, &own)
+            };
+            type_specs::fetch(ctx, &mut specs, wanted, options.error_format).await;
+        }
+        type_specs::apply(&specs, &mut collected.lock().unwrap().instances);
+    }
+    if options.with_ri_coverage {
+        for ctx in contexts.iter() {
+            let own: Vec<Details> = collected.lock().unwrap().instances.iter()
+                .filter(|d| d.profile == ctx.profile && d.account_id == ctx.account_id)
+                .cloned()
+                .collect();
+            let coverage = reserved::coverage(ctx, &own, options.error_format).await;
+            reserved::apply(&coverage, &mut collected.lock().unwrap().instances);
+        }
+    }
+    let scan = collected.lock().unwrap().clone();
+    #[cfg(feature = "cloudwatch")]
+    {
+        if let (true, Some(ctx)) = (options.emit_cloudwatch, contexts.first()) {
+            cloudwatch::put_counts(ctx, options, &scan.instances).await;
+        }
+    }
+    scan.finished(options.started)
+}
+
+/// The `--accounts-file` entries, the `--org` member accounts, or the
+/// profiles (or default chain) with their account ids resolved, each with
+/// its account alias looked up once for all of its regions and cycles.
+async fn scan_contexts(options: &Options) -> Vec<CredentialContext> {
+    if !options.accounts.is_empty() {
+        return with_aliases(assumed(accounts::contexts(options)).await).await;
+    }
+    #[cfg(feature = "organizations")]
+    {
+        if options.org {
+            return match organizations::contexts(options).await {
+                Ok(contexts) => with_aliases(assumed(contexts).await).await,
+                Err(why) => {
+                    error::report(&why, options.error_format);
+                    Vec::new()
+                }
+            }
+        }
+    }
+    let mut contexts = Vec::new();
+    for mut ctx in credentials::contexts(options).into_iter() {
+        identity::identify(&mut ctx).await;
+        contexts.push(ctx);
+    }
+    with_aliases(contexts).await
+}
+
+/// Organization and `--accounts-file` accounts whose role could be
+/// assumed. They are checked once up front, since some will inevitably
+/// lack the role; those that do are reported and left out.
+async fn assumed(contexts: Vec<CredentialContext>) -> Vec<CredentialContext> {
+    let mut usable = Vec::new();
+    for mut ctx in contexts.into_iter() {
+        match identity::identify(&mut ctx).await {
+            true => usable.push(ctx),
+            false => println!("account {}: skipped, couldn't assume its role", account_label(&ctx))
+        }
+    }
+    usable
+}
+
+fn account_label(ctx: &CredentialContext) -> String {
+    format!("{} ({})", ctx.account_id.as_deref().unwrap_or("unknown"), ctx.account_name.as_deref().unwrap_or("unnamed"))
+}
+
+async fn with_aliases(contexts: Vec<CredentialContext>) -> Vec<CredentialContext> {
+    let mut aliased = Vec::new();
+    for ctx in contexts.into_iter() {
+        aliased.push(CredentialContext { account_alias: identity::account_alias(&ctx).await, ..ctx });
+    }
+    aliased
+}
+
+/// Scans one context's regions. Organization and `--accounts-file`
+/// accounts each report their outcome.
+async fn scan_context(ctx: &CredentialContext, options: &Options, collected: &Collected) {
+    let per_account = options.org || !options.accounts.is_empty();
+    let account = account_label(ctx);
+    match (&ctx.regions, &*options.region) {
+        (Some(regions), "all") => {
+            for region in regions.iter() {
+                process_single_region(region.clone(), ctx, options, collected).await;
+            }
+        },
+        (Some(regions), region) if !regions.iter().any(|r| r == region) => {
+            println!("account {}: skipped, {} is not one of its regions", account, region);
+            return;
+        },
+        (_, "all") => process_all_regions(ctx, options, collected).await,
+        (_, region) => process_single_region(region.to_string(), ctx, options, collected).await
+    };
+    if per_account {
+        let found = collected.lock().unwrap().instances.iter().filter(|d| d.account_id == ctx.account_id).count();
+        println!("account {}: {} instances", account, found);
+    }
+}
+
+/// Compares two result files and prints what changed to stdout, as JSON or
+/// one row per change in the other formats.
+fn run_diff(options: &Options) -> Result<i32, AppError> {
+    let old = diff::load(&history::resolve(options, &options.diff_files[0])?)?;
+    let new = diff::load(&history::resolve(options, &options.diff_files[1])?)?;
+    let changes = diff::diff(&old, &new, &options.ignore_fields);
+    let rendered = match options.format {
+        output::Format::Json => serde_json::to_value(&changes).unwrap_or_default(),
+        _ => serde_json::Value::Array(changes.rows())
+    };
+    println!("{}", output::render(options.format, &rendered));
+    eprintln!("{} added, {} removed, {} modified", changes.added.len(), changes.removed.len(), changes.modified.len());
+    Ok(0)
+}
+
+/// Answers a `history` query from the `--sqlite` database and prints the
+/// rows to stdout. Exits 1 when nothing matched, as when an instance was
+/// never seen.
+#[cfg(feature = "sqlite")]
+fn run_history(options: &Options) -> Result<i32, AppError> {
+    let (path, query) = match (&options.sqlite, &options.history_query) {
+        (Some(path), Some(query)) => (path, query),
+        _ => return Err(AppError::usage("history requires a query and --sqlite".to_string()))
+    };
+    let rows = sqlite::query(path, query, &options.by, options.since)?;
+    let matched = !rows.is_empty();
+    println!("{}", output::render(options.format, &serde_json::Value::Array(rows)));
+    Ok(match matched {
+        true => 0,
+        false => 1
+    })
+}
+
+/// Looks for the search term in every selected region at once, printing each
+/// match as a JSON line as soon as its page arrives. With `--first` the
+/// remaining regions are abandoned after the first page with a match.
+/// Exits 1 when nothing matched, like grep.
+async fn run_search(options: &Options, contexts: &[CredentialContext]) -> Result<i32, AppError> {
+    let term = options.search_term.to_lowercase();
+    let mut matched = 0;
+    'contexts: for ctx in contexts.iter() {
+        let regions: Vec<String> = match &*options.region {
+            "all" => {
+                let partition = match options.cross_partition {
+                    true => None,
+                    false => ctx.partition.clone()
+                };
+                region_list().into_iter()
+                    .filter(|r| partition.as_ref().map_or(true, |p| identity::region_partition(r) == p.as_str()))
+                    .map(|r| r.to_string())
+                    .collect()
+            },
+            region => vec![region.to_string()]
+        };
+        let searches = regions.into_iter().map(|region| {
+            let client = Ec2Client::new_with_client(ctx.client_for(&region), identity::ec2_region(&region));
+            let pages = describe_instances(region.clone(), client, get_instance_request(Some(1000), options));
+            Box::pin(pages.map(move |page| (region.clone(), page)))
+        });
+        let pages = stream::select_all(searches);
+        pin_mut!(pages);
+        while let Some((region, page)) = pages.next().await {
+            let details = match page {
+                Ok(details) => details.unwrap_or_default(),
+                Err(why) => {
+                    let failure = AppError::aws(&region, format!("failed to describe instances: {}", aws_error::describe(&why)));
+                    error::report(&failure.with_request_id(aws_error::request_id(&why)), options.error_format);
+                    continue;
+                }
+            };
+            let hits: Vec<Details> = details.into_iter()
+                .filter(|d| search_matches(d, &term))
+                .map(|d| Details { account_alias: ctx.account_alias.clone(), account_id: ctx.account_id.clone(), account_name: ctx.account_name.clone(), profile: ctx.profile.clone(), ..d })
+                .collect();
+            for hit in projected(options, &hits).iter() {
+                println!("{}", hit);
+            }
+            matched += hits.len();
+            if options.first && matched > 0 {
+                break 'contexts;
+            }
+        }
+    }
+    match matched {
+        0 => {
+            eprintln!("no instances matched '{}'", options.search_term);
+            Ok(1)
+        },
+        n => {
+            eprintln!("{} instances matched", n);
+            Ok(0)
+        }
+    }
+}
+
+/// Case-insensitive substring match on the ids, addresses and names someone
+/// is likely to be holding when they ask where an instance is.
+fn search_matches(details: &Details, term: &str) -> bool {
+    let candidates = [
+        details.instance_id.as_deref(),
+        details.name.as_deref(),
+        details.private_dns_name.as_deref(),
+        details.public_dns_name.as_deref(),
+        details.public_ip_address.as_deref()
+    ];
+    candidates.iter().flatten().copied()
+        .chain(details.private_ip_addresses.iter().map(String::as_str))
+        .any(|c| c.to_lowercase().contains(term))
+}
+
+/// Filters the collected instances, writes them (or the requested report)
+/// out and prints a summary. Returns the process exit code.
+async fn finish(options: &Options, scan: ScanReport) -> Result<i32, AppError> {
+    debug!("scanned {} regions in {:?}", scan.per_region_counts.len(), scan.duration);
+    if !scan.errors.is_empty() {
+        println!("{} regions failed during the scan, results are incomplete", scan.errors.len());
+    }
+    if options.estimate_calls {
+        estimate::report(options, &scan.estimates);
+        return Ok(0)
+    }
+    let metadata = metadata(options, &scan);
+    let orphans = scan.orphans;
+    let collected = scan.instances;
+    let total = collected.len();
+    if options.exit_state {
+        return Ok(exit_state(&collected))
+    }
+    if let Some(ids) = &options.instance_ids {
+        let not_found: Vec<&str> = ids.iter()
+            .filter(|id| !collected.iter().any(|d| d.instance_id.as_ref() == Some(*id)))
+            .map(|id| id.as_str())
+            .collect();
+        if !not_found.is_empty() {
+            println!("requested instance ids not found in any scanned region: {}", not_found.join(", "));
+        }
+    }
+    let filtered = filters::apply(options, collected);
+    if filtered.undated > 0 {
+        println!("{} instances excluded by the age filters for a missing or unparseable launch_time", filtered.undated);
+    }
+    let mut output = sort::sort(&options.sort_by, filtered.kept, options.started);
+    derived::apply(options, &mut output);
+    let asserted = check_assertions(options, &output);
+    if let Some(limit) = options.limit {
+        if output.len() > limit {
+            println!("output truncated to {} of {} instances by --limit", limit, output.len());
+            output.truncate(limit);
+        }
+    }
+    let changed = match options.only_changed {
+        true => state_store::changed(options, &output)?,
+        false => None
+    };
+    let code = write_results(options, changed.as_deref().unwrap_or(&output), &orphans, total, metadata).await?;
+    if options.only_changed {
+        state_store::save(options, &output)?;
+        if let Some(changed) = &changed {
+            println!("{} of {} instances changed since the last run", changed.len(), output.len());
+        }
+    }
+    history::save(options, &output)?;
+    if options.all_profiles || options.profiles.len() > 1 {
+        let mut per_profile: BTreeMap<String, usize> = BTreeMap::new();
+        for d in output.iter() {
+            let label = match (&d.profile, d.account_alias.as_ref().or(d.account_id.as_ref())) {
+                (Some(p), Some(a)) => format!("{} ({})", p, a),
+                (Some(p), None) => p.clone(),
+                _ => "default".to_string()
+            };
+            *per_profile.entry(label).or_insert(0) += 1;
+        }
+        for (profile, count) in per_profile.iter() {
+            println!("  profile {}: {}", profile, count);
+        }
+    }
+    Ok(code.max(asserted))
+}
+
+/// Prints the state of the one requested instance and maps it to the exit
+/// code, `running` being the only success.
+fn exit_state(collected: &[Details]) -> i32 {
+    match collected.first().and_then(|d| d.state.as_deref()) {
+        Some(state) => {
+            println!("{}", state);
+            match state {
+                "running" => 0,
+                _ => EXIT_NOT_RUNNING
+            }
+        },
+        None => {
+            println!("not found");
+            EXIT_STATE_UNKNOWN
+        }
+    }
+}
+
+/// Prints each failed `--assert` and returns the exit code of the most
+/// severe, or 0 when they all hold.
+fn check_assertions(options: &Options, output: &[Details]) -> i32 {
+    options.assertions.iter()
+        .filter_map(|a| {
+            let failure = assertions::check(a, output, options.started)?;
+            println!("{}", failure);
+            Some(a.severity.exit_code())
+        })
+        .max()
+        .unwrap_or(0)
+}
+
+/// What produced an output file, written ahead of the instances with
+/// `--envelope` so archived scans are self-describing.
+fn metadata(options: &Options, scan: &ScanReport) -> serde_json::Value {
+    json!({
+        "schema_version": SCHEMA_VERSION,
+        "tool_version": env!("CARGO_PKG_VERSION"),
+        "started": options.started.to_rfc3339(),
+        "duration_secs": scan.duration.as_secs_f64(),
+        "regions": scan.per_region_counts.keys().collect::<Vec<&String>>(),
+        "assumed_role": options.assume_role,
+        "account_id": options.assume_role.as_deref().and_then(options::account_id),
+        "arguments": options.arguments
+    })
+}
+
+/// The instances as output records: `--fields` selected, `--redact` masked
+/// and, with `--omit-null`, null fields left out. Redaction stops here:
+/// SQLite, `--history-dir` and `--expect` work from the real instances.
+fn projected(options: &Options, details: &[Details]) -> Vec<serde_json::Value> {
+    let mut records = fields::project(options.fields.as_deref(), details);
+    fields::redact(&mut records, &options.redact, options.redact_omit);
+    if options.omit_null {
+        fields::omit_null(&mut records);
+    }
+    records
+}
+
+/// Writes the summary, report or instance list. Returns the exit code.
+async fn write_results(options: &Options, output: &[Details], orphans: &[orphans::Orphan], total: usize, metadata: serde_json::Value) -> Result<i32, AppError> {
+    if options.command == Command::Summarize {
+        let groups = summarize::summarize(&grouping::summary_keys(&options.group_by, &options.by), output);
+        write_output(options, &groups).await?;
+        println!("{} groups from {} instances ({} collected before filtering)", groups.len(), output.len(), total);
+        return Ok(0)
+    }
+    if let Some(expected) = &options.expected {
+        let drift = drift::compare(expected, output);
+        write_output(options, &drift).await?;
+        println!("{} expected instances missing, {} unexpected instances found", drift.missing.len(), drift.unexpected.len());
+        return Ok(match drift.is_empty() {
+            true => 0,
+            false => EXIT_FINDINGS
+        })
+    }
+    if let Some(report) = options.report {
+        let rendered = reports::render(report, options, output, orphans);
+        write_output(options, &rendered.body).await?;
+        println!("{} findings from {} instances", rendered.findings, output.len());
+        return Ok(match (rendered.gate, rendered.findings) {
+            (true, n) if n > 0 => EXIT_FINDINGS,
+            _ => 0
+        })
+    }
+    if let Some(size) = options.shard_size {
+        let parts = write_shards(options, projected(options, output), size, metadata).await?;
+        println!("{} instances written in {} parts ({} collected before filtering)", output.len(), parts, total);
+        return Ok(0)
+    }
+    let instances = match options.group_by.is_empty() {
+        true => serde_json::Value::Array(projected(options, output)),
+        false => grouping::nest(&options.group_by, output, projected(options, output))
+    };
+    match options.envelope {
+        true => write_output(options, &json!({ "metadata": metadata, "instances": instances })).await?,
+        false => write_output(options, &instances).await?
+    }
+    println!("{} instances written ({} collected before filtering)", output.len(), total);
+    #[cfg(feature = "sqlite")]
+    {
+        if let Some(path) = &options.sqlite {
+            sqlite::write(path, options.sqlite_mode, output)?;
+            println!("{} instances written to {}", output.len(), path.display());
+        }
+    }
+    #[cfg(feature = "cloudwatch-logs")]
+    {
+        if let Some(group) = &options.cloudwatch_logs_group {
+            let sent = cloudwatch_logs::put_records(options, group, &projected(options, output)).await?;
+            println!("{} instances written to log group {}", sent, group);
+        }
+    }
+    if !options.types.is_empty() {
+        let mut per_type: BTreeMap<&str, usize> = BTreeMap::new();
+        for d in output.iter() {
+            *per_type.entry(d.instance_type.as_deref().unwrap_or("unknown")).or_insert(0) += 1;
+        }
+        for (t, count) in per_type.iter() {
+            println!("  {}: {}", t, count);
+        }
+    }
+    Ok(0)
+}
+
+/// The output is written to a temporary file in the same directory and
+/// renamed into place, so a reader polling the file never sees a partial
+/// write. With `--no-clobber` it is hard linked instead, which fails rather
+/// than replace an existing output, even one that appeared mid-scan.
+///
+/// With `--output-dir` each run gets its own `instances_<region>_<time>`
+/// file in the directory, which is created if missing.
+async fn write_output<T: Serialize + ?Sized>(options: &Options, output: &T) -> Result<(), AppError> {
+    let extension = options.format.extension();
+    let path = match &options.output_dir {
+        Some(dir) => {
+            if let Err(why) = tokio::fs::create_dir_all(dir).await {
+                return Err(match why.kind() {
+                    ErrorKind::PermissionDenied => AppError::io(format!("couldn't create --output-dir {}: permission denied", dir.display())),
+                    _ => AppError::io(format!("couldn't create --output-dir {}: {}", dir.display(), why))
+                })
+            }
+            let stamp = chrono::Utc::now().format("%Y%m%dT%H%M%SZ");
+            dir.join(format!("instances_{}_{}.{}", options.region, stamp, extension))
+        },
+        None => PathBuf::from(format!("instance_results.{}", extension))
     };
-    let output: Vec<Details> = match region {
-        "all" => process_all_regions().await,
-        _ => process_single_region(region.to_string()).await
+    write_file(options, &path, output).await
+}
+
+/// `--shard-size`: writes the records to `instance_results.part1.<ext>`,
+/// `part2` and so on, at most `size` to a file, each with its own envelope
+/// under `--envelope`. Higher-numbered parts left by an earlier, larger run
+/// are removed so they aren't read as part of this one. Returns the number
+/// of parts; an empty result still writes an empty `part1`.
+async fn write_shards(options: &Options, records: Vec<serde_json::Value>, size: usize, metadata: serde_json::Value) -> Result<usize, AppError> {
+    let extension = options.format.extension();
+    let parts: Vec<&[serde_json::Value]> = match records.is_empty() {
+        true => vec![&records[..]],
+        false => records.chunks(size).collect()
     };
-    let writable = serde_json::to_string(&output).unwrap_or("".to_string());
-    match file.write_all((&writable).as_bytes()).await {
-        Err(why) => panic!("couldn't write to {}: {}", display, why),
-        Ok(_) => println!("successfully wrote to {}", display),
+    for (index, part) in parts.iter().enumerate() {
+        let file_name = format!("instance_results.part{}.{}", index + 1, extension);
+        match options.envelope {
+            true => write_file(options, Path::new(&file_name), &json!({ "metadata": metadata, "part": index + 1, "parts": parts.len(), "instances": part })).await?,
+            false => write_file(options, Path::new(&file_name), part).await?
+        }
+    }
+    let mut stale = parts.len() + 1;
+    while !options.no_clobber {
+        let file_name = format!("instance_results.part{}.{}", stale, extension);
+        match tokio::fs::remove_file(&file_name).await {
+            Ok(_) => println!("removed {} left by an earlier run", file_name),
+            Err(why) if why.kind() == ErrorKind::NotFound => break,
+            Err(why) => return Err(AppError::io(format!("couldn't remove {}: {}", file_name, why)))
+        }
+        stale += 1;
     }
+    Ok(parts.len())
 }
 
-async fn process_all_regions() -> Vec<Details> {
-    let mut output: Vec<Details> = Vec::new();
+async fn write_file<T: Serialize + ?Sized>(options: &Options, path: &Path, output: &T) -> Result<(), AppError> {
+    let display = path.display();
+    let file_name = path.file_name().and_then(|n| n.to_str()).unwrap_or_default();
+    let temp_path = path.with_file_name(format!(".{}.tmp.{}", file_name, std::process::id()));
+    let temp = temp_path.as_path();
+    let writable = output::render(options.format, &serde_json::to_value(output).unwrap_or_default());
+    if let Err(why) = write_temp(temp, &writable).await {
+        let _ = tokio::fs::remove_file(temp).await;
+        return Err(match why.kind() {
+            ErrorKind::PermissionDenied => AppError::io(format!("couldn't write to {}: permission denied", temp.display())),
+            _ => AppError::io(format!("couldn't write to {}: {}", temp.display(), why))
+        })
+    }
+    let placed = match options.no_clobber {
+        true => {
+            let linked = tokio::fs::hard_link(temp, path).await;
+            let _ = tokio::fs::remove_file(temp).await;
+            linked
+        },
+        false => tokio::fs::rename(temp, path).await
+    };
+    match placed {
+        Err(why) => {
+            let _ = tokio::fs::remove_file(temp).await;
+            match why.kind() {
+                ErrorKind::AlreadyExists => Err(AppError::io(format!("{} already exists and --no-clobber was given", display))),
+                _ => Err(AppError::io(format!("couldn't move output into {}: {}", display, why)))
+            }
+        },
+        Ok(_) => {
+            println!("successfully wrote to {}", display);
+            Ok(())
+        }
+    }
+}
+
+async fn write_temp(temp: &Path, contents: &str) -> std::io::Result<()> {
+    let mut file = File::create(temp).await?;
+    file.write_all(contents.as_bytes()).await?;
+    file.sync_all().await
+}
+
+/// Unless `--cross-partition` is given, regions outside the partition of the
+/// current credentials are skipped since they can only ever fail.
+async fn process_all_regions(ctx: &CredentialContext, options: &Options, collected: &Collected) {
+    let partition = match options.cross_partition {
+        true => None,
+        false => ctx.partition.clone()
+    };
     for r in region_list().iter() {
-        let result = process_region(r.to_string()).await;
-        output.extend(result);
+        if let Some(p) = &partition {
+            // A region with its own --region-profiles credentials isn't
+            // bound by the partition of the context's.
+            if identity::region_partition(r) != p.as_str() && !ctx.region_clients.contains_key(*r) {
+                println!("skipping {} as it is outside the {} partition", r, p);
+                continue;
+            }
+        }
+        process_region(r.to_string(), ctx, options, collected).await;
     }
-    output
 }
 
-async fn process_single_region(region: String) -> Vec<Details> {
-    process_region(region.to_string()).await
+async fn process_single_region(region: String, ctx: &CredentialContext, options: &Options, collected: &Collected) {
+    process_region(region.to_string(), ctx, options, collected).await
+}
+
+const RETRY_EMPTY_DELAY: Duration = Duration::from_secs(5);
+
+/// Instances asked for per DescribeInstances call while scanning.
+const PAGE_SIZE: i64 = 25;
+
+/// Pages are pushed into `collected` as they arrive rather than once the
+/// region finishes, so a deadline hit mid-region keeps the pages already read.
+///
+/// Each instance id is pushed at most once per region: ids already seen on
+/// an earlier page, or before a re-query, are dropped as pages arrive.
+async fn process_region(region: String, ctx: &CredentialContext, options: &Options, collected: &Collected) {
+    if options.estimate_calls {
+        match estimate::sample(ctx, &region, get_instance_request(Some(estimate::SAMPLE_SIZE), options), PAGE_SIZE).await {
+            Ok(estimate) => collected.lock().unwrap().estimates.push(estimate),
+            Err(failure) => {
+                error::report(&failure, options.error_format);
+                collected.lock().unwrap().errors.push(failure)
+            }
+        }
+        return;
+    }
+    let r = identity::ec2_region(&region);
+    let client = Ec2Client::new_with_client(ctx.client_for(&region), r.clone());
+    let mut request = get_instance_request(Some(PAGE_SIZE), options);
+    let mut retried_empty = false;
+    let mut seen: HashSet<String> = HashSet::new();
+    collected.lock().unwrap().per_region_counts.entry(region.clone()).or_insert(0);
+    'query: loop {
+        let mut found = 0;
+        let mut failed = false;
+        let s = describe_instances(region.clone(), client.clone(), request.clone());
+        pin_mut!(s);
+        while let Some(page) = s.next().await {
+            match page {
+                Ok(Some(details)) => {
+                    let mut stamped: Vec<Details> = unseen(&mut seen, details).into_iter()
+                        .map(|d| Details { account_alias: ctx.account_alias.clone(), account_id: ctx.account_id.clone(), account_name: ctx.account_name.clone(), profile: ctx.profile.clone(), ..d })
+                        .collect();
+                    found += stamped.len();
+                    enrich::page(ctx, &r, options, &mut stamped).await;
+                    collected.lock().unwrap().add_page(&region, stamped)
+                },
+                Ok(None) => {},
+                Err(why) => {
+                    // A requested id living in another region fails the whole
+                    // call, so drop the ids EC2 names and ask again.
+                    if aws_error::error_code(&why).as_deref() == Some("InvalidInstanceID.NotFound") && request.instance_ids.is_some() {
+                        let missing = aws_error::instance_ids(&aws_error::describe(&why));
+                        let ids = request.instance_ids.get_or_insert_with(Vec::new);
+                        let before = ids.len();
+                        ids.retain(|id| !missing.contains(id));
+                        if !ids.is_empty() && ids.len() < before {
+                            continue 'query;
+                        }
+                        if ids.is_empty() {
+                            break 'query;
+                        }
+                    }
+                    failed = true;
+                    let request_id = aws_error::request_id(&why);
+                    debug!("describe instances in {} failed with request id {:?}", region, request_id);
+                    let message = match retry::classify(&why) {
+                        retry::Failure::Auth if options.mfa_serial.is_some() => format!("authentication failed, the MFA session may have expired, rerun with a fresh code: {}", aws_error::describe(&why)),
+                        _ if aws_error::assume_role_denied(&why) => format!("access denied assuming the role (check the ExternalId and the role's trust policy): {}", aws_error::describe(&why)),
+                        retry::Failure::Auth => format!("authentication failed, check the credentials or profile: {}", aws_error::describe(&why)),
+                        _ => format!("failed to describe instances: {}", aws_error::describe(&why))
+                    };
+                    let failure = AppError::aws(&region, message).with_request_id(request_id);
+                    error::report(&failure, options.error_format);
+                    collected.lock().unwrap().errors.push(failure)
+                }
+            }
+        }
+        // Instances launched moments ago can be missing from the first
+        // answer while EC2 catches up, so `--retry-empty` asks once more.
+        if options.retry_empty && found == 0 && !failed && !retried_empty {
+            debug!("no instances in {}, retrying in {:?}", region, RETRY_EMPTY_DELAY);
+            retried_empty = true;
+            tokio::time::sleep(RETRY_EMPTY_DELAY).await;
+            continue 'query;
+        }
+        break;
+    }
+    if options.report == Some(Report::Orphans) {
+        let images_in_use: BTreeSet<String> = collected.lock().unwrap().instances.iter()
+            .filter(|d| d.region == region && d.profile == ctx.profile)
+            .filter_map(|d| d.image_id.clone())
+            .collect();
+        let found = orphans::collect(ctx, &region, &images_in_use, options.error_format).await;
+        collected.lock().unwrap().orphans.extend(found);
+    }
 }
 
-async fn process_region(region: String) -> Vec<Details> {
-    let r = Region::from_str(&region).unwrap();
-    let client = Ec2Client::new(r);
-    let s = describe_instances(region, client);
-    let s = s.filter_map(|v| async move { v.ok() }); // returns Option<Vec<Details>>
-    let s = s.filter_map(|v| async move { v }); // returns Vec<Details>
-    let s: Vec<Vec<Details>> = s.collect().await;
-    s.into_iter().flatten().collect()
+/// The instances in `page` whose ids aren't in `seen` yet, adding them to
+/// it. Instances without an id are always kept.
+fn unseen(seen: &mut HashSet<String>, page: Vec<Details>) -> Vec<Details> {
+    page.into_iter()
+        .filter(|d| d.instance_id.as_ref().map_or(true, |id| seen.insert(id.clone())))
+        .collect()
 }
 
-fn get_instance_request(max_items: Option<i64>) -> DescribeInstancesRequest {
+/// Builds the first page's request; later pages are clones with `next_token`
+/// set so filters apply identically to every page and every region.
+fn get_instance_request(max_items: Option<i64>, options: &Options) -> DescribeInstancesRequest {
+    let mut filters = options.aws_filters.clone();
+    if !options.states.is_empty() && !filters.iter().any(|f| f.name.as_deref() == Some("instance-state-name")) {
+        debug!("filtering states server-side with instance-state-name={}", options.states.join(","));
+        filters.push(Filter {
+            name: Some("instance-state-name".to_string()),
+            values: Some(options.states.clone())
+        });
+    }
+    // EC2 tags instances launched from a template with its id, which is the
+    // only way DescribeInstances can filter on it.
+    if let Some(template) = &options.launch_template {
+        filters.push(Filter {
+            name: Some("tag:aws:ec2launchtemplate:id".to_string()),
+            values: Some(vec![template.clone()])
+        });
+    }
+    if !options.key_names.is_empty() && !filters.iter().any(|f| f.name.as_deref() == Some("key-name")) {
+        filters.push(Filter {
+            name: Some("key-name".to_string()),
+            values: Some(options.key_names.clone())
+        });
+    }
+    // Globs are left to the client-side filter; exact types can be narrowed
+    // down by the API as well.
+    let exact_types = !options.types.is_empty() && options.types.iter().all(|t| !t.contains(|c: char| c == '*' || c == '?'));
+    if exact_types && !filters.iter().any(|f| f.name.as_deref() == Some("instance-type")) {
+        filters.push(Filter {
+            name: Some("instance-type".to_string()),
+            values: Some(options.types.clone())
+        });
+    }
     DescribeInstancesRequest {
         dry_run: None,
-        filters: None,
-        instance_ids: None,
-        max_results: max_items,
+        filters: match filters.is_empty() {
+            true => None,
+            false => Some(filters)
+        },
+        // EC2 rejects MaxResults alongside explicit instance ids.
+        instance_ids: options.instance_ids.clone(),
+        max_results: match options.instance_ids {
+            Some(_) => None,
+            None => max_items
+        },
         next_token: None
     }
 }
 
 struct RequestContext {
     client: Ec2Client,
+    base: DescribeInstancesRequest,
     request: Option<DescribeInstancesRequest>,
     region: String
 }
 
-fn describe_instances(region: String, ec2_client: Ec2Client) -> impl Stream<Item = DetailResult> {
-    let max_items = 25;
+fn describe_instances(region: String, ec2_client: Ec2Client, base: DescribeInstancesRequest) -> impl Stream<Item = DetailResult> {
     let ctx = Some(RequestContext {
         client: ec2_client,
-        request: Some(get_instance_request(Some(max_items))),
+        request: Some(base.clone()),
+        base: base,
         region: region
     });
     stream::unfold(ctx, |ctx| async {
@@ -126,27 +1016,45 @@ fn describe_instances(region: String, ec2_client: Ec2Client) -> impl Stream<Item
         }
         let rc = ctx.unwrap();
         let c = rc.client.clone();
-        let response: Result<DescribeInstancesResult, RusotoError<DescribeInstancesError>> = c.describe_instances(rc.request?).await;
+        let request = rc.request?;
+        let sent_token = request.next_token.clone();
+        let response: Result<DescribeInstancesResult, RusotoError<DescribeInstancesError>> = retry::with_retry(&rc.region, request, |req| {
+            let c = c.clone();
+            async move { c.describe_instances(req).await }
+        }).await;
         match response {
             Ok(r) => {
                 let result = process_reservations(r.reservations, rc.region.clone());
-                if r.next_token.is_none() {
-                    return Some((Ok(result), None));
+                let req = match next_request(&rc.base, &sent_token, r.next_token) {
+                    Some(req) => req,
+                    None => return Some((Ok(result), None))
+                };
+                if result.as_ref().map_or(true, |page| page.is_empty()) {
+                    debug!("empty page with a continuation token in {}, fetching the next page", rc.region);
                 }
-                let mut req = get_instance_request(Some(25));
-                req.next_token = r.next_token;
 
                 Some((Ok(result), Some(RequestContext {
                     client: rc.client,
+                    base: rc.base,
                     request: Some(req),
                     region: rc.region
                 })))
             },
-            Err(_) => None
+            Err(why) => Some((Err(why), None))
         }
     })
 }
 
+/// The request for the page after one sent with `sent`, or `None` when
+/// `received` ends the scan. An empty page can still carry a token, so only
+/// the token decides; an empty or repeated one would never advance.
+fn next_request(base: &DescribeInstancesRequest, sent: &Option<String>, received: Option<String>) -> Option<DescribeInstancesRequest> {
+    match received {
+        Some(token) if !token.is_empty() && Some(&token) != sent.as_ref() => Some(DescribeInstancesRequest { next_token: Some(token), ..base.clone() }),
+        _ => None
+    }
+}
+
 fn process_reservations(reservations: Option<Vec<Reservation>>, region: String) -> Option<Vec<Details>> {
     match reservations {
         Some(r) => Some(r.into_iter()
@@ -161,28 +1069,96 @@ fn process_reservations(reservations: Option<Vec<Reservation>>, region: String)
     }
 }
 
+/// The accelerator services an instance has something attached from.
+/// DescribeInstances only gives association ids, not types such as
+/// `eg1.medium`, so the field lists services rather than types.
+fn accelerator_services(elastic_gpus: &[ElasticGpuAssociation], inference: &[ElasticInferenceAcceleratorAssociation]) -> Vec<String> {
+    let mut services = Vec::new();
+    if !elastic_gpus.is_empty() {
+        services.push("elastic-gpu".to_string());
+    }
+    if !inference.is_empty() {
+        services.push("elastic-inference".to_string());
+    }
+    services
+}
+
 fn instance_map<'a>(instances: Option<Vec<Instance>>, region: &'a str) -> Option<Vec<Details>> {
     let result = instances?.into_iter().map(|a| {
+        let tags = all_tags(&a.tags);
         let tag_map = map_tags(a.tags);
+        let interfaces = a.network_interfaces.unwrap_or_default();
+        let placement = a.placement.unwrap_or_default();
+        let elastic_gpus = a.elastic_gpu_associations.unwrap_or_default();
+        let inference = a.elastic_inference_accelerator_associations.unwrap_or_default();
         Details {
+            accelerator_services: accelerator_services(&elastic_gpus, &inference),
+            account_alias: None,
+            account_id: None,
+            account_name: None,
+            autoscaling_group: tags.get("aws:autoscaling:groupName").cloned(),
+            availability_zone: placement.availability_zone,
+            billing_note: None,
+            boot_mode: None,
+            capacity_reservation_id: a.capacity_reservation_id,
+            cfn_stack: tags.get("aws:cloudformation:stack-name").cloned(),
+            cpu_p95: None,
+            default_vcpus: None,
+            elastic_gpu: Some(!elastic_gpus.is_empty()),
+            host_id: placement.host_id,
+            image_id: a.image_id,
             instance_id: a.instance_id,
             instance_type: a.instance_type,
+            is_nat_candidate: None,
             key_name: a.key_name,
+            launch_template_id: tags.get("aws:ec2launchtemplate:id").cloned(),
+            launch_template_version: tags.get("aws:ec2launchtemplate:version").cloned(),
             launch_time: a.launch_time,
+            memory_mib: None,
+            network_interface_ids: interfaces.iter().filter_map(|n| n.network_interface_id.clone()).collect(),
+            private_ip_addresses: interfaces.iter()
+                .flat_map(|n| n.private_ip_addresses.iter().flatten())
+                .filter_map(|ip| ip.private_ip_address.clone())
+                .collect(),
+            network_performance: None,
+            open_to_internet: None,
+            platform: a.platform,
+            platform_details: a.platform_details,
+            private_dns_name: a.private_dns_name,
+            public_dns_name: a.public_dns_name,
+            public_ip_address: a.public_ip_address,
             region: region.to_string(),
+            ri_covered: None,
+            ri_days_remaining: None,
+            root_device_name: a.root_device_name,
+            security_group_ids: a.security_groups.iter().flatten().filter_map(|g| g.group_id.clone()).collect(),
             source_dest_check: a.source_dest_check,
             state: match a.state {
                 Some(s) => s.name,
                 _ => None
             },
+            state_transition_reason: a.state_transition_reason,
+            subnet_id: a.subnet_id,
+            tag_count: Some(tags.len() as i64),
+            tags: tags,
+            vpc_id: a.vpc_id,
             name: tag_map.name,
+            profile: None,
             project: tag_map.project,
-            environment: tag_map.environment
+            environment: tag_map.environment,
+            environment_normalized: None
         }
     }).collect();
     Some(result)
 }
 
+fn all_tags(tags: &Option<Vec<Tag>>) -> BTreeMap<String, String> {
+    tags.iter()
+        .flatten()
+        .filter_map(|t| Some((t.key.clone()?, t.value.clone().unwrap_or_default())))
+        .collect()
+}
+
 fn map_tags(tags: Option<Vec<Tag>>) -> TagMap {
     let mut tag_map = TagMap {
         project: None,
@@ -212,16 +1188,122 @@ struct TagMap {
     project: Option<String>
 }
 
-#[derive(Serialize, Debug, Clone)]
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default)]
 struct Details {
+    accelerator_services: Vec<String>,
+    account_alias: Option<String>,
+    account_id: Option<String>,
+    account_name: Option<String>,
+    autoscaling_group: Option<String>,
+    availability_zone: Option<String>,
+    billing_note: Option<String>,
+    /// Always null for now: rusoto_ec2 0.46 predates `Instance.BootMode`,
+    /// so it can't be read. The field is output so consumers can rely on
+    /// it once a newer client fills it in.
+    boot_mode: Option<String>,
+    capacity_reservation_id: Option<String>,
+    cfn_stack: Option<String>,
+    cpu_p95: Option<f64>,
+    default_vcpus: Option<i64>,
+    elastic_gpu: Option<bool>,
     environment: Option<String>,
+    environment_normalized: Option<String>,
+    host_id: Option<String>,
+    image_id: Option<String>,
     instance_id: Option<String>,
     instance_type: Option<String>,
+    is_nat_candidate: Option<bool>,
     key_name: Option<String>,
+    launch_template_id: Option<String>,
+    launch_template_version: Option<String>,
     launch_time: Option<String>,
+    memory_mib: Option<i64>,
     name: Option<String>,
+    network_interface_ids: Vec<String>,
+    network_performance: Option<String>,
+    open_to_internet: Option<bool>,
+    platform: Option<String>,
+    platform_details: Option<String>,
+    private_dns_name: Option<String>,
+    private_ip_addresses: Vec<String>,
+    profile: Option<String>,
     project: Option<String>,
+    public_dns_name: Option<String>,
+    public_ip_address: Option<String>,
     region: String,
+    ri_covered: Option<bool>,
+    ri_days_remaining: Option<i64>,
+    root_device_name: Option<String>,
+    security_group_ids: Vec<String>,
     source_dest_check: Option<bool>,
-    state: Option<String>
+    state: Option<String>,
+    state_transition_reason: Option<String>,
+    subnet_id: Option<String>,
+    tag_count: Option<i64>,
+    tags: BTreeMap<String, String>,
+    vpc_id: Option<String>
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(id: &str) -> Details {
+        Details { instance_id: Some(id.to_string()), ..Default::default() }
+    }
+
+    fn ids(details: &[Details]) -> Vec<String> {
+        details.iter().filter_map(|d| d.instance_id.clone()).collect()
+    }
+
+    #[test]
+    fn unseen_drops_ids_from_earlier_pages() {
+        let mut seen = HashSet::new();
+        let first = unseen(&mut seen, vec![instance("i-1"), instance("i-2")]);
+        let second = unseen(&mut seen, vec![instance("i-2"), instance("i-3"), instance("i-3"), Details::default(), Details::default()]);
+        assert_eq!(ids(&first), vec!["i-1", "i-2"]);
+        assert_eq!(ids(&second), vec!["i-3"]);
+        assert_eq!(second.len(), 3);
+    }
+
+    #[test]
+    fn an_empty_page_with_a_token_still_reaches_the_final_page() {
+        let base = DescribeInstancesRequest { max_results: Some(PAGE_SIZE), ..Default::default() };
+        let pages = vec![(Vec::new(), Some("page-2".to_string())), (vec![instance("i-1"), instance("i-2")], None)];
+        let (mut request, mut collected, mut sent) = (Some(base.clone()), Vec::new(), Vec::new());
+        for (page, token) in pages.into_iter() {
+            let current = request.take().unwrap();
+            sent.push(current.next_token.clone());
+            collected.extend(page);
+            request = next_request(&base, &current.next_token, token);
+        }
+        assert!(request.is_none());
+        assert_eq!(sent, vec![None, Some("page-2".to_string())]);
+        assert_eq!(ids(&collected), vec!["i-1", "i-2"]);
+    }
+
+    #[test]
+    fn empty_or_repeated_tokens_end_the_scan() {
+        let base = DescribeInstancesRequest::default();
+        assert_eq!(next_request(&base, &None, None), None);
+        assert_eq!(next_request(&base, &None, Some(String::new())), None);
+        assert_eq!(next_request(&base, &Some("a".to_string()), Some("a".to_string())), None);
+        assert_eq!(next_request(&base, &Some("a".to_string()), Some("b".to_string())).and_then(|r| r.next_token), Some("b".to_string()));
+    }
+
+    #[cfg(feature = "sqlite")]
+    #[test]
+    fn redacting_instance_id_leaves_sqlite_rows_apart() {
+        let args: Vec<String> = ["list_servers", "eu-west-1", "--redact", "instance_id"].iter().map(|a| a.to_string()).collect();
+        let options = options::parse_args(&args).unwrap();
+        let details = vec![instance("i-1"), instance("i-2")];
+        let records = projected(&options, &details);
+        assert!(records.iter().all(|r| r["instance_id"] == "[redacted]"));
+        let path = std::env::temp_dir().join(format!("ec2-monitoring-{}-redact.sqlite", std::process::id()));
+        sqlite::write(&path, options.sqlite_mode, &details).unwrap();
+        let rows: i64 = rusqlite::Connection::open(&path).unwrap().query_row("SELECT COUNT(*) FROM instances", [], |row| row.get(0)).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows, 2);
+    }
 }
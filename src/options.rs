@@ -0,0 +1,1178 @@
+use crate::accounts::{self, AccountEntry};
+use crate::assertions::{self, Assertion};
+use crate::changes::Severity;
+use crate::drift;
+use crate::credentials;
+use crate::error::{AppError, ErrorFormat};
+use crate::fields;
+use crate::grouping::GroupKey;
+use crate::identity;
+use crate::output::Format;
+use crate::pricing::{self, PriceList};
+use crate::query::{self, Expr};
+use crate::schedule::Schedule;
+use crate::sort::{self, SortKey};
+use chrono::{DateTime, Utc};
+use chrono_tz::Tz;
+use regex::Regex;
+use rusoto_ec2::Filter;
+use std::collections::{BTreeMap, BTreeSet};
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::Duration;
+
+pub struct Options {
+    /// `--account-concurrency`: how many accounts or profiles are scanned
+    /// at once.
+    pub account_concurrency: usize,
+    /// `--accounts-file` entries, scanned instead of the caller's account.
+    pub accounts: Vec<AccountEntry>,
+    /// `--all-profiles`: scan every profile in the shared config files.
+    pub all_profiles: bool,
+    pub allowed_tags: Vec<String>,
+    /// The command line after the program name, recorded in `--envelope`
+    /// metadata.
+    pub arguments: Vec<String>,
+    /// `--assume-role`: a role ARN assumed before scanning.
+    pub assume_role: Option<String>,
+    /// `--assert-readonly`: before scanning, check that the credentials
+    /// can't change instances.
+    pub assert_readonly: bool,
+    /// `--assert` checks, evaluated against the filtered instances.
+    pub assertions: Vec<Assertion>,
+    pub aws_filters: Vec<Filter>,
+    pub billing_notes: bool,
+    pub by: Vec<String>,
+    /// `--case-insensitive-names`: `--report duplicates` treats names that
+    /// differ only in case as the same.
+    pub case_insensitive_names: bool,
+    /// `--cloudwatch-logs-group`: also write the instances to a new stream
+    /// in this CloudWatch Logs group, one event per record.
+    pub cloudwatch_logs_group: Option<String>,
+    pub command: Command,
+    /// `--cpu-threshold`: p95 CPU percentage under which `--report rightsize`
+    /// suggests a smaller type.
+    pub cpu_threshold: f64,
+    pub cpu_window: Duration,
+    pub cross_partition: bool,
+    pub deadline: Option<Duration>,
+    /// `--deprecated-keys`: key pair names flagged by `--report key-audit`.
+    pub deprecated_keys: Vec<String>,
+    pub diff_files: Vec<PathBuf>,
+    /// `--emit-cloudwatch`: publish instance counts per region and state as
+    /// CloudWatch metrics after the scan, under `--namespace`.
+    pub emit_cloudwatch: bool,
+    /// `--envelope`: wrap JSON output as `{metadata, instances}`.
+    pub envelope: bool,
+    pub error_format: ErrorFormat,
+    /// `--estimate-calls`: sample each region and print how many
+    /// DescribeInstances calls a scan would make, instead of scanning.
+    pub estimate_calls: bool,
+    pub exempt_names: Vec<String>,
+    /// `--exit-state`: print the single instance's state and exit 0 only if
+    /// it is running, writing no file.
+    pub exit_state: bool,
+    /// `--expect`: the instance ids that should exist, compared with the
+    /// scan instead of writing the instance list.
+    pub expected: Option<BTreeSet<String>>,
+    /// `--external-id` sent with `--assume-role`, for roles whose trust
+    /// policy requires one.
+    pub external_id: Option<String>,
+    pub fields: Option<Vec<String>>,
+    /// `--first`: `search` stops at the first region with a match.
+    pub first: bool,
+    pub format: Format,
+    /// `--full-refresh-every`: how often `--incremental` still runs a full
+    /// scan, to pick up changes that don't show in instance states.
+    pub full_refresh_every: Duration,
+    /// `--grace-period`: how long `serve` waits on shutdown for requests
+    /// and notifications still in flight.
+    pub grace_period: Duration,
+    /// `--group-by account,region`: nest the instance list by these levels
+    /// instead of writing one flat array.
+    pub group_by: Vec<GroupKey>,
+    /// `--history-dir`: also keep a timestamped snapshot of every run here.
+    pub history_dir: Option<PathBuf>,
+    /// The query given to the `history` subcommand.
+    pub history_query: Option<HistoryQuery>,
+    pub ignore_fields: Vec<String>,
+    /// `--incremental`: between full scans, re-read only the instances
+    /// that are new or changed state. The inventory is saved after every
+    /// cycle and picked up again on restart.
+    pub incremental: bool,
+    pub instance_ids: Option<Vec<String>>,
+    /// `--keep`: how many `--history-dir` snapshots to keep.
+    pub keep: Option<usize>,
+    /// `--keep-for`: how long to keep `--history-dir` snapshots.
+    pub keep_for: Option<Duration>,
+    /// `--key-name`: only instances launched with one of these key pairs.
+    pub key_names: Vec<String>,
+    pub launch_template: Option<String>,
+    /// `--limit`: a cap on the whole output across every region and
+    /// profile, applied after filtering and sorting.
+    pub limit: Option<usize>,
+    /// `--listen`: the address `serve` accepts requests on.
+    pub listen: SocketAddr,
+    /// `--mfa-serial`: the MFA device for `--assume-role`.
+    pub mfa_serial: Option<String>,
+    /// `--mfa-token`; prompted for on a terminal when absent.
+    pub mfa_token: Option<String>,
+    pub name_regex: Option<Regex>,
+    pub name_regex_invert: bool,
+    /// `--namespace` for `--emit-cloudwatch` metrics.
+    pub namespace: Option<String>,
+    /// `--nat-candidates`: fill `is_nat_candidate`; source/destination
+    /// checking is disabled on instances that forward traffic, such as NAT
+    /// instances and routers.
+    pub nat_candidates: bool,
+    pub newer_than: Option<Duration>,
+    pub no_clobber: bool,
+    /// `--normalize-environment`: fill `environment_normalized` using the
+    /// same folding as `--report tag-report`.
+    pub normalize_environment: bool,
+    /// `--notify-min-severity state|lifecycle`: `lifecycle` leaves out
+    /// stops and starts, keeping launches and terminations.
+    pub notify_min_severity: Severity,
+    /// `--notify-url`: POST each watch or serve cycle's changes here.
+    pub notify_url: Option<String>,
+    /// `--omit-null`: leave null fields out of JSON records instead of
+    /// writing them as `null`.
+    pub omit_null: bool,
+    pub older_than: Option<Duration>,
+    /// `--only-changed`: write only the instances that are new or changed
+    /// state since the last run, as recorded in `state_file`.
+    pub only_changed: bool,
+    /// `--org`: scan every account in the AWS Organization.
+    pub org: bool,
+    pub org_accounts: Vec<String>,
+    pub org_exclude_accounts: Vec<String>,
+    pub org_ous: Vec<String>,
+    /// `--org-role`: the role assumed in each member account.
+    pub org_role: String,
+    /// `--output-dir`: write each run to a new timestamped file here
+    /// instead of `instance_results.<ext>`.
+    pub output_dir: Option<PathBuf>,
+    /// `--prices`: hourly on-demand prices used by `--report cost-summary`.
+    pub prices: Option<PriceList>,
+    pub profiles: Vec<String>,
+    /// `--prune-dry-run`: list the snapshots pruning would remove instead
+    /// of removing them.
+    pub prune_dry_run: bool,
+    pub query: Option<Expr>,
+    /// `--queue-url`: the SQS queue `listen` takes state-change events from.
+    pub queue_url: Option<String>,
+    /// `--ready-intervals`: `serve` reports not ready once its last good
+    /// collection is older than this many refresh intervals.
+    pub ready_intervals: u32,
+    /// `--ready-max-failures`: `serve` reports not ready after this many
+    /// failed collections in a row.
+    pub ready_max_failures: u32,
+    /// `--refresh-interval`: how often `serve` rescans.
+    pub refresh_interval: Duration,
+    /// `--redact`: fields, or `tags.<Key>`, masked in the instance output.
+    pub redact: Vec<String>,
+    /// `--redact-omit`: drop the `--redact` fields instead of masking them.
+    pub redact_omit: bool,
+    pub region: String,
+    /// `--region-profiles`: regions scanned with another profile's
+    /// credentials rather than the context's.
+    pub region_profiles: BTreeMap<String, String>,
+    pub report: Option<Report>,
+    pub require_tags: Vec<String>,
+    /// `--retry-empty`: query a region a second time when it returns no
+    /// instances at all.
+    pub retry_empty: bool,
+    /// `--role-chain`: jump roles assumed in order, each with the previous
+    /// one's credentials, before `--assume-role` or the `--org` role.
+    pub role_chain: Vec<RoleHop>,
+    /// `--schedule "cron[ | region flags...]"`: run on these cron schedules
+    /// instead of once or every `--watch` interval.
+    pub schedules: Vec<Schedule>,
+    pub search_term: String,
+    /// `--session-duration` (or `--duration`) of an assumed role session;
+    /// STS defaults to an hour.
+    pub session_duration: Option<Duration>,
+    /// `--session-name` for an assumed role, shown in CloudTrail.
+    pub session_name: String,
+    /// `--shard-size`: split the instance list into files of at most this
+    /// many records.
+    pub shard_size: Option<usize>,
+    /// `--since`: how far back `history count` looks; all runs when unset.
+    pub since: Option<Duration>,
+    pub sort_by: Vec<SortKey>,
+    /// `--sqlite`: also write the instances to this SQLite database.
+    pub sqlite: Option<PathBuf>,
+    /// `--sqlite-mode replace|upsert`, upsert by default.
+    pub sqlite_mode: SqliteMode,
+    /// When the run began; launch ages are measured from here.
+    pub started: DateTime<Utc>,
+    /// `--state-file`: where `--only-changed` keeps each instance's last
+    /// seen state between runs. `--incremental` keeps its inventory in the
+    /// same directory.
+    pub state_file: Option<PathBuf>,
+    /// `--state` values. These are sent to DescribeInstances as an
+    /// `instance-state-name` filter, so terminated instances never leave
+    /// AWS, and are checked again client-side in `filters::apply`. An
+    /// explicit `--aws-filter instance-state-name=...` takes precedence for
+    /// the server-side half.
+    pub states: Vec<String>,
+    pub stopped_for: Duration,
+    /// `--tag-key`: the tag whose values `--report tag-report` examines.
+    pub tag_key: Option<String>,
+    /// `--tag-synonyms variant=canonical,...`, keyed by lowercase variant.
+    pub tag_synonyms: BTreeMap<String, String>,
+    pub tags: Vec<TagFilter>,
+    pub tags_not: Vec<TagFilter>,
+    /// `--timezone` the `--schedule` expressions are read in, UTC by default.
+    pub timezone: Tz,
+    pub types: Vec<String>,
+    /// `--watch`: rescan on this interval until interrupted.
+    pub watch: Option<Duration>,
+    /// Fetch p95 CPU from CloudWatch for running instances; implied by
+    /// `--report rightsize`.
+    pub with_cpu: bool,
+    /// `--with-ri-coverage`: fill `ri_covered` and `ri_days_remaining` for
+    /// running instances from the account's active reserved instances.
+    pub with_ri_coverage: bool,
+    /// `--with-type-specs`: join vCPU, memory and network performance from
+    /// DescribeInstanceTypes onto each instance.
+    pub with_type_specs: bool
+}
+
+/// The optional leading subcommand; a plain scan when none is given.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Diff,
+    History,
+    Listen,
+    Scan,
+    Search,
+    Serve,
+    Summarize
+}
+
+/// A `--report` mode, written in place of the instance list.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Report {
+    Cleanup,
+    Compliance,
+    CostSummary,
+    Duplicates,
+    KeyAudit,
+    Orphans,
+    PlatformSummary,
+    ProjectEnvMatrix,
+    PublicExposure,
+    Rightsize,
+    SourceDest,
+    TagDensity,
+    TagPolicy,
+    TagReport
+}
+
+/// A `history` query against the run history kept by `--sqlite`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HistoryQuery {
+    /// Instances per day, grouped by the `--by` keys or region.
+    Count,
+    /// When an instance was first and last seen.
+    Seen(String),
+    /// The runs in which an instance's state changed.
+    Timeline(String)
+}
+
+/// What `--sqlite` does with the rows already in the table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SqliteMode {
+    /// Empty the table first, so it holds exactly this run's instances.
+    Replace,
+    /// Insert new instances and update known ones, keeping the rest.
+    Upsert
+}
+
+/// A tag key and the values accepted for it, from repeated `--tag` or
+/// `--tag-not` flags naming the same key.
+pub struct TagFilter {
+    pub key: String,
+    pub values: Vec<String>
+}
+
+/// A jump role from `--role-chain`, with the ExternalId its trust policy
+/// asks for, if any.
+pub struct RoleHop {
+    pub external_id: Option<String>,
+    pub role_arn: String
+}
+
+pub fn parse_args(args: &[String]) -> Result<Options, AppError> {
+    parse(args, false)
+}
+
+/// `nested` is set while parsing a `--schedule` target, which takes the
+/// schedules from the command line it was built from rather than its own.
+fn parse(args: &[String], nested: bool) -> Result<Options, AppError> {
+    let full = args;
+    if args.len() == 1 {
+        return Err(AppError::usage("no arguments were provided\nPlease provide a valid region or 'all' to get an output from every available region".to_string()))
+    }
+    let arguments = args[1..].to_vec();
+    let (command, args) = match args[1].as_str() {
+        "diff" => (Command::Diff, &args[1..]),
+        "history" => (Command::History, &args[1..]),
+        "listen" => (Command::Listen, &args[1..]),
+        "search" => (Command::Search, &args[1..]),
+        "serve" => (Command::Serve, &args[1..]),
+        "summarize" => (Command::Summarize, &args[1..]),
+        _ => (Command::Scan, args)
+    };
+    // diff compares two files in place of a region and never calls AWS.
+    let (diff_files, args) = match command {
+        Command::Diff if args.len() >= 3 => (vec![PathBuf::from(&args[1]), PathBuf::from(&args[2])], &args[1..]),
+        Command::Diff => return Err(AppError::usage("diff requires two result files: diff <old.json> <new.json>, or previous/latest with --history-dir".to_string())),
+        _ => (Vec::new(), args)
+    };
+    // search takes the term before the region.
+    let (search_term, args) = match command {
+        Command::Search if args.len() >= 2 && !args[1].trim().is_empty() => (args[1].clone(), &args[1..]),
+        Command::Search => return Err(AppError::usage("search requires a term: search <term> <region|all>".to_string())),
+        _ => (String::new(), args)
+    };
+    // history reads a database rather than AWS; its query, and the
+    // instance id for seen and timeline, stand in for the region.
+    let (history_query, args) = match command {
+        Command::History => match (args.get(1).map(|q| q.as_str()), args.get(2)) {
+            (Some("count"), _) => (Some(HistoryQuery::Count), args),
+            (Some("seen"), Some(id)) if !id.starts_with("--") => (Some(HistoryQuery::Seen(id.clone())), &args[1..]),
+            (Some("timeline"), Some(id)) if !id.starts_with("--") => (Some(HistoryQuery::Timeline(id.clone())), &args[1..]),
+            _ => return Err(AppError::usage("history requires a query: history count, history seen <instance-id> or history timeline <instance-id>".to_string()))
+        },
+        _ => (None, args)
+    };
+    if args.len() == 1 {
+        return Err(AppError::usage("no region was provided\nPlease provide a valid region or 'all' after the command".to_string()))
+    }
+    let mut options = Options {
+        account_concurrency: 4,
+        accounts: Vec::new(),
+        all_profiles: false,
+        allowed_tags: Vec::new(),
+        arguments: arguments,
+        assert_readonly: false,
+        assertions: Vec::new(),
+        assume_role: None,
+        aws_filters: Vec::new(),
+        billing_notes: false,
+        by: Vec::new(),
+        case_insensitive_names: false,
+        cloudwatch_logs_group: None,
+        command: command,
+        cpu_threshold: 10.0,
+        cpu_window: Duration::from_secs(14 * 24 * 60 * 60),
+        cross_partition: false,
+        deadline: None,
+        deprecated_keys: Vec::new(),
+        diff_files: diff_files,
+        emit_cloudwatch: false,
+        envelope: false,
+        error_format: ErrorFormat::Text,
+        estimate_calls: false,
+        exempt_names: Vec::new(),
+        exit_state: false,
+        expected: None,
+        external_id: None,
+        fields: None,
+        first: false,
+        format: Format::Json,
+        full_refresh_every: Duration::from_secs(6 * 60 * 60),
+        grace_period: Duration::from_secs(10),
+        group_by: Vec::new(),
+        history_dir: None,
+        history_query: history_query,
+        ignore_fields: Vec::new(),
+        incremental: false,
+        instance_ids: None,
+        keep: None,
+        keep_for: None,
+        key_names: Vec::new(),
+        launch_template: None,
+        limit: None,
+        listen: SocketAddr::from(([127, 0, 0, 1], 8080)),
+        mfa_serial: None,
+        mfa_token: None,
+        name_regex: None,
+        name_regex_invert: false,
+        namespace: None,
+        nat_candidates: false,
+        newer_than: None,
+        no_clobber: false,
+        normalize_environment: false,
+        notify_min_severity: Severity::State,
+        notify_url: None,
+        older_than: None,
+        omit_null: false,
+        only_changed: false,
+        org: false,
+        org_accounts: Vec::new(),
+        org_exclude_accounts: Vec::new(),
+        org_ous: Vec::new(),
+        org_role: "OrganizationAccountAccessRole".to_string(),
+        output_dir: None,
+        prices: None,
+        profiles: Vec::new(),
+        prune_dry_run: false,
+        query: None,
+        queue_url: None,
+        ready_intervals: 3,
+        ready_max_failures: 3,
+        redact: Vec::new(),
+        redact_omit: false,
+        refresh_interval: Duration::from_secs(5 * 60),
+        region: args[1].clone(),
+        region_profiles: BTreeMap::new(),
+        report: None,
+        require_tags: Vec::new(),
+        retry_empty: false,
+        role_chain: Vec::new(),
+        schedules: Vec::new(),
+        search_term: search_term,
+        session_duration: None,
+        session_name: "list_servers".to_string(),
+        shard_size: None,
+        since: None,
+        sort_by: sort::default_keys(),
+        sqlite: None,
+        sqlite_mode: SqliteMode::Upsert,
+        started: Utc::now(),
+        state_file: None,
+        states: Vec::new(),
+        stopped_for: Duration::from_secs(30 * 24 * 60 * 60),
+        tag_key: None,
+        tag_synonyms: BTreeMap::new(),
+        tags: Vec::new(),
+        tags_not: Vec::new(),
+        timezone: Tz::UTC,
+        types: Vec::new(),
+        watch: None,
+        with_cpu: false,
+        with_ri_coverage: false,
+        with_type_specs: false
+    };
+    let region_index = full.len() - args.len() + 1;
+    let mut schedule_specs: Vec<String> = Vec::new();
+    let mut iter = args[2..].iter();
+    while let Some(flag) = iter.next() {
+        match flag.as_str() {
+            "--account-concurrency" => {
+                let value = flag_value(flag, iter.next())?;
+                options.account_concurrency = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => n,
+                    Ok(_) => return Err(AppError::usage(format!("invalid value for {}: must be at least 1", flag))),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--accounts-file" => options.accounts = accounts::load(Path::new(flag_value(flag, iter.next())?))?,
+            "--all-profiles" => options.all_profiles = true,
+            "--allowed-tags" => options.allowed_tags.extend(split_list(flag_value(flag, iter.next())?)),
+            "--assert" => {
+                let assertion = flag_value(flag, iter.next())?;
+                match assertions::parse(assertion) {
+                    Ok(a) => options.assertions.push(a),
+                    Err(why) => return Err(AppError::usage(format!("invalid --assert '{}': {}", assertion, why)))
+                }
+            },
+            "--assert-readonly" => options.assert_readonly = true,
+            "--assume-role" => options.assume_role = Some(parse_role(flag, flag_value(flag, iter.next())?)?),
+            "--aws-filter" => add_aws_filter(&mut options.aws_filters, flag, flag_value(flag, iter.next())?)?,
+            "--billing-notes" => options.billing_notes = true,
+            "--by" => options.by = parse_group_keys(flag_value(flag, iter.next())?)?,
+            "--case-insensitive-names" => options.case_insensitive_names = true,
+            "--cloudwatch-logs-group" => {
+                require_feature(flag, "cloudwatch-logs", cfg!(feature = "cloudwatch-logs"))?;
+                options.cloudwatch_logs_group = Some(flag_value(flag, iter.next())?.to_string())
+            },
+            "--cpu-threshold" => {
+                let value = flag_value(flag, iter.next())?;
+                options.cpu_threshold = match value.parse::<f64>() {
+                    Ok(n) if n > 0.0 && n <= 100.0 => n,
+                    Ok(_) => return Err(AppError::usage(format!("invalid value for {}: expected a percentage between 0 and 100", flag))),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--cpu-window" => options.cpu_window = parse_duration(flag, flag_value(flag, iter.next())?)?,
+            "--cross-partition" => options.cross_partition = true,
+            "--deadline-secs" => {
+                let secs = flag_value(flag, iter.next())?;
+                options.deadline = match secs.parse::<u64>() {
+                    Ok(s) => Some(Duration::from_secs(s)),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--deprecated-keys" => options.deprecated_keys.extend(split_list(flag_value(flag, iter.next())?)),
+            "--duration" | "--session-duration" => {
+                let duration = parse_duration(flag, flag_value(flag, iter.next())?)?;
+                if duration < Duration::from_secs(15 * 60) || duration > Duration::from_secs(12 * 60 * 60) {
+                    return Err(AppError::usage(format!("invalid value for {}: role sessions last between 15m and 12h", flag)))
+                }
+                options.session_duration = Some(duration)
+            },
+            "--emit-cloudwatch" => {
+                require_feature(flag, "cloudwatch", cfg!(feature = "cloudwatch"))?;
+                options.emit_cloudwatch = true
+            },
+            "--envelope" => options.envelope = true,
+            "--error-format" => options.error_format = parse_error_format(flag_value(flag, iter.next())?)?,
+            "--estimate-calls" => options.estimate_calls = true,
+            "--exempt-names" => options.exempt_names.extend(split_list(flag_value(flag, iter.next())?)),
+            "--exit-state" => options.exit_state = true,
+            "--expect" => options.expected = Some(drift::load(Path::new(flag_value(flag, iter.next())?))?),
+            "--external-id" => options.external_id = Some(flag_value(flag, iter.next())?.to_string()),
+            "--fields" => options.fields = Some(parse_fields(flag_value(flag, iter.next())?)?),
+            "--first" => options.first = true,
+            "--format" => options.format = parse_format(flag_value(flag, iter.next())?)?,
+            "--full-refresh-every" => options.full_refresh_every = parse_duration(flag, flag_value(flag, iter.next())?)?,
+            "--grace-period" => options.grace_period = parse_duration(flag, flag_value(flag, iter.next())?)?,
+            "--group-by" => options.group_by = parse_group_by(flag_value(flag, iter.next())?)?,
+            "--history-dir" => options.history_dir = Some(PathBuf::from(flag_value(flag, iter.next())?)),
+            "--ignore-fields" => options.ignore_fields.extend(split_list(flag_value(flag, iter.next())?)),
+            "--incremental" => options.incremental = true,
+            "--instance-id" | "--instance-ids" => add_instance_ids(options.instance_ids.get_or_insert_with(Vec::new), flag, flag_value(flag, iter.next())?)?,
+            "--keep" => {
+                let value = flag_value(flag, iter.next())?;
+                options.keep = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => Some(n),
+                    Ok(_) => return Err(AppError::usage(format!("{} must keep at least one snapshot", flag))),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--keep-for" => options.keep_for = Some(parse_duration(flag, flag_value(flag, iter.next())?)?),
+            "--key-name" => options.key_names.extend(split_list(flag_value(flag, iter.next())?)),
+            "--launch-template" => options.launch_template = Some(flag_value(flag, iter.next())?.to_string()),
+            "--limit" => {
+                let value = flag_value(flag, iter.next())?;
+                options.limit = match value.parse::<usize>() {
+                    Ok(n) => Some(n),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--listen" => {
+                let value = flag_value(flag, iter.next())?;
+                options.listen = match value.parse::<SocketAddr>() {
+                    Ok(addr) => addr,
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}, expected host:port such as 0.0.0.0:8080", flag, why)))
+                }
+            },
+            "--mfa-serial" => options.mfa_serial = Some(flag_value(flag, iter.next())?.to_string()),
+            "--mfa-token" => {
+                let token = flag_value(flag, iter.next())?;
+                if token.len() != 6 || !token.chars().all(|c: char| c.is_ascii_digit()) {
+                    return Err(AppError::usage(format!("invalid value for {}: expected a 6 digit code", flag)))
+                }
+                options.mfa_token = Some(token.to_string())
+            },
+            "--name-regex" => {
+                let pattern = flag_value(flag, iter.next())?;
+                options.name_regex = match Regex::new(pattern) {
+                    Ok(r) => Some(r),
+                    Err(why) => return Err(AppError::usage(format!("invalid --name-regex '{}': {}", pattern, why)))
+                }
+            },
+            "--name-regex-invert" => options.name_regex_invert = true,
+            "--namespace" => options.namespace = Some(flag_value(flag, iter.next())?.to_string()),
+            "--nat-candidates" => options.nat_candidates = true,
+            "--newer-than" => options.newer_than = Some(parse_duration(flag, flag_value(flag, iter.next())?)?),
+            "--no-clobber" => options.no_clobber = true,
+            "--normalize-environment" => options.normalize_environment = true,
+            "--notify-min-severity" => {
+                options.notify_min_severity = match flag_value(flag, iter.next())? {
+                    "lifecycle" => Severity::Lifecycle,
+                    "state" => Severity::State,
+                    other => return Err(AppError::usage(format!("unknown {} '{}', expected state or lifecycle", flag, other)))
+                }
+            },
+            "--notify-url" => {
+                require_feature(flag, "webhook", cfg!(feature = "webhook"))?;
+                let url = flag_value(flag, iter.next())?;
+                if !url.starts_with("http://") && !url.starts_with("https://") {
+                    return Err(AppError::usage(format!("{} must be an http:// or https:// URL, got '{}'", flag, url)))
+                }
+                options.notify_url = Some(url.to_string())
+            },
+            "--older-than" => options.older_than = Some(parse_duration(flag, flag_value(flag, iter.next())?)?),
+            "--omit-null" => options.omit_null = true,
+            "--only-changed" => options.only_changed = true,
+            "--org" => {
+                require_feature(flag, "organizations", cfg!(feature = "organizations"))?;
+                options.org = true
+            },
+            "--org-accounts" => options.org_accounts.extend(split_list(flag_value(flag, iter.next())?)),
+            "--org-exclude-accounts" => options.org_exclude_accounts.extend(split_list(flag_value(flag, iter.next())?)),
+            "--org-ous" => options.org_ous.extend(split_list(flag_value(flag, iter.next())?)),
+            "--org-role" => options.org_role = flag_value(flag, iter.next())?.to_string(),
+            "--output-dir" => options.output_dir = Some(PathBuf::from(flag_value(flag, iter.next())?)),
+            "--prices" => options.prices = Some(pricing::load(Path::new(flag_value(flag, iter.next())?))?),
+            "--profile" | "--profiles" => options.profiles.extend(split_list(flag_value(flag, iter.next())?)),
+            "--prune-dry-run" => options.prune_dry_run = true,
+            "--queue-url" => {
+                require_feature(flag, "sqs", cfg!(feature = "sqs"))?;
+                options.queue_url = Some(flag_value(flag, iter.next())?.to_string())
+            },
+            // --where is the same language; repeated or combined
+            // expressions must all hold.
+            "--query" | "--where" => {
+                let expression = flag_value(flag, iter.next())?;
+                let parsed = match query::parse(expression) {
+                    Ok(q) => q,
+                    Err(why) => return Err(AppError::usage(format!("invalid {}: {}", flag, why)))
+                };
+                options.query = Some(match options.query.take() {
+                    Some(existing) => Expr::And(Box::new(existing), Box::new(parsed)),
+                    None => parsed
+                })
+            },
+            "--ready-intervals" => options.ready_intervals = parse_positive(flag, flag_value(flag, iter.next())?)?,
+            "--ready-max-failures" => options.ready_max_failures = parse_positive(flag, flag_value(flag, iter.next())?)?,
+            "--redact" => {
+                let value = flag_value(flag, iter.next())?;
+                let valid = fields::names();
+                for name in split_list(value) {
+                    if !name.starts_with("tags.") && !valid.contains(&name) {
+                        return Err(AppError::usage(format!("unknown field '{}' in {}, expected tags.<Key> or any of: {}", name, flag, valid.join(", "))))
+                    }
+                    options.redact.push(name);
+                }
+            },
+            "--redact-omit" => options.redact_omit = true,
+            "--refresh-interval" => options.refresh_interval = parse_duration(flag, flag_value(flag, iter.next())?)?,
+            "--region-profiles" => options.region_profiles = credentials::load_region_profiles(Path::new(flag_value(flag, iter.next())?))?,
+            "--report" => {
+                let report = parse_report(flag_value(flag, iter.next())?)?;
+                if report == Report::Rightsize {
+                    require_feature("--report rightsize", "cloudwatch", cfg!(feature = "cloudwatch"))?;
+                    options.with_cpu = true;
+                }
+                options.report = Some(report)
+            },
+            "--require-tags" => options.require_tags.extend(split_list(flag_value(flag, iter.next())?)),
+            "--retry-empty" => options.retry_empty = true,
+            "--role-chain" => options.role_chain.extend(parse_role_chain(flag, flag_value(flag, iter.next())?)?),
+            "--schedule" => schedule_specs.push(flag_value(flag, iter.next())?.to_string()),
+            "--session-name" => options.session_name = flag_value(flag, iter.next())?.to_string(),
+            "--shard-size" => {
+                let value = flag_value(flag, iter.next())?;
+                options.shard_size = match value.parse::<usize>() {
+                    Ok(n) if n > 0 => Some(n),
+                    Ok(_) => return Err(AppError::usage(format!("invalid value for {}: must be at least 1", flag))),
+                    Err(why) => return Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+                }
+            },
+            "--sort-by" => options.sort_by = parse_sort_keys(flag_value(flag, iter.next())?)?,
+            "--since" => options.since = Some(parse_duration(flag, flag_value(flag, iter.next())?)?),
+            "--sqlite" => {
+                require_feature(flag, "sqlite", cfg!(feature = "sqlite"))?;
+                options.sqlite = Some(PathBuf::from(flag_value(flag, iter.next())?))
+            },
+            "--sqlite-mode" => {
+                options.sqlite_mode = match flag_value(flag, iter.next())? {
+                    "replace" => SqliteMode::Replace,
+                    "upsert" => SqliteMode::Upsert,
+                    other => return Err(AppError::usage(format!("unknown {} '{}', expected replace or upsert", flag, other)))
+                }
+            },
+            "--state" => add_states(&mut options.states, flag_value(flag, iter.next())?)?,
+            "--state-file" => options.state_file = Some(PathBuf::from(flag_value(flag, iter.next())?)),
+            "--stopped-for" => options.stopped_for = parse_duration(flag, flag_value(flag, iter.next())?)?,
+            "--tag" => add_tag_filter(&mut options.tags, flag, flag_value(flag, iter.next())?)?,
+            "--tag-key" => options.tag_key = Some(flag_value(flag, iter.next())?.to_string()),
+            "--tag-not" => add_tag_filter(&mut options.tags_not, flag, flag_value(flag, iter.next())?)?,
+            "--tag-synonyms" => {
+                for pair in split_list(flag_value(flag, iter.next())?) {
+                    let (variant, canonical) = split_pair(flag, &pair)?;
+                    options.tag_synonyms.insert(variant.trim().to_lowercase(), canonical.trim().to_lowercase());
+                }
+            },
+            "--timezone" => {
+                let value = flag_value(flag, iter.next())?;
+                options.timezone = match value.parse::<Tz>() {
+                    Ok(tz) => tz,
+                    Err(why) => return Err(AppError::usage(format!("unknown {} '{}': {}", flag, value, why)))
+                }
+            },
+            "--type" => options.types.extend(split_list(flag_value(flag, iter.next())?)),
+            "--watch" => options.watch = Some(parse_duration(flag, flag_value(flag, iter.next())?)?),
+            "--with-cpu" => {
+                require_feature(flag, "cloudwatch", cfg!(feature = "cloudwatch"))?;
+                options.with_cpu = true
+            },
+            "--with-ri-coverage" => options.with_ri_coverage = true,
+            "--with-type-specs" => options.with_type_specs = true,
+            _ => return Err(AppError::usage(format!("unrecognised argument: {}", flag)))
+        }
+    }
+    apply_env_filters(&mut options)?;
+    if options.command == Command::Summarize && options.by.is_empty() {
+        return Err(AppError::usage("summarize requires --by".to_string()))
+    }
+    if !options.org && (!options.org_accounts.is_empty() || !options.org_exclude_accounts.is_empty() || !options.org_ous.is_empty()) {
+        return Err(AppError::usage("--org-accounts, --org-exclude-accounts and --org-ous require --org".to_string()))
+    }
+    if (options.keep.is_some() || options.keep_for.is_some() || options.prune_dry_run) && options.history_dir.is_none() {
+        return Err(AppError::usage("--keep, --keep-for and --prune-dry-run require --history-dir".to_string()))
+    }
+    if options.notify_url.is_some() && options.watch.is_none() && options.command != Command::Serve && schedule_specs.is_empty() {
+        return Err(AppError::usage("--notify-url requires --watch, --schedule or serve".to_string()))
+    }
+    if (options.command == Command::Listen) != options.queue_url.is_some() {
+        return Err(AppError::usage("listen requires --queue-url, which only applies to listen".to_string()))
+    }
+    if options.command == Command::Listen && (options.watch.is_some() || !schedule_specs.is_empty() || options.report.is_some() || options.expected.is_some()) {
+        return Err(AppError::usage("listen can't be combined with --watch, --schedule, --report or --expect".to_string()))
+    }
+    if options.shard_size.is_some() && (!options.group_by.is_empty() || options.report.is_some() || options.expected.is_some() || options.command != Command::Scan) {
+        return Err(AppError::usage("--shard-size only applies to the plain instance list, not --group-by, --report, --expect or other commands".to_string()))
+    }
+    if options.estimate_calls && (options.watch.is_some() || !schedule_specs.is_empty() || options.report.is_some() || options.expected.is_some() || options.shard_size.is_some() || options.command != Command::Scan) {
+        return Err(AppError::usage("--estimate-calls only applies to a one-off scan, not --watch, --schedule, --report, --expect, --shard-size or other commands".to_string()))
+    }
+    if options.command == Command::History && options.sqlite.is_none() {
+        return Err(AppError::usage("history requires --sqlite <database>".to_string()))
+    }
+    if options.since.is_some() && options.history_query != Some(HistoryQuery::Count) {
+        return Err(AppError::usage("--since only applies to history count".to_string()))
+    }
+    if options.command == Command::History && (options.watch.is_some() || options.report.is_some() || options.expected.is_some()) {
+        return Err(AppError::usage("history can't be combined with --watch, --report or --expect".to_string()))
+    }
+    if options.output_dir.is_some() && options.shard_size.is_some() {
+        return Err(AppError::usage("--output-dir can't be combined with --shard-size".to_string()))
+    }
+    if options.incremental && options.watch.is_none() && schedule_specs.is_empty() {
+        return Err(AppError::usage("--incremental requires --watch or --schedule".to_string()))
+    }
+    if options.incremental && (options.with_type_specs || options.with_ri_coverage) {
+        return Err(AppError::usage("--incremental can't be combined with --with-type-specs or --with-ri-coverage, which are worked out over a whole scan".to_string()))
+    }
+    if options.assert_readonly && (options.emit_cloudwatch || options.cloudwatch_logs_group.is_some() || options.command == Command::Listen) {
+        return Err(AppError::usage("--assert-readonly can't be combined with --emit-cloudwatch, --cloudwatch-logs-group or listen, which write to AWS".to_string()))
+    }
+    if options.omit_null && options.format != Format::Json && options.command != Command::Serve {
+        return Err(AppError::usage("--omit-null only applies to JSON output".to_string()))
+    }
+    if options.redact_omit && options.redact.is_empty() {
+        return Err(AppError::usage("--redact-omit requires --redact".to_string()))
+    }
+    if options.timezone != Tz::UTC && schedule_specs.is_empty() {
+        return Err(AppError::usage("--timezone requires --schedule".to_string()))
+    }
+    if !schedule_specs.is_empty() && (options.command == Command::Diff || options.command == Command::History || options.command == Command::Search) {
+        return Err(AppError::usage("--schedule can't be used with diff, history or search".to_string()))
+    }
+    if !schedule_specs.is_empty() && (options.watch.is_some() || options.exit_state || options.no_clobber || options.deadline.is_some()) {
+        return Err(AppError::usage("--schedule can't be combined with --watch, --exit-state, --no-clobber or --deadline".to_string()))
+    }
+    if options.notify_min_severity != Severity::State && options.notify_url.is_none() {
+        return Err(AppError::usage("--notify-min-severity requires --notify-url".to_string()))
+    }
+    if options.sqlite_mode == SqliteMode::Replace && options.sqlite.is_none() {
+        return Err(AppError::usage("--sqlite-mode requires --sqlite".to_string()))
+    }
+    if options.state_file.is_some() && !options.only_changed && !options.incremental {
+        return Err(AppError::usage("--state-file requires --only-changed or --incremental".to_string()))
+    }
+    if options.namespace.is_some() && !options.emit_cloudwatch {
+        return Err(AppError::usage("--namespace requires --emit-cloudwatch".to_string()))
+    }
+    if options.external_id.is_some() && options.assume_role.is_none() && !options.org && options.accounts.is_empty() {
+        return Err(AppError::usage("--external-id requires --assume-role, --accounts-file or --org".to_string()))
+    }
+    if !options.region_profiles.is_empty() && (options.org || !options.accounts.is_empty()) {
+        return Err(AppError::usage("--region-profiles can't be combined with --org or --accounts-file".to_string()))
+    }
+    if !options.accounts.is_empty() && options.org {
+        return Err(AppError::usage("--accounts-file and --org can't be combined".to_string()))
+    }
+    if !options.role_chain.is_empty() && options.assume_role.is_none() && !options.org && options.accounts.is_empty() {
+        return Err(AppError::usage("--role-chain requires --assume-role, --accounts-file or --org".to_string()))
+    }
+    if options.mfa_serial.is_some() && options.assume_role.is_none() {
+        return Err(AppError::usage("--mfa-serial requires --assume-role".to_string()))
+    }
+    if options.mfa_token.is_some() && options.mfa_serial.is_none() {
+        return Err(AppError::usage("--mfa-token requires --mfa-serial".to_string()))
+    }
+    if options.exit_state && options.instance_ids.as_ref().map_or(true, |ids| ids.len() != 1) {
+        return Err(AppError::usage("--exit-state requires exactly one --instance-id".to_string()))
+    }
+    if options.expected.is_some() && (options.report.is_some() || options.command == Command::Summarize) {
+        return Err(AppError::usage("--expect can't be combined with --report or summarize".to_string()))
+    }
+    if !options.group_by.is_empty() && options.report.is_some() {
+        return Err(AppError::usage("--group-by doesn't apply to --report output".to_string()))
+    }
+    if !options.group_by.is_empty() && options.command != Command::Summarize && options.format != Format::Json && options.format != Format::Html {
+        return Err(AppError::usage("--group-by nests the output, so it needs --format json or html".to_string()))
+    }
+    if options.command == Command::Serve && (options.watch.is_some() || options.report.is_some() || options.expected.is_some()) {
+        return Err(AppError::usage("serve can't be combined with --watch, --report or --expect".to_string()))
+    }
+    if options.watch.is_some() && (options.exit_state || options.no_clobber || options.deadline.is_some()) {
+        return Err(AppError::usage("--watch can't be combined with --exit-state, --no-clobber or --deadline".to_string()))
+    }
+    if options.envelope && options.format != Format::Json {
+        return Err(AppError::usage("--envelope only applies to --format json".to_string()))
+    }
+    if options.name_regex_invert && options.name_regex.is_none() {
+        return Err(AppError::usage("--name-regex-invert requires --name-regex".to_string()))
+    }
+    if options.report == Some(Report::Compliance) && options.require_tags.is_empty() {
+        return Err(AppError::usage("--report compliance requires --require-tags".to_string()))
+    }
+    if options.report == Some(Report::CostSummary) && options.prices.is_none() {
+        return Err(AppError::usage("--report cost-summary requires --prices".to_string()))
+    }
+    if options.report == Some(Report::KeyAudit) && options.deprecated_keys.is_empty() {
+        return Err(AppError::usage("--report key-audit requires --deprecated-keys".to_string()))
+    }
+    if options.report == Some(Report::TagPolicy) && options.allowed_tags.is_empty() {
+        return Err(AppError::usage("--report tag-policy requires --allowed-tags".to_string()))
+    }
+    if options.report == Some(Report::TagReport) && options.tag_key.is_none() {
+        return Err(AppError::usage("--report tag-report requires --tag-key".to_string()))
+    }
+    if !nested {
+        for spec in schedule_specs.iter() {
+            options.schedules.push(parse_schedule(full, region_index, spec)?);
+        }
+    }
+    if options.command == Command::Serve && (options.schedules.len() > 1 || options.schedules.iter().any(|s| s.target.is_some())) {
+        return Err(AppError::usage("serve takes a single --schedule without a target".to_string()))
+    }
+    Ok(options)
+}
+
+/// `cron` alone reruns the command line's own scan. `cron | region flags...`
+/// runs that region instead, with the flags added to the command line's,
+/// so one process can refresh a region hourly and everything daily.
+/// Five-field expressions are run at second zero.
+fn parse_schedule(full: &[String], region_index: usize, spec: &str) -> Result<Schedule, AppError> {
+    let (expression, target) = match spec.split_once('|') {
+        Some((expression, target)) => (expression.trim(), target.trim()),
+        None => (spec.trim(), "")
+    };
+    let normalized = match expression.split_whitespace().count() {
+        5 => format!("0 {}", expression),
+        _ => expression.to_string()
+    };
+    let times = match cron::Schedule::from_str(&normalized) {
+        Ok(times) => times,
+        Err(why) => return Err(AppError::usage(format!("invalid --schedule '{}': {}", expression, why)))
+    };
+    let tokens: Vec<&str> = target.split_whitespace().collect();
+    let target = match tokens.split_first() {
+        None => None,
+        Some((region, flags)) => {
+            if *region != "all" && !identity::is_region_name(region) {
+                return Err(AppError::usage(format!("--schedule '{}' targets '{}', which is not a region", expression, region)))
+            }
+            if flags.contains(&"--schedule") {
+                return Err(AppError::usage(format!("--schedule '{}' can't contain another --schedule", expression)))
+            }
+            let mut args = full.to_vec();
+            args[region_index] = region.to_string();
+            args.extend(flags.iter().map(|f| f.to_string()));
+            Some(Box::new(parse(&args, true)?))
+        }
+    };
+    Ok(Schedule { expression: expression.to_string(), target: target, times: times })
+}
+
+/// The account id field of an ARN, when it is a 12 digit number.
+pub fn account_id(arn: &str) -> Option<&str> {
+    let account = arn.split(':').nth(4)?;
+    match account.len() == 12 && account.chars().all(|c: char| c.is_ascii_digit()) {
+        true => Some(account),
+        false => None
+    }
+}
+
+/// The values EC2 reports for `InstanceState.name`.
+pub const INSTANCE_STATES: [&str; 6] = ["pending", "running", "shutting-down", "terminated", "stopping", "stopped"];
+
+fn add_states(states: &mut Vec<String>, value: &str) -> Result<(), AppError> {
+    for state in split_list(value) {
+        if !INSTANCE_STATES.contains(&state.as_str()) {
+            return Err(AppError::usage(format!("invalid --state '{}', expected one of: {}", state, INSTANCE_STATES.join(", "))))
+        }
+        if !states.contains(&state) {
+            states.push(state);
+        }
+    }
+    Ok(())
+}
+
+/// Filters that CI pipelines can set through the environment, each read in
+/// the same syntax as the flag it stands for.
+const ENV_FILTERS: [(&str, &str); 5] = [
+    ("EC2_MONITOR_NEWER_THAN", "--newer-than"),
+    ("EC2_MONITOR_OLDER_THAN", "--older-than"),
+    ("EC2_MONITOR_REQUIRE_TAGS", "--require-tags"),
+    ("EC2_MONITOR_STATE", "--state"),
+    ("EC2_MONITOR_TYPE", "--type")
+];
+
+/// Fills the `ENV_FILTERS` filters from the environment. A flag on the
+/// command line takes precedence over its variable, which takes precedence
+/// over the default; empty variables are ignored.
+fn apply_env_filters(options: &mut Options) -> Result<(), AppError> {
+    for (var, flag) in ENV_FILTERS.iter() {
+        let value = match std::env::var(var) {
+            Ok(v) if !v.trim().is_empty() => v,
+            _ => continue
+        };
+        if options.arguments.iter().any(|a| a == flag) {
+            continue;
+        }
+        match *flag {
+            "--newer-than" => options.newer_than = Some(parse_duration(var, &value)?),
+            "--older-than" => options.older_than = Some(parse_duration(var, &value)?),
+            "--require-tags" => options.require_tags = split_list(&value),
+            "--state" => add_states(&mut options.states, &value)?,
+            _ => options.types = split_list(&value)
+        }
+    }
+    Ok(())
+}
+
+/// Ids separated by commas or whitespace, lowercased and checked against
+/// `i-[0-9a-f]+` so a typo is caught here rather than as an API error.
+fn add_instance_ids(ids: &mut Vec<String>, flag: &str, value: &str) -> Result<(), AppError> {
+    for id in value.split(|c: char| c == ',' || c.is_whitespace()).filter(|id| !id.is_empty()) {
+        let id = id.to_lowercase();
+        match id.strip_prefix("i-") {
+            Some(hex) if !hex.is_empty() && hex.chars().all(|c: char| c.is_ascii_hexdigit()) => (),
+            _ => return Err(AppError::usage(format!("invalid instance id '{}' for {}, expected i- followed by hex digits", id, flag)))
+        }
+        if !ids.contains(&id) {
+            ids.push(id);
+        }
+    }
+    Ok(())
+}
+
+fn parse_role(flag: &str, role: &str) -> Result<String, AppError> {
+    match account_id(role).is_some() && role.contains(":role/") {
+        true => Ok(role.to_string()),
+        false => Err(AppError::usage(format!("invalid {} '{}', expected arn:aws:iam::<account-id>:role/<name>", flag, role)))
+    }
+}
+
+/// `arn[=external-id],...`, the roles to pass through in order.
+fn parse_role_chain(flag: &str, value: &str) -> Result<Vec<RoleHop>, AppError> {
+    let mut hops = Vec::new();
+    for entry in split_list(value) {
+        let hop = match entry.split_once('=') {
+            Some((role, external_id)) => RoleHop { external_id: Some(external_id.trim().to_string()), role_arn: parse_role(flag, role.trim())? },
+            None => RoleHop { external_id: None, role_arn: parse_role(flag, &entry)? }
+        };
+        hops.push(hop);
+    }
+    Ok(hops)
+}
+
+fn parse_positive(flag: &str, value: &str) -> Result<u32, AppError> {
+    match value.parse::<u32>() {
+        Ok(n) if n > 0 => Ok(n),
+        Ok(_) => Err(AppError::usage(format!("invalid value for {}: must be at least 1", flag))),
+        Err(why) => Err(AppError::usage(format!("invalid value for {}: {}", flag, why)))
+    }
+}
+
+/// Durations such as `180d` or `24h`, in humantime syntax.
+fn parse_duration(flag: &str, value: &str) -> Result<Duration, AppError> {
+    match humantime::parse_duration(value) {
+        Ok(d) => Ok(d),
+        Err(why) => Err(AppError::usage(format!("invalid duration for {} '{}': {}", flag, value, why)))
+    }
+}
+
+/// Grouping keys are output fields or `tags.<Key>`.
+fn parse_group_keys(value: &str) -> Result<Vec<String>, AppError> {
+    let valid = fields::names();
+    let keys = split_list(value);
+    match keys.iter().find(|k| !k.starts_with("tags.") && !valid.contains(k)) {
+        Some(unknown) => Err(AppError::usage(format!("unknown key '{}' in --by, expected tags.<Key> or any of: {}", unknown, valid.join(", ")))),
+        None => Ok(keys)
+    }
+}
+
+fn parse_group_by(value: &str) -> Result<Vec<GroupKey>, AppError> {
+    let mut keys = Vec::new();
+    for key in split_list(value) {
+        let key = match key.as_str() {
+            "account" => GroupKey::Account,
+            "region" => GroupKey::Region,
+            _ => return Err(AppError::usage(format!("unknown --group-by '{}', expected account or region", key)))
+        };
+        if !keys.contains(&key) {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+/// `field[:asc|:desc]`, comma separated, most significant first.
+fn parse_sort_keys(value: &str) -> Result<Vec<SortKey>, AppError> {
+    let mut keys = Vec::new();
+    for entry in split_list(value) {
+        let (field, direction) = match entry.rfind(':') {
+            Some(i) if entry[i + 1..] == *"asc" || entry[i + 1..] == *"desc" => (&entry[..i], &entry[i + 1..]),
+            _ => (entry.as_str(), "asc")
+        };
+        if !fields::is_known(field) {
+            return Err(AppError::usage(format!("unknown field '{}' in --sort-by, expected tags.<Key> or any of: {}, {}", field, fields::names().join(", "), fields::DERIVED.join(", "))))
+        }
+        keys.push(SortKey { field: field.to_string(), descending: direction == "desc" });
+    }
+    Ok(keys)
+}
+
+fn parse_fields(value: &str) -> Result<Vec<String>, AppError> {
+    let valid = fields::names();
+    let selected = split_list(value);
+    match selected.iter().find(|f| !valid.contains(f)) {
+        Some(unknown) => Err(AppError::usage(format!("unknown field '{}' in --fields, expected any of: {}", unknown, valid.join(", ")))),
+        None => Ok(selected)
+    }
+}
+
+fn parse_format(value: &str) -> Result<Format, AppError> {
+    match value {
+        "csv" => Ok(Format::Csv),
+        "html" => Ok(Format::Html),
+        "json" => Ok(Format::Json),
+        "table" => Ok(Format::Table),
+        "tsv" => Ok(Format::Tsv),
+        _ => Err(AppError::usage(format!("unknown --format '{}', expected one of: json, csv, tsv, table, html", value)))
+    }
+}
+
+fn parse_report(value: &str) -> Result<Report, AppError> {
+    match value {
+        "cleanup" => Ok(Report::Cleanup),
+        "compliance" => Ok(Report::Compliance),
+        "cost-summary" => Ok(Report::CostSummary),
+        "duplicates" => Ok(Report::Duplicates),
+        "key-audit" => Ok(Report::KeyAudit),
+        "orphans" => Ok(Report::Orphans),
+        "platform-summary" => Ok(Report::PlatformSummary),
+        "project-env-matrix" => Ok(Report::ProjectEnvMatrix),
+        "public-exposure" => Ok(Report::PublicExposure),
+        "rightsize" => Ok(Report::Rightsize),
+        "source-dest" => Ok(Report::SourceDest),
+        "tag-density" => Ok(Report::TagDensity),
+        "tag-policy" => Ok(Report::TagPolicy),
+        "tag-report" => Ok(Report::TagReport),
+        _ => Err(AppError::usage(format!("unknown --report '{}', expected one of: cleanup, compliance, cost-summary, duplicates, key-audit, orphans, platform-summary, project-env-matrix, public-exposure, rightsize, source-dest, tag-density, tag-policy, tag-report", value)))
+    }
+}
+
+/// Splits a comma separated flag value, dropping empty entries.
+fn split_list(value: &str) -> Vec<String> {
+    value.split(',')
+        .map(|v| v.trim())
+        .filter(|v| !v.is_empty())
+        .map(|v| v.to_string())
+        .collect()
+}
+
+/// Finds `--error-format` ahead of the full parse so that errors from the
+/// parse itself are reported in the requested format.
+pub fn error_format(args: &[String]) -> ErrorFormat {
+    match args.iter().position(|a| a == "--error-format") {
+        Some(i) => args.get(i + 1)
+            .and_then(|f| parse_error_format(f).ok())
+            .unwrap_or(ErrorFormat::Text),
+        None => ErrorFormat::Text
+    }
+}
+
+fn parse_error_format(value: &str) -> Result<ErrorFormat, AppError> {
+    match value {
+        "json" => Ok(ErrorFormat::Json),
+        "text" => Ok(ErrorFormat::Text),
+        _ => Err(AppError::usage(format!("invalid --error-format '{}', expected text or json", value)))
+    }
+}
+
+/// Parses `name=value` in EC2 filter syntax. Repeating a name adds another
+/// accepted value to the same filter rather than a second filter.
+fn add_aws_filter(filters: &mut Vec<Filter>, flag: &str, arg: &str) -> Result<(), AppError> {
+    let (name, value) = split_pair(flag, arg)?;
+    match filters.iter_mut().find(|f| f.name.as_deref() == Some(name)) {
+        Some(f) => f.values.get_or_insert_with(Vec::new).push(value.to_string()),
+        None => filters.push(Filter {
+            name: Some(name.to_string()),
+            values: Some(vec![value.to_string()])
+        })
+    }
+    Ok(())
+}
+
+fn add_tag_filter(filters: &mut Vec<TagFilter>, flag: &str, arg: &str) -> Result<(), AppError> {
+    let (key, value) = split_pair(flag, arg)?;
+    match filters.iter_mut().find(|f| f.key == key) {
+        Some(f) => f.values.push(value.to_string()),
+        None => filters.push(TagFilter {
+            key: key.to_string(),
+            values: vec![value.to_string()]
+        })
+    }
+    Ok(())
+}
+
+fn split_pair<'a>(flag: &str, arg: &'a str) -> Result<(&'a str, &'a str), AppError> {
+    match arg.find('=') {
+        Some(i) if i > 0 && i < arg.len() - 1 => Ok((&arg[..i], &arg[i + 1..])),
+        _ => Err(AppError::usage(format!("invalid {} '{}', expected name=value", flag, arg)))
+    }
+}
+
+/// Rejects a flag belonging to an optional integration when the binary was
+/// built without the cargo feature that provides it, e.g.
+/// `require_feature(flag, "sqlite", cfg!(feature = "sqlite"))?`.
+fn require_feature(flag: &str, feature: &str, enabled: bool) -> Result<(), AppError> {
+    match enabled {
+        true => Ok(()),
+        false => Err(AppError::usage(format!("{} is unavailable: built without {} support (rebuild with --features {})", flag, feature, feature)))
+    }
+}
+
+fn flag_value<'a>(flag: &str, value: Option<&'a String>) -> Result<&'a str, AppError> {
+    match value {
+        Some(v) => Ok(v),
+        None => Err(AppError::usage(format!("{} requires a value", flag)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+
+    #[test]
+    fn instance_ids_split_on_commas_and_whitespace() {
+        let mut ids = Vec::new();
+        add_instance_ids(&mut ids, "--instance-ids", "i-0abc123, I-0DEF456\ni-1").unwrap();
+        add_instance_ids(&mut ids, "--instance-ids", "i-0abc123,,i-0fff").unwrap();
+        assert_eq!(ids, vec!["i-0abc123", "i-0def456", "i-1", "i-0fff"]);
+    }
+
+    #[test]
+    fn malformed_instance_ids_are_rejected() {
+        for bad in ["0abc123", "i-", "i-0xyz", "vol-0abc", "i-0abc,ami-1"].iter() {
+            let err = add_instance_ids(&mut Vec::new(), "--instance-ids", bad).unwrap_err();
+            assert_eq!(err.kind, ErrorKind::Usage, "{}", bad);
+            assert!(err.message.ends_with("for --instance-ids, expected i- followed by hex digits"), "{}", err.message);
+        }
+    }
+}
@@ -0,0 +1,108 @@
+use crate::credentials::{self, CredentialContext};
+use crate::error::{AppError, ErrorKind};
+use crate::identity;
+use crate::options::Options;
+use rusoto_core::Region;
+use rusoto_organizations::{
+    Account, ListAccountsForParentRequest, ListAccountsRequest, ListOrganizationalUnitsForParentRequest, Organizations,
+    OrganizationsClient
+};
+use std::collections::BTreeSet;
+
+/// One context per active member account in scope, assumed into with
+/// `--org-role` from the management account's credentials. Accounts are
+/// all of the organization, or those under `--org-ous` (recursively),
+/// narrowed by `--org-accounts` and `--org-exclude-accounts`.
+pub async fn contexts(options: &Options) -> Result<Vec<CredentialContext>, AppError> {
+    let management = match credentials::contexts(options).into_iter().next() {
+        Some(ctx) => ctx,
+        None => return Err(AppError::usage("--org needs working management account credentials".to_string()))
+    };
+    let client = OrganizationsClient::new_with_client(management.client.clone(), Region::UsEast1);
+    let accounts = match options.org_ous.is_empty() {
+        true => list_accounts(&client).await?,
+        false => {
+            let mut accounts = Vec::new();
+            for ou in options.org_ous.iter() {
+                accounts.extend(accounts_under(&client, ou).await?);
+            }
+            accounts
+        }
+    };
+    let partition = identity::caller_identity(&management).await
+        .and_then(|i| i.arn)
+        .and_then(|arn| identity::arn_partition(&arn))
+        .unwrap_or_else(|| "aws".to_string());
+    let hops = options.role_chain.len() + options.assume_role.iter().count();
+    let mut seen = BTreeSet::new();
+    let mut contexts = Vec::new();
+    for account in accounts.into_iter() {
+        let id = match account.id {
+            Some(id) => id,
+            None => continue
+        };
+        if account.status.as_deref() != Some("ACTIVE") || !seen.insert(id.clone()) {
+            continue;
+        }
+        if (!options.org_accounts.is_empty() && !options.org_accounts.contains(&id)) || options.org_exclude_accounts.contains(&id) {
+            continue;
+        }
+        let role_arn = format!("arn:{}:iam::{}:role/{}", partition, id, options.org_role);
+        let ctx = management.assume_role(&role_arn, options.external_id.clone(), hops, options)?;
+        contexts.push(CredentialContext { account_id: Some(id), account_name: account.name, ..ctx });
+    }
+    Ok(contexts)
+}
+
+async fn list_accounts(client: &OrganizationsClient) -> Result<Vec<Account>, AppError> {
+    let mut request = ListAccountsRequest::default();
+    let mut accounts = Vec::new();
+    loop {
+        let page = match client.list_accounts(request.clone()).await {
+            Ok(p) => p,
+            Err(why) => return Err(org_error(format!("couldn't list organization accounts: {}", why)))
+        };
+        accounts.extend(page.accounts.unwrap_or_default());
+        match page.next_token {
+            Some(token) => request.next_token = Some(token),
+            None => return Ok(accounts)
+        }
+    }
+}
+
+/// Accounts directly in `parent` and in every OU below it.
+async fn accounts_under(client: &OrganizationsClient, parent: &str) -> Result<Vec<Account>, AppError> {
+    let mut accounts = Vec::new();
+    let mut parents = vec![parent.to_string()];
+    while let Some(parent) = parents.pop() {
+        let mut request = ListAccountsForParentRequest { parent_id: parent.clone(), ..Default::default() };
+        loop {
+            let page = match client.list_accounts_for_parent(request.clone()).await {
+                Ok(p) => p,
+                Err(why) => return Err(org_error(format!("couldn't list accounts in {}: {}", parent, why)))
+            };
+            accounts.extend(page.accounts.unwrap_or_default());
+            match page.next_token {
+                Some(token) => request.next_token = Some(token),
+                None => break
+            }
+        }
+        let mut request = ListOrganizationalUnitsForParentRequest { parent_id: parent.clone(), ..Default::default() };
+        loop {
+            let page = match client.list_organizational_units_for_parent(request.clone()).await {
+                Ok(p) => p,
+                Err(why) => return Err(org_error(format!("couldn't list organizational units in {}: {}", parent, why)))
+            };
+            parents.extend(page.organizational_units.unwrap_or_default().into_iter().filter_map(|ou| ou.id));
+            match page.next_token {
+                Some(token) => request.next_token = Some(token),
+                None => break
+            }
+        }
+    }
+    Ok(accounts)
+}
+
+fn org_error(message: String) -> AppError {
+    AppError::new(ErrorKind::Aws, message)
+}
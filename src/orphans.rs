@@ -0,0 +1,199 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError, ErrorFormat};
+use crate::{aws_error, identity, retry};
+use rusoto_core::RusotoError;
+use rusoto_ec2::{
+    DescribeAddressesRequest, DescribeImagesRequest, DescribeNetworkInterfacesError, DescribeNetworkInterfacesRequest,
+    DescribeSnapshotsError, DescribeSnapshotsRequest, DescribeVolumesError, DescribeVolumesRequest, Ec2, Ec2Client,
+    NetworkInterface, Snapshot, Tag, Volume
+};
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::future::Future;
+
+/// Approximate us-east-1 list prices in USD, used only for estimates.
+const EIP_MONTHLY: f64 = 3.65;
+const SNAPSHOT_GB_MONTH: f64 = 0.05;
+
+/// A resource that costs money or clutters the account without being used
+/// by anything, found in one region.
+#[derive(Serialize, Debug, Clone)]
+pub struct Orphan {
+    pub resource: &'static str,
+    pub id: Option<String>,
+    pub region: String,
+    pub detail: String,
+    pub created: Option<String>,
+    pub estimated_monthly_cost: Option<f64>,
+    pub tags: BTreeMap<String, String>
+}
+
+/// Collects unattached volumes, unassociated Elastic IPs, snapshots whose
+/// volume no longer exists, detached network interfaces and owned AMIs that
+/// no instance in `images_in_use` was launched from. A failed call is
+/// reported and that resource type skipped.
+pub async fn collect(ctx: &CredentialContext, region: &str, images_in_use: &BTreeSet<String>, error_format: ErrorFormat) -> Vec<Orphan> {
+    let client = Ec2Client::new_with_client(ctx.client_for(region), identity::ec2_region(region));
+    let mut orphans = Vec::new();
+
+    let volumes = or_report(region, "volumes", volumes(&client, region).await, error_format).unwrap_or_default();
+    let volume_ids: BTreeSet<String> = volumes.iter().filter_map(|v| v.volume_id.clone()).collect();
+    for v in volumes.into_iter().filter(|v| v.state.as_deref() == Some("available")) {
+        let volume_type = v.volume_type.clone().unwrap_or_default();
+        let size = v.size.unwrap_or(0);
+        orphans.push(Orphan {
+            resource: "volume",
+            id: v.volume_id,
+            region: region.to_string(),
+            detail: format!("{} {} GiB unattached", volume_type, size),
+            created: v.create_time,
+            estimated_monthly_cost: volume_gb_month(&volume_type).map(|p| round(p * size as f64)),
+            tags: tags(v.tags)
+        });
+    }
+
+    let addresses = or_report(region, "addresses", with_retry(region, DescribeAddressesRequest::default(), &client, |c, r| async move { c.describe_addresses(r).await }).await, error_format);
+    for a in addresses.and_then(|r| r.addresses).unwrap_or_default().into_iter().filter(|a| a.association_id.is_none()) {
+        orphans.push(Orphan {
+            resource: "address",
+            id: a.allocation_id,
+            region: region.to_string(),
+            detail: format!("{} unassociated", a.public_ip.unwrap_or_default()),
+            created: None,
+            estimated_monthly_cost: Some(EIP_MONTHLY),
+            tags: tags(a.tags)
+        });
+    }
+
+    let snapshots = or_report(region, "snapshots", snapshots(&client, region).await, error_format).unwrap_or_default();
+    for s in snapshots.into_iter().filter(|s| s.volume_id.as_ref().map_or(true, |v| !volume_ids.contains(v))) {
+        let size = s.volume_size.unwrap_or(0);
+        orphans.push(Orphan {
+            resource: "snapshot",
+            id: s.snapshot_id,
+            region: region.to_string(),
+            detail: format!("{} GiB from deleted volume {}", size, s.volume_id.unwrap_or_default()),
+            created: s.start_time,
+            // Snapshots are incremental, so the volume size is an upper bound.
+            estimated_monthly_cost: Some(round(SNAPSHOT_GB_MONTH * size as f64)),
+            tags: tags(s.tags)
+        });
+    }
+
+    let interfaces = or_report(region, "network interfaces", network_interfaces(&client, region).await, error_format).unwrap_or_default();
+    for n in interfaces.into_iter().filter(|n| n.status.as_deref() == Some("available")) {
+        orphans.push(Orphan {
+            resource: "network_interface",
+            id: n.network_interface_id,
+            region: region.to_string(),
+            detail: format!("detached in {}", n.subnet_id.unwrap_or_default()),
+            created: None,
+            estimated_monthly_cost: Some(0.0),
+            tags: tags(n.tag_set)
+        });
+    }
+
+    let request = DescribeImagesRequest { owners: Some(vec!["self".to_string()]), ..Default::default() };
+    let images = or_report(region, "images", with_retry(region, request, &client, |c, r| async move { c.describe_images(r).await }).await, error_format);
+    for i in images.and_then(|r| r.images).unwrap_or_default().into_iter() {
+        if i.image_id.as_ref().map_or(false, |id| images_in_use.contains(id)) {
+            continue;
+        }
+        orphans.push(Orphan {
+            resource: "image",
+            id: i.image_id,
+            region: region.to_string(),
+            detail: format!("{} not used by any instance", i.name.unwrap_or_default()),
+            created: i.creation_date,
+            // Charged through its snapshots, which are listed separately
+            // only once the image is deregistered.
+            estimated_monthly_cost: None,
+            tags: tags(i.tags)
+        });
+    }
+    orphans
+}
+
+async fn volumes(client: &Ec2Client, region: &str) -> Result<Vec<Volume>, RusotoError<DescribeVolumesError>> {
+    let mut request = DescribeVolumesRequest::default();
+    let mut all = Vec::new();
+    loop {
+        let page = with_retry(region, request.clone(), client, |c, r| async move { c.describe_volumes(r).await }).await?;
+        all.extend(page.volumes.unwrap_or_default());
+        match page.next_token {
+            Some(token) => request.next_token = Some(token),
+            None => return Ok(all)
+        }
+    }
+}
+
+async fn snapshots(client: &Ec2Client, region: &str) -> Result<Vec<Snapshot>, RusotoError<DescribeSnapshotsError>> {
+    let mut request = DescribeSnapshotsRequest { owner_ids: Some(vec!["self".to_string()]), ..Default::default() };
+    let mut all = Vec::new();
+    loop {
+        let page = with_retry(region, request.clone(), client, |c, r| async move { c.describe_snapshots(r).await }).await?;
+        all.extend(page.snapshots.unwrap_or_default());
+        match page.next_token {
+            Some(token) => request.next_token = Some(token),
+            None => return Ok(all)
+        }
+    }
+}
+
+async fn network_interfaces(client: &Ec2Client, region: &str) -> Result<Vec<NetworkInterface>, RusotoError<DescribeNetworkInterfacesError>> {
+    let mut request = DescribeNetworkInterfacesRequest::default();
+    let mut all = Vec::new();
+    loop {
+        let page = with_retry(region, request.clone(), client, |c, r| async move { c.describe_network_interfaces(r).await }).await?;
+        all.extend(page.network_interfaces.unwrap_or_default());
+        match page.next_token {
+            Some(token) => request.next_token = Some(token),
+            None => return Ok(all)
+        }
+    }
+}
+
+async fn with_retry<R, T, E, F, Fut>(region: &str, request: R, client: &Ec2Client, call: F) -> Result<T, RusotoError<E>>
+where
+    R: retry::ReadOnlyRequest,
+    E: Error + 'static,
+    F: Fn(Ec2Client, R) -> Fut,
+    Fut: Future<Output = Result<T, RusotoError<E>>>
+{
+    retry::with_retry(region, request, |req| call(client.clone(), req)).await
+}
+
+fn or_report<T, E: Error + 'static>(region: &str, what: &str, result: Result<T, RusotoError<E>>, error_format: ErrorFormat) -> Option<T> {
+    match result {
+        Ok(value) => Some(value),
+        Err(why) => {
+            let failure = AppError::aws(region, format!("failed to describe {}: {}", what, aws_error::describe(&why)));
+            error::report(&failure.with_request_id(aws_error::request_id(&why)), error_format);
+            None
+        }
+    }
+}
+
+fn volume_gb_month(volume_type: &str) -> Option<f64> {
+    match volume_type {
+        "gp2" => Some(0.10),
+        "gp3" => Some(0.08),
+        "io1" | "io2" => Some(0.125),
+        "st1" => Some(0.045),
+        "sc1" => Some(0.015),
+        "standard" => Some(0.05),
+        _ => None
+    }
+}
+
+fn round(cost: f64) -> f64 {
+    (cost * 100.0).round() / 100.0
+}
+
+fn tags(tags: Option<Vec<Tag>>) -> BTreeMap<String, String> {
+    tags.into_iter()
+        .flatten()
+        .filter_map(|t| Some((t.key?, t.value.unwrap_or_default())))
+        .collect()
+}
@@ -0,0 +1,244 @@
+use serde_json::{Map, Value};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    Html,
+    Json,
+    Table,
+    Tsv
+}
+
+impl Format {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Format::Csv => "csv",
+            Format::Html => "html",
+            Format::Json => "json",
+            Format::Table => "txt",
+            Format::Tsv => "tsv"
+        }
+    }
+}
+
+/// Renders the serialized output. Every format works from the same JSON
+/// value so the field set is identical whichever is chosen.
+pub fn render(format: Format, output: &Value) -> String {
+    match format {
+        Format::Csv => render_delimited(output, ',', csv_field),
+        Format::Html => render_html(output),
+        Format::Json => serde_json::to_string(output).unwrap_or_default(),
+        Format::Table => render_table(output),
+        Format::Tsv => render_delimited(output, '\t', tsv_field)
+    }
+}
+
+/// The rows of a tabular rendering; anything other than an array of records
+/// is treated as a single row.
+fn rows(output: &Value) -> &[Value] {
+    match output {
+        Value::Array(rows) => rows,
+        other => std::slice::from_ref(other)
+    }
+}
+
+fn render_delimited(output: &Value, delimiter: char, field: fn(&str) -> String) -> String {
+    let rows = rows(output);
+    let columns = columns(rows);
+    let mut text = columns.iter().map(|c| field(c)).collect::<Vec<String>>().join(&delimiter.to_string());
+    text.push('\n');
+    for row in rows.iter() {
+        let line = columns.iter()
+            .map(|c| field(&cell(row.get(c))))
+            .collect::<Vec<String>>()
+            .join(&delimiter.to_string());
+        text.push_str(&line);
+        text.push('\n');
+    }
+    text
+}
+
+/// Quotes per RFC 4180 when the value contains a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    match value.contains(|c: char| c == ',' || c == '"' || c == '\n' || c == '\r') {
+        true => format!("\"{}\"", value.replace('"', "\"\"")),
+        false => value.to_string()
+    }
+}
+
+/// TSV has no quoting, so tabs and line breaks become spaces.
+fn tsv_field(value: &str) -> String {
+    value.replace(|c: char| c == '\t' || c == '\n' || c == '\r', " ")
+}
+
+/// Space-aligned columns for reading in a terminal.
+fn render_table(output: &Value) -> String {
+    let rows = rows(output);
+    let columns = columns(rows);
+    let cells: Vec<Vec<String>> = rows.iter()
+        .map(|row| columns.iter().map(|c| tsv_field(&cell(row.get(c)))).collect())
+        .collect();
+    let widths: Vec<usize> = columns.iter().enumerate()
+        .map(|(i, c)| cells.iter().map(|r| r[i].chars().count()).chain(std::iter::once(c.len())).max().unwrap_or(0))
+        .collect();
+    let line = |values: Vec<&str>| -> String {
+        values.iter().zip(widths.iter())
+            .map(|(v, w)| format!("{:width$}", v, width = *w))
+            .collect::<Vec<String>>()
+            .join("  ")
+            .trim_end()
+            .to_string()
+    };
+    let mut text = line(columns.iter().map(|c| c.as_str()).collect());
+    text.push('\n');
+    for row in cells.iter() {
+        text.push_str(&line(row.iter().map(|c| c.as_str()).collect()));
+        text.push('\n');
+    }
+    text
+}
+
+const HTML_STYLE: &str = "body{font-family:sans-serif;margin:2em}\
+table{border-collapse:collapse;font-size:13px}\
+th,td{border:1px solid #ccc;padding:4px 8px;text-align:left;vertical-align:top}\
+th{background:#f0f0f0;cursor:pointer;user-select:none}\
+tr:nth-child(even){background:#fafafa}";
+
+/// Clicking a header sorts by that column, toggling direction on repeat.
+const HTML_SCRIPT: &str = "document.querySelectorAll('th').forEach(function(th){\
+th.addEventListener('click',function(){\
+var table=th.closest('table'),body=table.tBodies[0],i=th.cellIndex,asc=th.dataset.asc!=='true';\
+var rows=Array.prototype.slice.call(body.rows);\
+rows.sort(function(a,b){var x=a.cells[i].textContent,y=b.cells[i].textContent;\
+return (asc?1:-1)*x.localeCompare(y,undefined,{numeric:true});});\
+rows.forEach(function(r){body.appendChild(r);});\
+th.dataset.asc=asc;});});";
+
+/// A self-contained page: arrays of records become tables, objects of
+/// arrays (e.g. grouped by region) become a titled table per key, with a
+/// sub-heading per level when groups are nested.
+fn render_html(output: &Value) -> String {
+    let mut body = String::new();
+    match output {
+        Value::Array(rows) => body.push_str(&html_table(rows)),
+        Value::Object(groups) if is_grouped(output) => body.push_str(&html_groups(groups, 2)),
+        other => body.push_str(&format!("<pre>{}</pre>\n", escape(&serde_json::to_string_pretty(other).unwrap_or_default())))
+    }
+    format!("<!DOCTYPE html>\n<html>\n<head>\n<meta charset=\"utf-8\">\n<title>EC2 instances</title>\n<style>{}</style>\n</head>\n<body>\n{}<script>{}</script>\n</body>\n</html>\n",
+        HTML_STYLE, body, HTML_SCRIPT)
+}
+
+/// Arrays of records, or objects of those nested any number of levels deep,
+/// as written by `--group-by`.
+fn is_grouped(value: &Value) -> bool {
+    match value {
+        Value::Array(_) => true,
+        Value::Object(groups) => groups.values().all(is_grouped),
+        _ => false
+    }
+}
+
+/// A heading per group, one level deeper for each level of nesting.
+fn html_groups(groups: &Map<String, Value>, level: usize) -> String {
+    let mut html = String::new();
+    for (name, members) in groups.iter() {
+        html.push_str(&format!("<h{level}>{}</h{level}>\n", escape(name), level = level.min(6)));
+        match members {
+            Value::Object(inner) => html.push_str(&html_groups(inner, level + 1)),
+            rows => html.push_str(&html_table(rows.as_array().map(|r| r.as_slice()).unwrap_or(&[])))
+        }
+    }
+    html
+}
+
+fn html_table(rows: &[Value]) -> String {
+    let columns = columns(rows);
+    let mut html = String::from("<table>\n<thead><tr>");
+    for c in columns.iter() {
+        html.push_str(&format!("<th>{}</th>", escape(c)));
+    }
+    html.push_str("</tr></thead>\n<tbody>\n");
+    for row in rows.iter() {
+        html.push_str("<tr>");
+        for c in columns.iter() {
+            html.push_str(&format!("<td>{}</td>", escape(&cell(row.get(c)))));
+        }
+        html.push_str("</tr>\n");
+    }
+    html.push_str("</tbody>\n</table>\n");
+    html
+}
+
+/// Column names in first-seen order across all rows.
+fn columns(rows: &[Value]) -> Vec<String> {
+    let mut columns: Vec<String> = Vec::new();
+    for row in rows.iter() {
+        if let Value::Object(fields) = row {
+            for key in fields.keys() {
+                if !columns.contains(key) {
+                    columns.push(key.clone());
+                }
+            }
+        }
+    }
+    columns
+}
+
+/// Flattens a field to display text; maps such as `tags` become `k=v; k=v`.
+fn cell(value: Option<&Value>) -> String {
+    match value {
+        None | Some(Value::Null) => String::new(),
+        Some(Value::String(s)) => s.clone(),
+        Some(Value::Object(map)) => map.iter()
+            .map(|(k, v)| format!("{}={}", k, cell(Some(v))))
+            .collect::<Vec<String>>()
+            .join("; "),
+        Some(Value::Array(items)) => items.iter()
+            .map(|v| cell(Some(v)))
+            .collect::<Vec<String>>()
+            .join(", "),
+        Some(other) => other.to_string()
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn csv_field_leaves_plain_values_alone() {
+        assert_eq!(csv_field("i-0abc"), "i-0abc");
+    }
+
+    #[test]
+    fn csv_field_quotes_commas_quotes_and_newlines() {
+        assert_eq!(csv_field("web, blue"), "\"web, blue\"");
+        assert_eq!(csv_field("the \"main\" box"), "\"the \"\"main\"\" box\"");
+        assert_eq!(csv_field("line one\nline two"), "\"line one\nline two\"");
+        assert_eq!(csv_field("cr\rhere"), "\"cr\rhere\"");
+    }
+
+    #[test]
+    fn csv_keeps_a_tag_with_a_comma_and_quote_in_one_field() {
+        let rows = json!([{ "instance_id": "i-1", "name": "web, \"blue\"" }]);
+        assert_eq!(render(Format::Csv, &rows), "instance_id,name\ni-1,\"web, \"\"blue\"\"\"\n");
+    }
+
+    #[test]
+    fn table_pads_to_the_widest_cell_and_trims_empty_trailing_cells() {
+        let rows = json!([
+            { "current_type": "m5.2xlarge", "suggested_type": "m5.xlarge", "note": null },
+            { "current_type": "t3.micro", "suggested_type": null, "note": "burstable" }
+        ]);
+        let expected = format!("current_type  suggested_type  note\nm5.2xlarge    m5.xlarge\nt3.micro{}burstable\n", " ".repeat(22));
+        assert_eq!(render(Format::Table, &rows), expected);
+    }
+}
@@ -0,0 +1,30 @@
+use crate::error::AppError;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::path::Path;
+
+/// AWS bills a month as 730 hours for on-demand estimates.
+pub const HOURS_PER_MONTH: f64 = 730.0;
+
+/// On-demand hourly prices by instance type, in the account's currency.
+pub type PriceList = HashMap<String, f64>;
+
+/// Reads a `--prices` file: a JSON object of instance type to hourly price,
+/// e.g. `{"m5.large": 0.096, "t3.micro": 0.0104}`.
+pub fn load(path: &Path) -> Result<PriceList, AppError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    let object = match serde_json::from_str(&text) {
+        Ok(Value::Object(o)) => o,
+        Ok(_) => return Err(AppError::usage(format!("{} is not an object of instance type to hourly price", path.display()))),
+        Err(why) => return Err(AppError::usage(format!("couldn't parse {}: {}", path.display(), why)))
+    };
+    object.into_iter()
+        .map(|(instance_type, price)| match price.as_f64() {
+            Some(p) => Ok((instance_type, p)),
+            None => Err(AppError::usage(format!("{}: price for {} is not a number", path.display(), instance_type)))
+        })
+        .collect()
+}
@@ -0,0 +1,67 @@
+use crate::Details;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::time::Duration;
+
+/// Everything `/metrics` reports about one completed collection.
+pub struct Snapshot<'a> {
+    pub details: &'a [Details],
+    pub duration: Duration,
+    /// When the last collection in which any region succeeded finished.
+    pub last_success: Option<DateTime<Utc>>,
+    /// Failed region scans since the server started, by region.
+    pub region_errors: &'a BTreeMap<String, u64>
+}
+
+/// The Prometheus text exposition of a collection. The whole page is built
+/// at once, so a scrape sees one collection or the next, never a mix.
+pub fn render(snapshot: &Snapshot) -> String {
+    let mut counts: BTreeMap<[String; 4], usize> = BTreeMap::new();
+    for d in snapshot.details.iter() {
+        let labels = [
+            d.region.clone(),
+            d.state.clone().unwrap_or_default(),
+            d.instance_type.clone().unwrap_or_default(),
+            d.environment.clone().unwrap_or_default()
+        ];
+        *counts.entry(labels).or_insert(0) += 1;
+    }
+    let mut text = String::new();
+    let _ = writeln!(text, "# HELP ec2_inventory_instances Instances in the last collection.");
+    let _ = writeln!(text, "# TYPE ec2_inventory_instances gauge");
+    for ([region, state, instance_type, environment], count) in counts.iter() {
+        let _ = writeln!(text, "ec2_inventory_instances{{region=\"{}\",state=\"{}\",instance_type=\"{}\",environment=\"{}\"}} {}",
+            label(region), label(state), label(instance_type), label(environment), count);
+    }
+    let _ = writeln!(text, "# HELP ec2_inventory_collection_duration_seconds How long the last collection took.");
+    let _ = writeln!(text, "# TYPE ec2_inventory_collection_duration_seconds gauge");
+    let _ = writeln!(text, "ec2_inventory_collection_duration_seconds {}", snapshot.duration.as_secs_f64());
+    if let Some(last_success) = snapshot.last_success {
+        let _ = writeln!(text, "# HELP ec2_inventory_last_success_timestamp_seconds When the last successful collection finished.");
+        let _ = writeln!(text, "# TYPE ec2_inventory_last_success_timestamp_seconds gauge");
+        let _ = writeln!(text, "ec2_inventory_last_success_timestamp_seconds {}", last_success.timestamp());
+    }
+    let _ = writeln!(text, "# HELP ec2_inventory_region_errors_total Region scans that failed.");
+    let _ = writeln!(text, "# TYPE ec2_inventory_region_errors_total counter");
+    for (region, errors) in snapshot.region_errors.iter() {
+        let _ = writeln!(text, "ec2_inventory_region_errors_total{{region=\"{}\"}} {}", label(region), errors);
+    }
+    text
+}
+
+/// Escapes a label value as the exposition format requires. Tag values can
+/// hold anything, so other control characters become spaces.
+fn label(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '\\' => escaped.push_str("\\\\"),
+            '"' => escaped.push_str("\\\""),
+            '\n' => escaped.push_str("\\n"),
+            c if c.is_control() => escaped.push(' '),
+            c => escaped.push(c)
+        }
+    }
+    escaped
+}
@@ -0,0 +1,328 @@
+use crate::fields;
+use crate::Details;
+use chrono::{DateTime, Utc};
+use serde_json::{Map, Value};
+use std::cmp::Ordering;
+use std::fmt;
+
+/// A parsed `--query` predicate, e.g.
+/// `state == 'running' && tags.Environment != 'prod' && age_days > 90`.
+///
+/// Identifiers name any output field, `tags.<Key>` for a single tag, or the
+/// derived `age_days`. Literals are quoted strings, numbers, `true`, `false`
+/// and `null`. Comparisons are `== != < <= > >=`, combined with `&&`, `||`,
+/// `!` and parentheses. A bare identifier is true when its value is truthy.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Expr {
+    And(Box<Expr>, Box<Expr>),
+    Compare(Operand, Op, Operand),
+    Not(Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Truthy(Operand)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Field(String),
+    Literal(Value)
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    Eq,
+    Ge,
+    Gt,
+    Le,
+    Lt,
+    Ne
+}
+
+#[derive(Debug, PartialEq)]
+pub struct QueryError {
+    /// Character offset into the expression where the problem was found.
+    pub position: usize,
+    pub message: String
+}
+
+impl fmt::Display for QueryError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at position {}", self.message, self.position)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    And,
+    Close,
+    Ident(String),
+    Literal(Value),
+    Not,
+    Op(Op),
+    Open,
+    Or
+}
+
+pub fn parse(input: &str) -> Result<Expr, QueryError> {
+    let tokens = tokenize(input)?;
+    let mut parser = Parser { tokens: tokens, pos: 0, end: input.chars().count() };
+    let expr = parser.or()?;
+    match parser.tokens.get(parser.pos) {
+        Some((_, at)) => Err(QueryError { position: *at, message: "unexpected trailing input".to_string() }),
+        None => Ok(expr)
+    }
+}
+
+/// Evaluates the expression against a single instance. `now` is the run
+/// timestamp that `age_days` is measured from.
+pub fn matches(expr: &Expr, details: &Details, now: DateTime<Utc>) -> bool {
+    eval(expr, &fields::record(details, now))
+}
+
+fn eval(expr: &Expr, record: &Map<String, Value>) -> bool {
+    match expr {
+        Expr::And(a, b) => eval(a, record) && eval(b, record),
+        Expr::Or(a, b) => eval(a, record) || eval(b, record),
+        Expr::Not(e) => !eval(e, record),
+        Expr::Truthy(operand) => truthy(&resolve(operand, record)),
+        Expr::Compare(left, op, right) => compare(&resolve(left, record), *op, &resolve(right, record))
+    }
+}
+
+fn resolve(operand: &Operand, record: &Map<String, Value>) -> Value {
+    match operand {
+        Operand::Literal(v) => v.clone(),
+        Operand::Field(path) => fields::lookup(record, path)
+    }
+}
+
+fn truthy(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64().map_or(false, |n| n != 0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(a) => !a.is_empty(),
+        Value::Object(o) => !o.is_empty()
+    }
+}
+
+/// Numbers compare numerically and strings lexically. Ordering against null
+/// or between mismatched types is always false; equality is exact.
+fn compare(left: &Value, op: Op, right: &Value) -> bool {
+    let ordering = match (left, right) {
+        (Value::Number(a), Value::Number(b)) => a.as_f64().and_then(|a| b.as_f64().and_then(|b| a.partial_cmp(&b))),
+        (Value::String(a), Value::String(b)) => Some(a.cmp(b)),
+        (Value::Bool(a), Value::Bool(b)) => Some(a.cmp(b)),
+        (Value::Null, Value::Null) => Some(Ordering::Equal),
+        _ => None
+    };
+    match (op, ordering) {
+        (Op::Eq, o) => o == Some(Ordering::Equal),
+        (Op::Ne, o) => o != Some(Ordering::Equal),
+        (_, None) => false,
+        (_, Some(_)) if left.is_null() => false,
+        (Op::Lt, Some(o)) => o == Ordering::Less,
+        (Op::Le, Some(o)) => o != Ordering::Greater,
+        (Op::Gt, Some(o)) => o == Ordering::Greater,
+        (Op::Ge, Some(o)) => o != Ordering::Less
+    }
+}
+
+fn tokenize(input: &str) -> Result<Vec<(Token, usize)>, QueryError> {
+    let chars: Vec<char> = input.chars().collect();
+    let mut tokens = Vec::new();
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        let start = i;
+        let two: String = chars[i..chars.len().min(i + 2)].iter().collect();
+        let token = match (c, two.as_str()) {
+            (c, _) if c.is_whitespace() => {
+                i += 1;
+                continue;
+            },
+            (_, "&&") => { i += 2; Token::And },
+            (_, "||") => { i += 2; Token::Or },
+            (_, "==") => { i += 2; Token::Op(Op::Eq) },
+            (_, "!=") => { i += 2; Token::Op(Op::Ne) },
+            (_, "<=") => { i += 2; Token::Op(Op::Le) },
+            (_, ">=") => { i += 2; Token::Op(Op::Ge) },
+            ('<', _) => { i += 1; Token::Op(Op::Lt) },
+            ('>', _) => { i += 1; Token::Op(Op::Gt) },
+            ('!', _) => { i += 1; Token::Not },
+            ('(', _) => { i += 1; Token::Open },
+            (')', _) => { i += 1; Token::Close },
+            ('\'', _) | ('"', _) => {
+                let end = match chars[i + 1..].iter().position(|q| *q == c) {
+                    Some(offset) => i + 1 + offset,
+                    None => return Err(QueryError { position: start, message: "unterminated string".to_string() })
+                };
+                let text: String = chars[i + 1..end].iter().collect();
+                i = end + 1;
+                Token::Literal(Value::String(text))
+            },
+            (c, _) if c.is_ascii_digit() || (c == '-' && chars.get(i + 1).map_or(false, |n| n.is_ascii_digit())) => {
+                i += 1;
+                while i < chars.len() && (chars[i].is_ascii_digit() || chars[i] == '.') {
+                    i += 1;
+                }
+                let text: String = chars[start..i].iter().collect();
+                match text.parse::<f64>() {
+                    Ok(n) if n.fract() == 0.0 && !text.contains('.') => Token::Literal(Value::from(n as i64)),
+                    Ok(n) => Token::Literal(Value::from(n)),
+                    Err(_) => return Err(QueryError { position: start, message: format!("invalid number '{}'", text) })
+                }
+            },
+            (c, _) if c.is_alphabetic() || c == '_' => {
+                while i < chars.len() && (chars[i].is_alphanumeric() || "_.:-".contains(chars[i])) {
+                    i += 1;
+                }
+                let word: String = chars[start..i].iter().collect();
+                match word.as_str() {
+                    "true" => Token::Literal(Value::Bool(true)),
+                    "false" => Token::Literal(Value::Bool(false)),
+                    "null" => Token::Literal(Value::Null),
+                    _ => Token::Ident(word)
+                }
+            },
+            (c, _) => return Err(QueryError { position: start, message: format!("unexpected character '{}'", c) })
+        };
+        tokens.push((token, start));
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<(Token, usize)>,
+    pos: usize,
+    end: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos).map(|(t, _)| t)
+    }
+
+    fn position(&self) -> usize {
+        self.tokens.get(self.pos).map_or(self.end, |(_, at)| *at)
+    }
+
+    fn error(&self, message: &str) -> QueryError {
+        QueryError { position: self.position(), message: message.to_string() }
+    }
+
+    fn or(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.pos += 1;
+            left = Expr::Or(Box::new(left), Box::new(self.and()?));
+        }
+        Ok(left)
+    }
+
+    fn and(&mut self) -> Result<Expr, QueryError> {
+        let mut left = self.unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.pos += 1;
+            left = Expr::And(Box::new(left), Box::new(self.unary()?));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Expr, QueryError> {
+        match self.peek() {
+            Some(Token::Not) => {
+                self.pos += 1;
+                Ok(Expr::Not(Box::new(self.unary()?)))
+            },
+            Some(Token::Open) => {
+                self.pos += 1;
+                let inner = self.or()?;
+                match self.peek() {
+                    Some(Token::Close) => {
+                        self.pos += 1;
+                        Ok(inner)
+                    },
+                    _ => Err(self.error("expected ')'"))
+                }
+            },
+            _ => self.comparison()
+        }
+    }
+
+    fn comparison(&mut self) -> Result<Expr, QueryError> {
+        let left = self.operand()?;
+        match self.peek().cloned() {
+            Some(Token::Op(op)) => {
+                self.pos += 1;
+                Ok(Expr::Compare(left, op, self.operand()?))
+            },
+            _ => Ok(Expr::Truthy(left))
+        }
+    }
+
+    fn operand(&mut self) -> Result<Operand, QueryError> {
+        let operand = match self.peek() {
+            Some(Token::Ident(name)) => Operand::Field(name.clone()),
+            Some(Token::Literal(value)) => Operand::Literal(value.clone()),
+            Some(_) => return Err(self.error("expected a field name or value")),
+            None => return Err(self.error("unexpected end of expression"))
+        };
+        self.pos += 1;
+        Ok(operand)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Map<String, Value> {
+        match json!({"age_days": 120, "name": "web 'blue'", "state": "running", "tags": {"Environment": "staging"}}) {
+            Value::Object(record) => record,
+            _ => unreachable!()
+        }
+    }
+
+    fn holds(query: &str) -> bool {
+        eval(&parse(query).unwrap(), &record())
+    }
+
+    #[test]
+    fn and_binds_tighter_than_or() {
+        let field = |name: &str| Operand::Field(name.to_string());
+        let expected = Expr::Or(
+            Box::new(Expr::Truthy(field("a"))),
+            Box::new(Expr::And(Box::new(Expr::Truthy(field("b"))), Box::new(Expr::Truthy(field("c")))))
+        );
+        assert_eq!(parse("a || b && c"), Ok(expected));
+        assert!(holds("state == 'running' || state == 'stopped' && age_days < 1"));
+        assert!(!holds("(state == 'running' || state == 'stopped') && age_days < 1"));
+        assert!(holds("!(age_days < 90) && tags.Environment != 'prod'"));
+    }
+
+    #[test]
+    fn strings_take_either_quote() {
+        assert!(holds("state == \"running\""));
+        assert!(holds("name == \"web 'blue'\""));
+        assert_eq!(parse("state == 'running"), Err(QueryError { position: 9, message: "unterminated string".to_string() }));
+    }
+
+    #[test]
+    fn unknown_fields_are_null() {
+        assert!(holds("missing == null"));
+        assert!(holds("tags.Owner == null"));
+        assert!(!holds("missing"));
+        assert!(!holds("missing > 0"));
+        assert!(!holds("missing < 0"));
+    }
+
+    #[test]
+    fn malformed_queries_are_rejected() {
+        assert_eq!(parse("state =="), Err(QueryError { position: 8, message: "unexpected end of expression".to_string() }));
+        assert_eq!(parse("(state"), Err(QueryError { position: 6, message: "expected ')'".to_string() }));
+        assert_eq!(parse("state running").unwrap_err().message, "unexpected trailing input");
+        assert_eq!(parse("state = 'x'").unwrap_err().message, "unexpected character '='");
+    }
+}
@@ -0,0 +1,74 @@
+use crate::credentials::CredentialContext;
+use crate::error::{AppError, ErrorKind};
+use crate::identity;
+use rusoto_core::Region;
+use rusoto_iam::{Iam, IamClient, SimulatePrincipalPolicyRequest};
+
+/// EC2 actions that change instances. `--assert-readonly` refuses to scan
+/// with credentials allowed any of them.
+const WRITE_ACTIONS: [&str; 6] = [
+    "ec2:ModifyInstanceAttribute",
+    "ec2:RebootInstances",
+    "ec2:RunInstances",
+    "ec2:StartInstances",
+    "ec2:StopInstances",
+    "ec2:TerminateInstances"
+];
+
+/// `--assert-readonly`: checks with IAM's policy simulator that the
+/// identity behind `ctx` can't change instances, before anything is
+/// scanned. Fails closed: when the simulation can't be run, for instance
+/// because the identity may not call `iam:SimulatePrincipalPolicy`, the
+/// scan doesn't go ahead either.
+///
+/// The simulation covers identity policies, not SCPs or resource policies,
+/// so it can report a denied action as allowed but not the reverse. The
+/// tool itself only ever sends EC2 describe requests; `retry::with_retry`
+/// accepts nothing else. Writes elsewhere happen only when asked for, with
+/// `--emit-cloudwatch`, `--cloudwatch-logs-group` or `listen`, and options
+/// rejects those alongside this flag.
+pub async fn verify(ctx: &CredentialContext) -> Result<(), AppError> {
+    let failed = |why: String| AppError::new(ErrorKind::Aws, format!("--assert-readonly couldn't check the credentials: {}", why));
+    let arn = match identity::caller_identity(ctx).await.and_then(|i| i.arn) {
+        Some(arn) => arn,
+        None => return Err(failed("GetCallerIdentity failed".to_string()))
+    };
+    let principal = principal_arn(&arn).ok_or_else(|| failed(format!("{} can't be simulated; root and federated users have no IAM policies to check", arn)))?;
+    let region = match arn.split(':').nth(1) {
+        Some("aws-cn") => Region::CnNorth1,
+        Some("aws-us-gov") => Region::UsGovWest1,
+        _ => Region::UsEast1
+    };
+    let client = IamClient::new_with_client(ctx.client.clone(), region);
+    let request = SimulatePrincipalPolicyRequest {
+        action_names: WRITE_ACTIONS.iter().map(|a| a.to_string()).collect(),
+        policy_source_arn: principal.clone(),
+        ..Default::default()
+    };
+    let result = client.simulate_principal_policy(request).await.map_err(|why| failed(why.to_string()))?;
+    let allowed: Vec<String> = result.evaluation_results.unwrap_or_default().into_iter()
+        .filter(|r| r.eval_decision == "allowed")
+        .map(|r| r.eval_action_name)
+        .collect();
+    match allowed.is_empty() {
+        true => Ok(()),
+        false => Err(AppError::new(ErrorKind::Aws, format!("--assert-readonly: {} is allowed {}", principal, allowed.join(", "))))
+    }
+}
+
+/// The IAM user or role to simulate for a caller ARN. An assumed-role
+/// session acts with its role's policies; the role's path isn't in the
+/// session ARN, so roles with a path can't be resolved this way.
+fn principal_arn(arn: &str) -> Option<String> {
+    let parts: Vec<&str> = arn.splitn(6, ':').collect();
+    if parts.len() != 6 {
+        return None
+    }
+    let (partition, account, resource) = (parts[1], parts[4], parts[5]);
+    match resource.split('/').collect::<Vec<&str>>().as_slice() {
+        ["user", ..] if parts[2] == "iam" => Some(arn.to_string()),
+        ["role", ..] if parts[2] == "iam" => Some(arn.to_string()),
+        ["assumed-role", role, _session] => Some(format!("arn:{}:iam::{}:role/{}", partition, account, role)),
+        _ => None
+    }
+}
@@ -0,0 +1,66 @@
+use super::ReportOutput;
+use crate::derived::launched_at;
+use crate::options::Options;
+use crate::Details;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use regex::Regex;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Candidate {
+    instance_id: Option<String>,
+    instance_type: Option<String>,
+    name: Option<String>,
+    owner: Option<String>,
+    project: Option<String>,
+    region: String,
+    stopped_days: i64,
+    stopped_since: String,
+    /// Whether `stopped_since` came from the state transition reason or is
+    /// the launch time standing in for it.
+    stopped_since_source: &'static str
+}
+
+/// Stopped instances, outside Auto Scaling groups, that have been stopped
+/// for longer than `--stopped-for` and are only accruing EBS charges.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let mut candidates: Vec<Candidate> = details.iter()
+        .filter(|d| d.state.as_deref() == Some("stopped") && d.autoscaling_group.is_none())
+        .filter_map(|d| {
+            let (since, source) = match stopped_at(d) {
+                Some(t) => (t, "state_transition_reason"),
+                None => (launched_at(d)?, "launch_time")
+            };
+            let stopped_for = options.started.signed_duration_since(since);
+            match stopped_for.to_std().map_or(false, |s| s > options.stopped_for) {
+                true => Some(Candidate {
+                    instance_id: d.instance_id.clone(),
+                    instance_type: d.instance_type.clone(),
+                    name: d.name.clone(),
+                    owner: d.tags.get("Owner").cloned(),
+                    project: d.project.clone(),
+                    region: d.region.clone(),
+                    stopped_days: stopped_for.num_days(),
+                    stopped_since: since.to_rfc3339(),
+                    stopped_since_source: source
+                }),
+                false => None
+            }
+        })
+        .collect();
+    candidates.sort_by(|a, b| b.stopped_days.cmp(&a.stopped_days));
+    ReportOutput {
+        findings: candidates.len(),
+        gate: false,
+        body: serde_json::to_value(candidates).unwrap_or_default()
+    }
+}
+
+/// EC2 records when an instance was stopped in the transition reason, e.g.
+/// `User initiated (2021-03-01 10:12:45 GMT)`.
+fn stopped_at(details: &Details) -> Option<DateTime<Utc>> {
+    let reason = details.state_transition_reason.as_deref()?;
+    let re = Regex::new(r"\((\d{4}-\d{2}-\d{2} \d{2}:\d{2}:\d{2}) GMT\)").unwrap();
+    let stamp = re.captures(reason)?.get(1)?.as_str();
+    NaiveDateTime::parse_from_str(stamp, "%Y-%m-%d %H:%M:%S").ok().map(|t| DateTime::from_utc(t, Utc))
+}
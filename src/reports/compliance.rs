@@ -0,0 +1,47 @@
+use super::ReportOutput;
+use crate::filters::glob_match;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Violation {
+    instance_id: Option<String>,
+    missing_tags: Vec<String>,
+    name: Option<String>
+}
+
+/// Instances lacking any of `--require-tags`, or carrying one with an empty
+/// value, grouped by region. Names matching `--exempt-names` are skipped.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let mut by_region: BTreeMap<String, Vec<Violation>> = BTreeMap::new();
+    let mut findings = 0;
+    for d in details.iter().filter(|d| !is_exempt(options, d)) {
+        let missing: Vec<String> = options.require_tags.iter()
+            .filter(|t| d.tags.get(*t).map_or(true, |v| v.trim().is_empty()))
+            .cloned()
+            .collect();
+        if missing.is_empty() {
+            continue;
+        }
+        findings += 1;
+        by_region.entry(d.region.clone()).or_insert_with(Vec::new).push(Violation {
+            instance_id: d.instance_id.clone(),
+            missing_tags: missing,
+            name: d.name.clone()
+        });
+    }
+    ReportOutput {
+        body: serde_json::to_value(by_region).unwrap_or_default(),
+        findings: findings,
+        gate: true
+    }
+}
+
+fn is_exempt(options: &Options, details: &Details) -> bool {
+    match &details.name {
+        Some(name) => options.exempt_names.iter().any(|p| glob_match(p, name)),
+        None => false
+    }
+}
@@ -0,0 +1,98 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::pricing::HOURS_PER_MONTH;
+use crate::Details;
+use serde::Serialize;
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Project bucket for instances without a Project tag, kept separate since
+/// it is the figure that drives tagging compliance.
+const UNTAGGED: &str = "(untagged)";
+const NONE_BUCKET: &str = "(none)";
+
+#[derive(Serialize)]
+struct Row {
+    project: String,
+    environment: String,
+    region: String,
+    instances: usize,
+    running: usize,
+    estimated_monthly_cost: f64,
+    note: Option<String>
+}
+
+#[derive(Default)]
+struct Bucket {
+    instances: usize,
+    running: usize,
+    hourly: f64,
+    stopped: usize,
+    unpriced: BTreeSet<String>
+}
+
+/// Estimated monthly on-demand spend from the `--prices` list, by Project,
+/// then Environment and region. Stopped instances contribute nothing as
+/// volume data isn't collected.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let prices = options.prices.clone().unwrap_or_default();
+    let mut buckets: BTreeMap<(String, String, String), Bucket> = BTreeMap::new();
+    for d in details.iter() {
+        let key = (
+            label(&d.project, UNTAGGED),
+            label(&d.environment, NONE_BUCKET),
+            d.region.clone()
+        );
+        let bucket = buckets.entry(key).or_default();
+        bucket.instances += 1;
+        match (d.state.as_deref(), d.instance_type.as_deref()) {
+            (Some("running"), Some(t)) => {
+                bucket.running += 1;
+                match prices.get(t) {
+                    Some(p) => bucket.hourly += p,
+                    None => {
+                        bucket.unpriced.insert(t.to_string());
+                    }
+                }
+            },
+            (Some("running"), None) => bucket.running += 1,
+            _ => bucket.stopped += 1
+        }
+    }
+    let rows: Vec<Row> = buckets.into_iter()
+        .map(|((project, environment, region), b)| Row {
+            project: project,
+            environment: environment,
+            region: region,
+            instances: b.instances,
+            running: b.running,
+            estimated_monthly_cost: (b.hourly * HOURS_PER_MONTH * 100.0).round() / 100.0,
+            note: note(&b)
+        })
+        .collect();
+    ReportOutput {
+        findings: rows.iter().filter(|r| r.project == UNTAGGED).count(),
+        gate: false,
+        body: serde_json::to_value(rows).unwrap_or_default()
+    }
+}
+
+fn label(value: &Option<String>, empty: &str) -> String {
+    match value {
+        Some(v) if !v.is_empty() => v.clone(),
+        _ => empty.to_string()
+    }
+}
+
+fn note(bucket: &Bucket) -> Option<String> {
+    let mut notes = Vec::new();
+    if bucket.stopped > 0 {
+        notes.push(format!("{} not running, counted as 0 (no EBS volume data)", bucket.stopped));
+    }
+    if !bucket.unpriced.is_empty() {
+        notes.push(format!("no price for {}", bucket.unpriced.iter().cloned().collect::<Vec<String>>().join(", ")));
+    }
+    match notes.is_empty() {
+        true => None,
+        false => Some(notes.join("; "))
+    }
+}
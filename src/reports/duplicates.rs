@@ -0,0 +1,56 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Member {
+    instance_id: Option<String>,
+    name: Option<String>,
+    region: String,
+    state: Option<String>
+}
+
+/// Names used by more than one non-terminated instance, in any region.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let groups: BTreeMap<String, Vec<Member>> = duplicate_names(details, options.case_insensitive_names).into_iter()
+        .map(|(name, members)| {
+            let members = members.into_iter()
+                .map(|d| Member {
+                    instance_id: d.instance_id.clone(),
+                    name: d.name.clone(),
+                    region: d.region.clone(),
+                    state: d.state.clone()
+                })
+                .collect();
+            (name, members)
+        })
+        .collect();
+    ReportOutput {
+        findings: groups.len(),
+        gate: false,
+        body: serde_json::to_value(groups).unwrap_or_default()
+    }
+}
+
+/// Groups instances sharing a Name tag, keyed by the name (lowercased when
+/// `ignore_case`), keeping only names with more than one member. Unnamed and
+/// terminated instances are left out. Anything generating per-host config
+/// from names should use this to decide when a suffix is needed.
+pub fn duplicate_names(details: &[Details], ignore_case: bool) -> BTreeMap<String, Vec<&Details>> {
+    let mut by_name: BTreeMap<String, Vec<&Details>> = BTreeMap::new();
+    for d in details.iter().filter(|d| d.state.as_deref() != Some("terminated")) {
+        let name = match d.name.as_deref().map(str::trim) {
+            Some(n) if !n.is_empty() => n,
+            _ => continue
+        };
+        let key = match ignore_case {
+            true => name.to_lowercase(),
+            false => name.to_string()
+        };
+        by_name.entry(key).or_insert_with(Vec::new).push(d);
+    }
+    by_name.retain(|_, members| members.len() > 1);
+    by_name
+}
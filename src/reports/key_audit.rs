@@ -0,0 +1,55 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use serde_json::json;
+use std::collections::BTreeMap;
+
+/// Owner bucket for instances without an Owner tag.
+const NO_OWNER: &str = "(none)";
+
+#[derive(Serialize)]
+struct KeyUse {
+    instance_id: Option<String>,
+    key_name: Option<String>,
+    launch_time: Option<String>,
+    name: Option<String>,
+    state: Option<String>
+}
+
+/// Instances still launched with one of `--deprecated-keys`, grouped by
+/// region and then Owner tag, alongside those with no key pair at all.
+/// Only the deprecated matches count as findings.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let mut deprecated: BTreeMap<String, BTreeMap<String, Vec<KeyUse>>> = BTreeMap::new();
+    let mut no_key_pair = Vec::new();
+    let mut findings = 0;
+    for d in details.iter() {
+        match &d.key_name {
+            Some(key) if options.deprecated_keys.contains(key) => {
+                findings += 1;
+                let owner = d.tags.get("Owner").cloned().unwrap_or_else(|| NO_OWNER.to_string());
+                deprecated.entry(d.region.clone()).or_insert_with(BTreeMap::new)
+                    .entry(owner).or_insert_with(Vec::new)
+                    .push(key_use(d));
+            },
+            Some(_) => {},
+            None => no_key_pair.push(key_use(d))
+        }
+    }
+    ReportOutput {
+        body: json!({ "deprecated": deprecated, "no_key_pair": no_key_pair }),
+        findings: findings,
+        gate: true
+    }
+}
+
+fn key_use(details: &Details) -> KeyUse {
+    KeyUse {
+        instance_id: details.instance_id.clone(),
+        key_name: details.key_name.clone(),
+        launch_time: details.launch_time.clone(),
+        name: details.name.clone(),
+        state: details.state.clone()
+    }
+}
@@ -0,0 +1,49 @@
+mod cleanup;
+mod compliance;
+mod cost_summary;
+mod duplicates;
+mod key_audit;
+mod orphans;
+mod platform_summary;
+mod project_env_matrix;
+mod public_exposure;
+mod rightsize;
+mod source_dest;
+mod tag_density;
+mod tag_policy;
+mod tag_report;
+
+use crate::options::{Options, Report};
+use crate::orphans::Orphan;
+use crate::Details;
+use serde_json::Value;
+
+/// What a `--report` mode writes in place of the instance list. `findings`
+/// counts the entries it found; for `gate` reports, such as policy checks
+/// meant to run in CI, a non-zero count fails the run.
+pub struct ReportOutput {
+    pub body: Value,
+    pub findings: usize,
+    pub gate: bool
+}
+
+/// `orphans` holds the resources gathered during the scan for
+/// `--report orphans`; it is empty for every other report.
+pub fn render(report: Report, options: &Options, details: &[Details], orphans: &[Orphan]) -> ReportOutput {
+    match report {
+        Report::Cleanup => cleanup::render(options, details),
+        Report::Compliance => compliance::render(options, details),
+        Report::CostSummary => cost_summary::render(options, details),
+        Report::Duplicates => duplicates::render(options, details),
+        Report::KeyAudit => key_audit::render(options, details),
+        Report::Orphans => orphans::render(options, orphans),
+        Report::PlatformSummary => platform_summary::render(options, details),
+        Report::ProjectEnvMatrix => project_env_matrix::render(options, details),
+        Report::PublicExposure => public_exposure::render(options, details),
+        Report::Rightsize => rightsize::render(options, details),
+        Report::SourceDest => source_dest::render(options, details),
+        Report::TagDensity => tag_density::render(options, details),
+        Report::TagPolicy => tag_policy::render(options, details),
+        Report::TagReport => tag_report::render(options, details)
+    }
+}
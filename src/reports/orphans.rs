@@ -0,0 +1,58 @@
+use super::ReportOutput;
+use crate::filters::tags_match;
+use crate::options::Options;
+use crate::orphans::Orphan;
+use crate::output::Format;
+use serde::Serialize;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+#[derive(Serialize)]
+struct Total {
+    resource: String,
+    count: usize,
+    estimated_monthly_cost: f64
+}
+
+/// Every orphaned resource found in the scanned regions that passes the tag
+/// filters. JSON and HTML get a section per resource type plus a totals
+/// section; the tabular formats get one row per resource so they stay flat.
+pub fn render(options: &Options, orphans: &[Orphan]) -> ReportOutput {
+    let kept: Vec<&Orphan> = orphans.iter().filter(|o| tags_match(options, &o.tags)).collect();
+    let mut sections: BTreeMap<&str, Vec<&Orphan>> = BTreeMap::new();
+    for o in kept.iter() {
+        sections.entry(o.resource).or_insert_with(Vec::new).push(o);
+    }
+    let mut totals: Vec<Total> = sections.iter()
+        .map(|(resource, members)| Total {
+            resource: resource.to_string(),
+            count: members.len(),
+            estimated_monthly_cost: round(members.iter().filter_map(|o| o.estimated_monthly_cost).sum())
+        })
+        .collect();
+    totals.push(Total {
+        resource: "all".to_string(),
+        count: kept.len(),
+        estimated_monthly_cost: round(totals.iter().map(|t| t.estimated_monthly_cost).sum())
+    });
+    let body = match options.format {
+        Format::Json | Format::Html => {
+            let mut document = Map::new();
+            for (resource, members) in sections.iter() {
+                document.insert(resource.to_string(), serde_json::to_value(members).unwrap_or_default());
+            }
+            document.insert("totals".to_string(), serde_json::to_value(&totals).unwrap_or_default());
+            Value::Object(document)
+        },
+        _ => serde_json::to_value(&kept).unwrap_or_default()
+    };
+    ReportOutput {
+        findings: kept.len(),
+        gate: false,
+        body: body
+    }
+}
+
+fn round(cost: f64) -> f64 {
+    (cost * 100.0).round() / 100.0
+}
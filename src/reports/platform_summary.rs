@@ -0,0 +1,30 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Default)]
+struct Platforms {
+    linux: usize,
+    windows: usize
+}
+
+/// Windows and Linux instance counts per region, for licensing true-ups.
+/// Only instances EC2 reports as `windows` count as Windows; everything
+/// else is counted as Linux. Informational only.
+pub fn render(_options: &Options, details: &[Details]) -> ReportOutput {
+    let mut by_region: BTreeMap<String, Platforms> = BTreeMap::new();
+    for d in details.iter() {
+        let platforms = by_region.entry(d.region.clone()).or_insert_with(Platforms::default);
+        match d.platform.as_deref() {
+            Some("windows") => platforms.windows += 1,
+            _ => platforms.linux += 1
+        }
+    }
+    ReportOutput {
+        body: serde_json::to_value(by_region).unwrap_or_default(),
+        findings: 0,
+        gate: false
+    }
+}
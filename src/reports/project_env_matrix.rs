@@ -0,0 +1,34 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use std::collections::BTreeMap;
+
+/// Stands in for a missing or empty Project or Environment tag.
+const UNTAGGED: &str = "untagged";
+
+/// Instance counts keyed by `"project/environment"`, for cost allocation.
+/// Instances missing either tag count as findings.
+pub fn render(_options: &Options, details: &[Details]) -> ReportOutput {
+    let mut matrix: BTreeMap<String, usize> = BTreeMap::new();
+    let mut findings = 0;
+    for d in details.iter() {
+        let project = dimension(&d.project);
+        let environment = dimension(&d.environment);
+        if project == UNTAGGED || environment == UNTAGGED {
+            findings += 1;
+        }
+        *matrix.entry(format!("{}/{}", project, environment)).or_insert(0) += 1;
+    }
+    ReportOutput {
+        body: serde_json::to_value(matrix).unwrap_or_default(),
+        findings: findings,
+        gate: false
+    }
+}
+
+fn dimension(value: &Option<String>) -> &str {
+    match value.as_deref().map(str::trim) {
+        Some(v) if !v.is_empty() => v,
+        _ => UNTAGGED
+    }
+}
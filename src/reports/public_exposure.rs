@@ -0,0 +1,42 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Exposure {
+    instance_id: Option<String>,
+    name: Option<String>,
+    open_to_internet: Option<bool>,
+    /// Running and reachable from anywhere: the ones to look at first.
+    priority: bool,
+    public_ip_address: Option<String>,
+    region: String,
+    security_group_ids: Vec<String>,
+    state: Option<String>
+}
+
+/// Instances with a public IP, with their security groups and whether any
+/// of those groups admits traffic from anywhere. Running instances open to
+/// the internet come first and are the findings.
+pub fn render(_options: &Options, details: &[Details]) -> ReportOutput {
+    let mut exposed: Vec<Exposure> = details.iter()
+        .filter(|d| d.public_ip_address.is_some())
+        .map(|d| Exposure {
+            instance_id: d.instance_id.clone(),
+            name: d.name.clone(),
+            open_to_internet: d.open_to_internet,
+            priority: d.state.as_deref() == Some("running") && d.open_to_internet == Some(true),
+            public_ip_address: d.public_ip_address.clone(),
+            region: d.region.clone(),
+            security_group_ids: d.security_group_ids.clone(),
+            state: d.state.clone()
+        })
+        .collect();
+    exposed.sort_by_key(|e| !e.priority);
+    ReportOutput {
+        findings: exposed.iter().filter(|e| e.priority).count(),
+        gate: false,
+        body: serde_json::to_value(exposed).unwrap_or_default()
+    }
+}
@@ -0,0 +1,120 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+
+/// Sizes in ascending order for common families, used to suggest the next
+/// size down. Families not listed are reported without a suggestion.
+const FAMILY_SIZES: [(&str, &[&str]); 14] = [
+    ("c4", &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge"]),
+    ("c5", &["large", "xlarge", "2xlarge", "4xlarge", "9xlarge", "12xlarge", "18xlarge", "24xlarge"]),
+    ("c6g", &["medium", "large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge"]),
+    ("m4", &["large", "xlarge", "2xlarge", "4xlarge", "10xlarge", "16xlarge"]),
+    ("m5", &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge"]),
+    ("m5a", &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge"]),
+    ("m6g", &["medium", "large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge"]),
+    ("r4", &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "16xlarge"]),
+    ("r5", &["large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge", "24xlarge"]),
+    ("r6g", &["medium", "large", "xlarge", "2xlarge", "4xlarge", "8xlarge", "12xlarge", "16xlarge"]),
+    ("t2", &["nano", "micro", "small", "medium", "large", "xlarge", "2xlarge"]),
+    ("t3", &["nano", "micro", "small", "medium", "large", "xlarge", "2xlarge"]),
+    ("t3a", &["nano", "micro", "small", "medium", "large", "xlarge", "2xlarge"]),
+    ("t4g", &["nano", "micro", "small", "medium", "large", "xlarge", "2xlarge"])
+];
+
+#[derive(Serialize)]
+struct Suggestion {
+    cpu_p95: f64,
+    current_type: String,
+    /// Needs pricing data, which isn't collected, so it is always null.
+    estimated_monthly_saving: Option<f64>,
+    instance_id: Option<String>,
+    name: Option<String>,
+    note: Option<String>,
+    region: String,
+    suggested_type: Option<String>
+}
+
+/// Running instances whose p95 CPU over the window is under
+/// `--cpu-threshold`, with the next size down in the same family.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let suggestions: Vec<Suggestion> = details.iter()
+        .filter_map(|d| {
+            let cpu = d.cpu_p95?;
+            let current = d.instance_type.clone()?;
+            if cpu >= options.cpu_threshold {
+                return None;
+            }
+            let (suggested, note) = match step_down(&current) {
+                _ if current.starts_with('t') => (None, Some("burstable: low average CPU is expected under the CPU credit model, check credit balance before resizing".to_string())),
+                StepDown::Smaller(t) => (Some(t), None),
+                StepDown::Smallest => (None, Some("already the smallest size, consider stopping".to_string())),
+                StepDown::Unknown => (None, Some("no size table for this family".to_string()))
+            };
+            Some(Suggestion {
+                cpu_p95: cpu,
+                current_type: current,
+                estimated_monthly_saving: None,
+                instance_id: d.instance_id.clone(),
+                name: d.name.clone(),
+                note: note,
+                region: d.region.clone(),
+                suggested_type: suggested
+            })
+        })
+        .collect();
+    ReportOutput {
+        findings: suggestions.len(),
+        gate: false,
+        body: serde_json::to_value(suggestions).unwrap_or_default()
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum StepDown {
+    Smaller(String),
+    Smallest,
+    Unknown
+}
+
+fn step_down(instance_type: &str) -> StepDown {
+    let mut parts = instance_type.splitn(2, '.');
+    let (family, size) = match (parts.next(), parts.next()) {
+        (Some(f), Some(s)) => (f, s),
+        _ => return StepDown::Unknown
+    };
+    let sizes = match FAMILY_SIZES.iter().find(|(f, _)| *f == family) {
+        Some((_, sizes)) => sizes,
+        None => return StepDown::Unknown
+    };
+    match sizes.iter().position(|s| *s == size) {
+        Some(0) => StepDown::Smallest,
+        Some(i) => StepDown::Smaller(format!("{}.{}", family, sizes[i - 1])),
+        None => StepDown::Unknown
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn steps_down_one_size_in_the_family() {
+        assert_eq!(step_down("m5.2xlarge"), StepDown::Smaller("m5.xlarge".to_string()));
+        assert_eq!(step_down("c5.12xlarge"), StepDown::Smaller("c5.9xlarge".to_string()));
+        assert_eq!(step_down("t3.micro"), StepDown::Smaller("t3.nano".to_string()));
+    }
+
+    #[test]
+    fn smallest_size_has_nothing_below() {
+        assert_eq!(step_down("m5.large"), StepDown::Smallest);
+        assert_eq!(step_down("t4g.nano"), StepDown::Smallest);
+    }
+
+    #[test]
+    fn unlisted_families_and_sizes_are_unknown() {
+        assert_eq!(step_down("x2gd.large"), StepDown::Unknown);
+        assert_eq!(step_down("m5.metal"), StepDown::Unknown);
+        assert_eq!(step_down("m5"), StepDown::Unknown);
+    }
+}
@@ -0,0 +1,43 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Appliance {
+    instance_id: Option<String>,
+    name: Option<String>,
+    network_interface_ids: Vec<String>,
+    private_ip_addresses: Vec<String>,
+    region: String,
+    state: Option<String>,
+    /// A stopped NAT or router is likely a route table black hole.
+    stopped: bool,
+    subnet_id: Option<String>,
+    vpc_id: Option<String>
+}
+
+/// Instances with the source/dest check disabled, usually NAT instances,
+/// routers and other network appliances, with the routing context auditors
+/// ask for. Only the stopped ones count as findings.
+pub fn render(_options: &Options, details: &[Details]) -> ReportOutput {
+    let appliances: Vec<Appliance> = details.iter()
+        .filter(|d| d.source_dest_check == Some(false))
+        .map(|d| Appliance {
+            instance_id: d.instance_id.clone(),
+            name: d.name.clone(),
+            network_interface_ids: d.network_interface_ids.clone(),
+            private_ip_addresses: d.private_ip_addresses.clone(),
+            region: d.region.clone(),
+            state: d.state.clone(),
+            stopped: d.state.as_deref() == Some("stopped"),
+            subnet_id: d.subnet_id.clone(),
+            vpc_id: d.vpc_id.clone()
+        })
+        .collect();
+    ReportOutput {
+        findings: appliances.iter().filter(|a| a.stopped).count(),
+        gate: false,
+        body: serde_json::to_value(appliances).unwrap_or_default()
+    }
+}
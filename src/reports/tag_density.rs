@@ -0,0 +1,36 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+#[derive(Serialize, Default)]
+struct Density {
+    average_tags: f64,
+    instances: usize,
+    tags: i64,
+    untagged: usize
+}
+
+/// Average tags per instance in each region, counting AWS-managed tags, as
+/// a measure of how far tagging has been adopted. Informational only.
+pub fn render(_options: &Options, details: &[Details]) -> ReportOutput {
+    let mut by_region: BTreeMap<String, Density> = BTreeMap::new();
+    for d in details.iter() {
+        let count = d.tag_count.unwrap_or(0);
+        let density = by_region.entry(d.region.clone()).or_insert_with(Density::default);
+        density.instances += 1;
+        density.tags += count;
+        if count == 0 {
+            density.untagged += 1;
+        }
+    }
+    for density in by_region.values_mut() {
+        density.average_tags = density.tags as f64 / density.instances as f64;
+    }
+    ReportOutput {
+        body: serde_json::to_value(by_region).unwrap_or_default(),
+        findings: 0,
+        gate: false
+    }
+}
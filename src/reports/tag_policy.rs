@@ -0,0 +1,39 @@
+use super::ReportOutput;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+
+#[derive(Serialize)]
+struct Violation {
+    disallowed_tags: Vec<String>,
+    instance_id: Option<String>,
+    name: Option<String>,
+    region: String
+}
+
+/// Instances carrying tag keys outside `--allowed-tags`. Keys with the
+/// reserved `aws:` prefix are set by AWS itself and are never reported.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let violations: Vec<Violation> = details.iter()
+        .filter_map(|d| {
+            let disallowed: Vec<String> = d.tags.keys()
+                .filter(|k| !k.starts_with("aws:") && !options.allowed_tags.contains(k))
+                .cloned()
+                .collect();
+            match disallowed.is_empty() {
+                true => None,
+                false => Some(Violation {
+                    disallowed_tags: disallowed,
+                    instance_id: d.instance_id.clone(),
+                    name: d.name.clone(),
+                    region: d.region.clone()
+                })
+            }
+        })
+        .collect();
+    ReportOutput {
+        findings: violations.len(),
+        gate: true,
+        body: serde_json::to_value(violations).unwrap_or_default()
+    }
+}
@@ -0,0 +1,46 @@
+use super::ReportOutput;
+use crate::derived::normalize_tag_value;
+use crate::options::Options;
+use crate::Details;
+use serde::Serialize;
+use std::collections::BTreeMap;
+
+/// Normalized value for instances without the tag at all.
+const MISSING: &str = "(missing)";
+
+#[derive(Serialize)]
+struct ValueGroup {
+    normalized: String,
+    count: usize,
+    /// Each distinct spelling seen, with its instance count.
+    variants: BTreeMap<String, usize>,
+    /// More than one spelling normalizes to this value and should be fixed.
+    inconsistent: bool
+}
+
+/// Every distinct value of `--tag-key` grouped by its normalized form.
+/// Groups with more than one spelling count as findings.
+pub fn render(options: &Options, details: &[Details]) -> ReportOutput {
+    let key = options.tag_key.as_deref().unwrap_or_default();
+    let mut groups: BTreeMap<String, BTreeMap<String, usize>> = BTreeMap::new();
+    for d in details.iter() {
+        let (normalized, raw) = match d.tags.get(key) {
+            Some(value) => (normalize_tag_value(value, &options.tag_synonyms), value.clone()),
+            None => (MISSING.to_string(), MISSING.to_string())
+        };
+        *groups.entry(normalized).or_insert_with(BTreeMap::new).entry(raw).or_insert(0) += 1;
+    }
+    let rows: Vec<ValueGroup> = groups.into_iter()
+        .map(|(normalized, variants)| ValueGroup {
+            normalized: normalized,
+            count: variants.values().sum(),
+            inconsistent: variants.len() > 1,
+            variants: variants
+        })
+        .collect();
+    ReportOutput {
+        findings: rows.iter().filter(|r| r.inconsistent).count(),
+        gate: false,
+        body: serde_json::to_value(rows).unwrap_or_default()
+    }
+}
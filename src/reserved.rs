@@ -0,0 +1,114 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError, ErrorFormat};
+use crate::{aws_error, identity, retry, Details};
+use chrono::{DateTime, Utc};
+use rusoto_ec2::{DescribeReservedInstancesRequest, Ec2, Ec2Client, Filter, ReservedInstances};
+use std::collections::{BTreeSet, HashMap};
+
+/// `ri_covered` and `ri_days_remaining` by instance id.
+pub type Coverage = HashMap<String, (bool, Option<i64>)>;
+
+/// An active reservation and how many of its instances are still unclaimed.
+struct Reservation {
+    availability_zone: Option<String>,
+    end: Option<DateTime<Utc>>,
+    instance_type: Option<String>,
+    platform: Option<String>,
+    unclaimed: i64
+}
+
+/// Matches one context's running instances against its active reserved
+/// instances, region by region. This is an approximation: an instance is
+/// covered when a reservation of the same type and platform, in the same
+/// zone for zonal reservations, has capacity left. Size flexibility and
+/// convertible exchanges are ignored, so coverage can be understated.
+pub async fn coverage(ctx: &CredentialContext, details: &[Details], error_format: ErrorFormat) -> Coverage {
+    let mut coverage = Coverage::new();
+    let regions: BTreeSet<&str> = details.iter().map(|d| d.region.as_str()).collect();
+    for region in regions.into_iter() {
+        let mut reservations = match reservations(ctx, region).await {
+            Ok(r) => r,
+            Err(failure) => {
+                error::report(&failure, error_format);
+                continue;
+            }
+        };
+        // Zonal reservations only fit their own zone, so they get first
+        // pick and regional ones cover what is left.
+        reservations.sort_by_key(|r| (r.availability_zone.is_none(), r.end));
+        let running = details.iter()
+            .filter(|d| d.region == region && d.state.as_deref() == Some("running"));
+        for d in running {
+            let id = match &d.instance_id {
+                Some(id) => id.clone(),
+                None => continue
+            };
+            let matched = reservations.iter_mut().find(|r| r.unclaimed > 0 && fits(r, d));
+            let entry = match matched {
+                Some(r) => {
+                    r.unclaimed -= 1;
+                    (true, r.end.map(|end| end.signed_duration_since(Utc::now()).num_days()))
+                },
+                None => (false, None)
+            };
+            coverage.insert(id, entry);
+        }
+    }
+    coverage
+}
+
+/// Sets `ri_covered` and `ri_days_remaining` on the instances in `coverage`.
+pub fn apply(coverage: &Coverage, details: &mut [Details]) {
+    for d in details.iter_mut() {
+        if let Some((covered, days)) = d.instance_id.as_ref().and_then(|id| coverage.get(id)) {
+            d.ri_covered = Some(*covered);
+            d.ri_days_remaining = *days;
+        }
+    }
+}
+
+async fn reservations(ctx: &CredentialContext, region: &str) -> Result<Vec<Reservation>, AppError> {
+    let client = Ec2Client::new_with_client(ctx.client_for(region), identity::ec2_region(region));
+    let request = DescribeReservedInstancesRequest {
+        filters: Some(vec![Filter { name: Some("state".to_string()), values: Some(vec!["active".to_string()]) }]),
+        ..Default::default()
+    };
+    let result = retry::with_retry(region, request, |req| {
+        let c = client.clone();
+        async move { c.describe_reserved_instances(req).await }
+    }).await;
+    match result {
+        Ok(found) => Ok(found.reserved_instances.unwrap_or_default().into_iter().map(reservation).collect()),
+        Err(why) => {
+            let failure = AppError::aws(region, format!("failed to describe reserved instances: {}", aws_error::describe(&why)));
+            Err(failure.with_request_id(aws_error::request_id(&why)))
+        }
+    }
+}
+
+fn reservation(ri: ReservedInstances) -> Reservation {
+    let zonal = ri.scope.as_deref() == Some("Availability Zone");
+    Reservation {
+        availability_zone: match zonal {
+            true => ri.availability_zone,
+            false => None
+        },
+        end: ri.end.as_deref()
+            .and_then(|end| DateTime::parse_from_rfc3339(end).ok())
+            .map(|end| end.with_timezone(&Utc)),
+        instance_type: ri.instance_type,
+        platform: ri.product_description.map(|p| p.trim_end_matches(" (Amazon VPC)").to_string()),
+        unclaimed: ri.instance_count.unwrap_or(0)
+    }
+}
+
+fn fits(reservation: &Reservation, details: &Details) -> bool {
+    let zone_fits = match &reservation.availability_zone {
+        Some(zone) => details.availability_zone.as_ref() == Some(zone),
+        None => true
+    };
+    zone_fits
+        && reservation.instance_type.is_some()
+        && reservation.instance_type == details.instance_type
+        && reservation.platform == details.platform_details
+}
@@ -0,0 +1,155 @@
+use crate::aws_error;
+use log::warn;
+use rusoto_core::RusotoError;
+use rusoto_ec2::{
+    DescribeAddressesRequest, DescribeImagesRequest, DescribeInstanceStatusRequest, DescribeInstanceTypesRequest, DescribeInstancesRequest,
+    DescribeNetworkInterfacesRequest, DescribeReservedInstancesRequest, DescribeSecurityGroupsRequest, DescribeSnapshotsRequest, DescribeVolumesRequest
+};
+use std::error::Error;
+use std::future::Future;
+use std::time::Duration;
+
+const MAX_ATTEMPTS: u32 = 5;
+const BASE_DELAY_MS: u64 = 200;
+
+const THROTTLE_CODES: [&str; 3] = ["Throttling", "RequestLimitExceeded", "ThrottlingException"];
+
+const AUTH_CODES: [&str; 8] = [
+    "AuthFailure",
+    "ExpiredToken",
+    "InvalidClientTokenId",
+    "MissingAuthenticationToken",
+    "OptInRequired",
+    "SignatureDoesNotMatch",
+    "UnauthorizedOperation",
+    "UnrecognizedClientException"
+];
+
+/// Why a call failed, as far as deciding whether to try it again goes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Failure {
+    /// The credentials were missing, expired or not allowed to make the
+    /// call. Retrying can't help.
+    Auth,
+    /// DNS failures, refused or reset connections and timeouts.
+    Network,
+    /// Anything else, such as a malformed request.
+    Other,
+    /// A 5xx from the service.
+    Server,
+    Throttled
+}
+
+impl Failure {
+    pub fn is_transient(self) -> bool {
+        match self {
+            Failure::Network | Failure::Server | Failure::Throttled => true,
+            Failure::Auth | Failure::Other => false
+        }
+    }
+}
+
+/// Marker for requests that are safe to send more than once. Only read-only
+/// describe requests implement it, so `with_retry` can never be pointed at an
+/// operation with side effects.
+pub trait ReadOnlyRequest: Clone {}
+
+impl ReadOnlyRequest for DescribeAddressesRequest {}
+impl ReadOnlyRequest for DescribeImagesRequest {}
+impl ReadOnlyRequest for DescribeInstanceStatusRequest {}
+impl ReadOnlyRequest for DescribeInstanceTypesRequest {}
+impl ReadOnlyRequest for DescribeInstancesRequest {}
+impl ReadOnlyRequest for DescribeNetworkInterfacesRequest {}
+impl ReadOnlyRequest for DescribeReservedInstancesRequest {}
+impl ReadOnlyRequest for DescribeSecurityGroupsRequest {}
+impl ReadOnlyRequest for DescribeSnapshotsRequest {}
+impl ReadOnlyRequest for DescribeVolumesRequest {}
+
+/// Sends `request` through `call`, retrying transient failures with
+/// exponential backoff. Each retry is logged at warn level with the region
+/// and attempt number.
+pub async fn with_retry<R, T, E, F, Fut>(region: &str, request: R, mut call: F) -> Result<T, RusotoError<E>>
+where
+    R: ReadOnlyRequest,
+    E: Error + 'static,
+    F: FnMut(R) -> Fut,
+    Fut: Future<Output = Result<T, RusotoError<E>>>
+{
+    let mut attempt = 1;
+    loop {
+        match call(request.clone()).await {
+            Err(why) if attempt < MAX_ATTEMPTS && classify(&why).is_transient() => {
+                attempt += 1;
+                warn!("retrying describe call in {} after a {:?} failure (attempt {} of {}): {}", region, classify(&why), attempt, MAX_ATTEMPTS, aws_error::describe(&why));
+                tokio::time::sleep(backoff(attempt)).await;
+            },
+            result => return result
+        }
+    }
+}
+
+pub fn classify<E>(err: &RusotoError<E>) -> Failure {
+    match err {
+        RusotoError::HttpDispatch(_) => Failure::Network,
+        RusotoError::Credentials(_) => Failure::Auth,
+        RusotoError::Unknown(res) => match aws_error::error_code(err) {
+            Some(code) if THROTTLE_CODES.contains(&code.as_str()) => Failure::Throttled,
+            Some(code) if AUTH_CODES.contains(&code.as_str()) => Failure::Auth,
+            _ if res.status.as_u16() == 401 || res.status.as_u16() == 403 => Failure::Auth,
+            _ if res.status.is_server_error() => Failure::Server,
+            _ => Failure::Other
+        },
+        _ => Failure::Other
+    }
+}
+
+fn backoff(attempt: u32) -> Duration {
+    Duration::from_millis(BASE_DELAY_MS * 2u64.pow(attempt - 1))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::credential::CredentialsError;
+    use rusoto_core::request::{BufferedHttpResponse, HttpDispatchError};
+    use rusoto_ec2::DescribeInstancesError;
+    use std::convert::TryInto;
+
+    fn response(status: u16, code: &str) -> RusotoError<DescribeInstancesError> {
+        let body = format!("<Response><Errors><Error><Code>{}</Code><Message>test</Message></Error></Errors></Response>", code);
+        RusotoError::Unknown(BufferedHttpResponse {
+            body: body.into(),
+            headers: Default::default(),
+            status: status.try_into().unwrap()
+        })
+    }
+
+    #[test]
+    fn throttling_is_retried() {
+        assert_eq!(classify(&response(400, "RequestLimitExceeded")), Failure::Throttled);
+        assert!(Failure::Throttled.is_transient());
+    }
+
+    #[test]
+    fn server_errors_are_retried() {
+        assert_eq!(classify(&response(503, "Unavailable")), Failure::Server);
+        assert!(Failure::Server.is_transient());
+    }
+
+    #[test]
+    fn dispatch_errors_are_retried() {
+        let err: RusotoError<DescribeInstancesError> = RusotoError::HttpDispatch(HttpDispatchError::new("connection reset by peer".to_string()));
+        assert_eq!(classify(&err), Failure::Network);
+        assert!(Failure::Network.is_transient());
+    }
+
+    #[test]
+    fn auth_and_validation_errors_fail_fast() {
+        let missing: RusotoError<DescribeInstancesError> = RusotoError::Credentials(CredentialsError::new("no credentials"));
+        assert_eq!(classify(&missing), Failure::Auth);
+        assert_eq!(classify(&response(403, "UnauthorizedOperation")), Failure::Auth);
+        assert_eq!(classify(&response(400, "InvalidParameterValue")), Failure::Other);
+        assert!(!Failure::Auth.is_transient());
+        assert!(!Failure::Other.is_transient());
+    }
+}
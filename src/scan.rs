@@ -0,0 +1,36 @@
+use crate::error::AppError;
+use crate::estimate::RegionEstimate;
+use crate::orphans::Orphan;
+use crate::Details;
+use chrono::{DateTime, Utc};
+use std::collections::BTreeMap;
+use std::time::Duration;
+
+/// The outcome of a scan: the instances found along with what the CLI
+/// summary reports about the run, so callers don't need to recompute it.
+#[derive(Debug, Clone, Default)]
+pub struct ScanReport {
+    pub instances: Vec<Details>,
+    /// Instances found in each scanned region, before any filtering.
+    pub per_region_counts: BTreeMap<String, usize>,
+    /// Failures the scan carried on past, one per failed region.
+    pub errors: Vec<AppError>,
+    /// Per-region call estimates, only gathered for `--estimate-calls`.
+    pub estimates: Vec<RegionEstimate>,
+    /// Unused resources, only gathered for `--report orphans`.
+    pub orphans: Vec<Orphan>,
+    pub duration: Duration
+}
+
+impl ScanReport {
+    pub fn add_page(&mut self, region: &str, page: Vec<Details>) {
+        *self.per_region_counts.entry(region.to_string()).or_insert(0) += page.len();
+        self.instances.extend(page);
+    }
+
+    /// Stamps the time taken since `started`.
+    pub fn finished(mut self, started: DateTime<Utc>) -> ScanReport {
+        self.duration = Utc::now().signed_duration_since(started).to_std().unwrap_or_default();
+        self
+    }
+}
@@ -0,0 +1,90 @@
+use crate::changes;
+use crate::credentials::CredentialContext;
+use crate::incremental;
+use crate::error::AppError;
+use crate::options::Options;
+use crate::systemd;
+use chrono::Utc;
+use chrono_tz::Tz;
+use log::{info, warn};
+use std::time::Duration;
+use tokio::sync::{watch, Mutex};
+
+/// A `--schedule` entry: when to run, and what to run when it differs from
+/// the command line's own scan.
+pub struct Schedule {
+    pub expression: String,
+    pub target: Option<Box<Options>>,
+    pub times: cron::Schedule
+}
+
+impl Schedule {
+    /// How long until the next tick, `None` once the schedule has no more.
+    pub fn until_next(&self, timezone: Tz) -> Option<Duration> {
+        let next = self.times.upcoming(timezone).next()?;
+        Some(next.with_timezone(&Utc).signed_duration_since(Utc::now()).to_std().unwrap_or_default())
+    }
+}
+
+/// Runs every `--schedule` until interrupted. Collections never overlap: a
+/// tick that arrives while any collection is still running is skipped with
+/// a warning rather than queued behind it.
+///
+/// Schedules without a target scan `contexts`. One with a target sets up
+/// its own once, since its flags can name other credentials.
+pub async fn run(options: &Options, contexts: &[CredentialContext]) -> Result<i32, AppError> {
+    let (stop, stopped) = watch::channel(false);
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            eprintln!("interrupted, stopping after the current collection");
+            systemd::stopping();
+            let _ = stop.send(true);
+        }
+    });
+    let busy = Mutex::new(());
+    let loops = options.schedules.iter()
+        .map(|schedule| run_one(options, contexts, schedule, &busy, stopped.clone()));
+    futures::future::join_all(loops).await;
+    Ok(0)
+}
+
+async fn run_one(options: &Options, contexts: &[CredentialContext], schedule: &Schedule, busy: &Mutex<()>, mut stopped: watch::Receiver<bool>) {
+    let target = schedule.target.as_deref().unwrap_or(options);
+    let own;
+    let contexts = match &schedule.target {
+        Some(target) => {
+            own = crate::scan_contexts(target).await;
+            &own[..]
+        },
+        None => contexts
+    };
+    let mut previous: Option<changes::Snapshot> = None;
+    let mut inventory = incremental::Inventory::restore(target, &schedule.expression);
+    loop {
+        let wait = match schedule.until_next(options.timezone) {
+            Some(wait) => wait,
+            None => return info!("'{}' has no further runs", schedule.expression)
+        };
+        info!("'{}' next runs in {}", schedule.expression, humantime::format_duration(Duration::from_secs(wait.as_secs())));
+        tokio::select! {
+            _ = systemd::idle(wait) => {},
+            _ = stopped.changed() => return
+        }
+        let guard = match busy.try_lock() {
+            Ok(guard) => guard,
+            Err(_) => {
+                warn!("skipping '{}': another collection is still running", schedule.expression);
+                continue;
+            }
+        };
+        let began = Utc::now();
+        crate::watch_cycle(target, contexts, &mut previous, &mut inventory).await;
+        drop(guard);
+        let missed = schedule.times.after(&began.with_timezone(&options.timezone))
+            .take_while(|tick| tick.with_timezone(&Utc) < Utc::now())
+            .count();
+        if missed > 0 {
+            warn!("'{}' skipped {} runs while its collection was running", schedule.expression, missed);
+        }
+    }
+}
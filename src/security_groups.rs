@@ -0,0 +1,57 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError};
+use crate::options::Options;
+use crate::{aws_error, retry, Details};
+use rusoto_core::Region;
+use rusoto_ec2::{DescribeSecurityGroupsRequest, Ec2, Ec2Client, IpPermission};
+use std::collections::BTreeSet;
+
+/// Group ids per DescribeSecurityGroups call, well under the request limit.
+const GROUPS_PER_CALL: usize = 100;
+
+/// Sets `open_to_internet` on the page's instances that have a public IP:
+/// true when any of their security groups accepts inbound traffic from
+/// 0.0.0.0/0 or ::/0.
+pub async fn fill_exposure(ctx: &CredentialContext, region: &Region, options: &Options, details: &mut [Details]) {
+    let wanted: Vec<String> = details.iter()
+        .filter(|d| d.public_ip_address.is_some())
+        .flat_map(|d| d.security_group_ids.iter().cloned())
+        .collect::<BTreeSet<String>>()
+        .into_iter()
+        .collect();
+    let client = Ec2Client::new_with_client(ctx.client_for(region.name()), region.clone());
+    let mut open: BTreeSet<String> = BTreeSet::new();
+    for chunk in wanted.chunks(GROUPS_PER_CALL) {
+        let request = DescribeSecurityGroupsRequest {
+            group_ids: Some(chunk.to_vec()),
+            ..Default::default()
+        };
+        let result = retry::with_retry(region.name(), request, |req| {
+            let c = client.clone();
+            async move { c.describe_security_groups(req).await }
+        }).await;
+        match result {
+            Ok(groups) => {
+                for group in groups.security_groups.unwrap_or_default() {
+                    if group.ip_permissions.iter().flatten().any(open_to_world) {
+                        open.extend(group.group_id);
+                    }
+                }
+            },
+            Err(why) => {
+                let failure = AppError::aws(region.name(), format!("failed to describe security groups: {}", aws_error::describe(&why)));
+                error::report(&failure.with_request_id(aws_error::request_id(&why)), options.error_format);
+                return;
+            }
+        }
+    }
+    for d in details.iter_mut().filter(|d| d.public_ip_address.is_some()) {
+        d.open_to_internet = Some(d.security_group_ids.iter().any(|g| open.contains(g)));
+    }
+}
+
+fn open_to_world(permission: &IpPermission) -> bool {
+    let v4 = permission.ip_ranges.iter().flatten().any(|r| r.cidr_ip.as_deref() == Some("0.0.0.0/0"));
+    let v6 = permission.ipv_6_ranges.iter().flatten().any(|r| r.cidr_ipv_6.as_deref() == Some("::/0"));
+    v4 || v6
+}
@@ -0,0 +1,496 @@
+use crate::credentials::CredentialContext;
+use crate::error::AppError;
+use crate::options::Options;
+use crate::output::{self, Format};
+use crate::prometheus::{self, Snapshot};
+use crate::scan::ScanReport;
+use crate::{changes, derived, fields, filters, sort, systemd, Details};
+use chrono::{DateTime, Duration as ChronoDuration, Utc};
+use log::{debug, warn};
+use serde_json::Value;
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::time::Instant;
+
+/// Requests larger than this are refused; every route is a bare GET.
+const MAX_REQUEST_BYTES: usize = 8 * 1024;
+
+/// Seconds a client is told to wait while the first collection runs.
+const RETRY_AFTER_SECS: u64 = 30;
+
+/// What the request handlers share with the refresh loop.
+struct Shared {
+    fields: Option<Vec<String>>,
+    health: RwLock<Health>,
+    /// `None` until the first collection has finished.
+    inventory: RwLock<Option<Vec<Details>>>,
+    /// The `/metrics` page for the same collection as `inventory`.
+    metrics: RwLock<Option<String>>,
+    /// `--omit-null`, applied to the JSON route only.
+    omit_null: bool,
+    ready_max_failures: u32,
+    redact: Vec<String>,
+    redact_omit: bool
+}
+
+/// How recent collections went, for `/readyz`.
+#[derive(Default)]
+struct Health {
+    /// Collections in a row in which every region failed.
+    consecutive_failures: u32,
+    /// When the last collection with any successful region finished.
+    last_success: Option<DateTime<Utc>>,
+    /// Past this, the last good collection is too old to be ready.
+    stale_after: Option<DateTime<Utc>>
+}
+
+impl Health {
+    /// Why the server shouldn't get traffic, if it shouldn't.
+    fn not_ready(&self, max_failures: u32) -> Option<String> {
+        if self.consecutive_failures >= max_failures {
+            return Some(format!("the last {} collections failed", self.consecutive_failures))
+        }
+        match (self.last_success, self.stale_after) {
+            (None, _) => Some("no collection has succeeded yet".to_string()),
+            (Some(last), Some(stale)) if Utc::now() > stale => Some(format!("the last good collection finished at {}", last.to_rfc3339())),
+            _ => None
+        }
+    }
+}
+
+/// `serve`: rescans every `--refresh-interval` and answers
+///
+/// - `GET /instances` with the latest results as JSON
+/// - `GET /instances.csv` with the same as CSV
+/// - `GET /metrics` with Prometheus gauges for the same collection
+/// - `GET /healthz` with 200 while the process is up
+/// - `GET /readyz` with 200 once a collection has succeeded, and 503 when
+///   the last good one is older than `--ready-intervals` refresh intervals
+///   or the last `--ready-max-failures` all failed
+///
+/// the instance routes narrowed by the query parameters `region`, `state`
+/// (comma separated) and `tag=Key:Value`. The command line filters are
+/// applied to every collection; the query narrows those results further.
+/// Requests are served while a refresh runs, from the previous collection.
+///
+/// On SIGTERM or Ctrl-C the server stops accepting connections and
+/// collecting, gives requests and notifications in flight up to
+/// `--grace-period` to finish, and exits zero.
+pub async fn run(options: &Options, contexts: &[CredentialContext]) -> Result<i32, AppError> {
+    let listener = match TcpListener::bind(options.listen).await {
+        Ok(l) => l,
+        Err(why) => return Err(AppError::io(format!("couldn't listen on {}: {}", options.listen, why)))
+    };
+    println!("serving on http://{}/instances", options.listen);
+    let shared = Arc::new(Shared {
+        fields: options.fields.clone(),
+        health: RwLock::new(Health::default()),
+        inventory: RwLock::new(None),
+        metrics: RwLock::new(None),
+        omit_null: options.omit_null,
+        ready_max_failures: options.ready_max_failures,
+        redact: options.redact.clone(),
+        redact_omit: options.redact_omit
+    });
+    let in_flight = Arc::new(());
+    let accept = accept_loop(listener, shared.clone(), in_flight.clone());
+    let refresh = refresh_loop(options, contexts, shared);
+    tokio::select! {
+        _ = futures::future::join(accept, refresh) => {},
+        _ = terminated() => println!("shutting down")
+    }
+    systemd::stopping();
+    let deadline = Instant::now() + options.grace_period;
+    while Arc::strong_count(&in_flight) > 1 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    if Arc::strong_count(&in_flight) > 1 {
+        warn!("{} requests were still being answered at shutdown", Arc::strong_count(&in_flight) - 1);
+    }
+    crate::flush_notifications(deadline.saturating_duration_since(Instant::now())).await;
+    Ok(0)
+}
+
+/// Resolves on SIGTERM, as sent by orchestrators stopping the process, or
+/// Ctrl-C.
+async fn terminated() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        if let Ok(mut term) = signal(SignalKind::terminate()) {
+            tokio::select! {
+                _ = term.recv() => {},
+                _ = tokio::signal::ctrl_c() => {}
+            }
+            return;
+        }
+    }
+    let _ = tokio::signal::ctrl_c().await;
+}
+
+async fn refresh_loop(options: &Options, contexts: &[CredentialContext], shared: Arc<Shared>) {
+    let mut region_errors: BTreeMap<String, u64> = BTreeMap::new();
+    let mut previous: Option<changes::Snapshot> = None;
+    loop {
+        let began = Utc::now();
+        let scan = crate::scan_all(options, contexts, Arc::new(Mutex::new(ScanReport::default()))).await;
+        let finished = Utc::now();
+        for failure in scan.errors.iter() {
+            *region_errors.entry(failure.region.clone().unwrap_or_default()).or_insert(0) += 1;
+        }
+        let failed = !scan.errors.is_empty() && scan.per_region_counts.keys()
+            .all(|region| scan.errors.iter().any(|e| e.region.as_ref() == Some(region)));
+        let failed_regions: BTreeSet<String> = scan.errors.iter().filter_map(|e| e.region.clone()).collect();
+        let mut kept = sort::sort(&options.sort_by, filters::apply(options, scan.instances).kept, finished);
+        derived::apply(options, &mut kept);
+        // A collection in which every region failed leaves the previous
+        // results as they were; one in which some did keeps those regions'
+        // instances from before rather than dropping them.
+        let kept = match (failed, shared.inventory.read().unwrap().as_ref()) {
+            (true, Some(before)) => before.clone(),
+            (false, Some(before)) => {
+                let fresh: BTreeSet<&String> = kept.iter().filter_map(|d| d.instance_id.as_ref()).collect();
+                let carried: Vec<Details> = before.iter()
+                    .filter(|d| failed_regions.contains(&d.region) && d.instance_id.as_ref().map_or(false, |id| !fresh.contains(id)))
+                    .cloned()
+                    .collect();
+                kept.extend(carried);
+                sort::sort(&options.sort_by, kept, finished)
+            },
+            (_, None) => kept
+        };
+        let summary = format!("{} instances, {} regions failed", kept.len(), scan.errors.len());
+        println!("refreshed: {}", summary);
+        systemd::collected(&format!("{} at {}", summary, finished.format("%H:%M:%SZ")), !failed);
+        let current = changes::snapshot(&kept);
+        if let Some(previous) = &previous {
+            crate::notify_changes(options, &changes::compare(previous, &current));
+        }
+        previous = Some(current);
+        let wait = match options.schedules.first() {
+            Some(schedule) => schedule.until_next(options.timezone).unwrap_or(options.refresh_interval),
+            None => options.refresh_interval
+        };
+        let last_success = {
+            let mut health = shared.health.write().unwrap();
+            match failed {
+                true => health.consecutive_failures += 1,
+                false => {
+                    health.consecutive_failures = 0;
+                    health.last_success = Some(finished);
+                    let allowed = wait.max(options.refresh_interval) * options.ready_intervals;
+                    health.stale_after = ChronoDuration::from_std(allowed).ok().and_then(|d| finished.checked_add_signed(d));
+                }
+            }
+            health.last_success
+        };
+        let metrics = prometheus::render(&Snapshot {
+            details: &kept,
+            duration: finished.signed_duration_since(began).to_std().unwrap_or_default(),
+            last_success: last_success,
+            region_errors: &region_errors
+        });
+        *shared.metrics.write().unwrap() = Some(metrics);
+        *shared.inventory.write().unwrap() = Some(kept);
+        systemd::idle(wait).await;
+    }
+}
+
+/// Each connection holds a clone of `in_flight` until it is answered, so
+/// shutdown can wait for the count to drop.
+async fn accept_loop(listener: TcpListener, shared: Arc<Shared>, in_flight: Arc<()>) {
+    loop {
+        match listener.accept().await {
+            Ok((stream, peer)) => {
+                debug!("connection from {}", peer);
+                let (shared, held) = (shared.clone(), in_flight.clone());
+                tokio::spawn(async move {
+                    handle(stream, shared).await;
+                    drop(held);
+                });
+            },
+            Err(why) => warn!("couldn't accept a connection: {}", why)
+        }
+    }
+}
+
+async fn handle(mut stream: TcpStream, shared: Arc<Shared>) {
+    let response = match read_request(&mut stream).await {
+        Some(request) => respond(&request, &shared),
+        None => Response::text(400, "Bad Request", "malformed request")
+    };
+    if let Err(why) = stream.write_all(&response.into_bytes()).await {
+        debug!("couldn't write a response: {}", why);
+    }
+}
+
+/// The request line, once the whole head has arrived.
+async fn read_request(stream: &mut TcpStream) -> Option<String> {
+    let mut head = Vec::new();
+    let mut buffer = [0u8; 1024];
+    while !head.windows(4).any(|w| w == b"\r\n\r\n") {
+        let read = stream.read(&mut buffer).await.ok()?;
+        if read == 0 || head.len() + read > MAX_REQUEST_BYTES {
+            return None;
+        }
+        head.extend_from_slice(&buffer[..read]);
+    }
+    String::from_utf8_lossy(&head).lines().next().map(|l| l.to_string())
+}
+
+fn respond(request_line: &str, shared: &Shared) -> Response {
+    let mut parts = request_line.split_whitespace();
+    let (method, target) = match (parts.next(), parts.next()) {
+        (Some(m), Some(t)) => (m, t),
+        _ => return Response::text(400, "Bad Request", "malformed request line")
+    };
+    if method != "GET" {
+        return Response::text(405, "Method Not Allowed", "only GET is supported");
+    }
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    if path == "/healthz" {
+        return Response::text(200, "OK", "ok");
+    }
+    if path == "/readyz" {
+        return match shared.health.read().unwrap().not_ready(shared.ready_max_failures) {
+            Some(why) => Response::text(503, "Service Unavailable", &why),
+            None => Response::text(200, "OK", "ready")
+        };
+    }
+    if path == "/metrics" {
+        return match shared.metrics.read().unwrap().as_ref() {
+            Some(metrics) => Response {
+                body: metrics.clone(),
+                headers: vec![("Content-Type".to_string(), "text/plain; version=0.0.4".to_string())],
+                reason: "OK",
+                status: 200
+            },
+            None => Response::starting()
+        };
+    }
+    let format = match path {
+        "/instances" => Format::Json,
+        "/instances.csv" => Format::Csv,
+        _ => return Response::text(404, "Not Found", "try /instances, /instances.csv, /metrics, /healthz or /readyz")
+    };
+    let inventory = shared.inventory.read().unwrap();
+    let instances = match inventory.as_ref() {
+        Some(i) => i,
+        None => return Response::starting()
+    };
+    let matched = match narrowed(instances, query) {
+        Ok(m) => m,
+        Err(why) => return Response::text(400, "Bad Request", &why)
+    };
+    let mut records = fields::project(shared.fields.as_deref(), &matched);
+    fields::redact(&mut records, &shared.redact, shared.redact_omit);
+    if shared.omit_null && format == Format::Json {
+        fields::omit_null(&mut records);
+    }
+    let records = Value::Array(records);
+    let content_type = match format {
+        Format::Csv => "text/csv; charset=utf-8",
+        _ => "application/json"
+    };
+    Response {
+        body: output::render(format, &records),
+        headers: vec![("Content-Type".to_string(), content_type.to_string())],
+        reason: "OK",
+        status: 200
+    }
+}
+
+/// The instances a request's query string asks for.
+fn narrowed(instances: &[Details], query: &str) -> Result<Vec<Details>, String> {
+    let query = Query::parse(query)?;
+    Ok(instances.iter().filter(|d| query.matches(d)).cloned().collect())
+}
+
+/// The `?region=&state=&tag=` narrowing of a request.
+#[derive(Default)]
+struct Query {
+    regions: Vec<String>,
+    states: Vec<String>,
+    tags: Vec<(String, String)>
+}
+
+impl Query {
+    fn parse(query: &str) -> Result<Query, String> {
+        let mut parsed = Query::default();
+        for pair in query.split('&').filter(|p| !p.is_empty()) {
+            let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value);
+            match key {
+                "region" => parsed.regions.extend(value.split(',').map(|v| v.trim().to_string())),
+                "state" => parsed.states.extend(value.split(',').map(|v| v.trim().to_string())),
+                "tag" => match value.split_once(':') {
+                    Some((k, v)) => parsed.tags.push((k.to_string(), v.to_string())),
+                    None => return Err(format!("tag must be Key:Value, found '{}'", value))
+                },
+                other => return Err(format!("unknown query parameter '{}', expected region, state or tag", other))
+            }
+        }
+        Ok(parsed)
+    }
+
+    fn matches(&self, details: &Details) -> bool {
+        (self.regions.is_empty() || self.regions.contains(&details.region))
+            && (self.states.is_empty() || details.state.as_ref().map_or(false, |s| self.states.contains(s)))
+            && self.tags.iter().all(|(k, v)| details.tags.get(k) == Some(v))
+    }
+}
+
+/// Percent-decoding of a query value, with `+` as a space.
+fn decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let hex = |i: usize| bytes.get(i).and_then(|b| (*b as char).to_digit(16));
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match (bytes[i], hex(i + 1), hex(i + 2)) {
+            (b'%', Some(high), Some(low)) => {
+                decoded.push((high * 16 + low) as u8);
+                i += 2;
+            },
+            (b'+', _, _) => decoded.push(b' '),
+            (b, _, _) => decoded.push(b)
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+struct Response {
+    body: String,
+    headers: Vec<(String, String)>,
+    reason: &'static str,
+    status: u16
+}
+
+impl Response {
+    fn text(status: u16, reason: &'static str, body: &str) -> Response {
+        Response {
+            body: format!("{}\n", body),
+            headers: vec![("Content-Type".to_string(), "text/plain; charset=utf-8".to_string())],
+            reason: reason,
+            status: status
+        }
+    }
+
+    /// The answer to every route until the first collection is in.
+    fn starting() -> Response {
+        let mut response = Response::text(503, "Service Unavailable", "the first collection is still running");
+        response.headers.push(("Retry-After".to_string(), RETRY_AFTER_SECS.to_string()));
+        response
+    }
+
+    fn into_bytes(self) -> Vec<u8> {
+        let mut head = format!("HTTP/1.1 {} {}\r\nContent-Length: {}\r\nConnection: close\r\n", self.status, self.reason, self.body.len());
+        for (name, value) in self.headers.iter() {
+            head.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        head.push_str("\r\n");
+        let mut bytes = head.into_bytes();
+        bytes.extend_from_slice(self.body.as_bytes());
+        bytes
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn instance(id: &str, region: &str, state: &str, env: &str) -> Details {
+        let mut tags = BTreeMap::new();
+        tags.insert("Env".to_string(), env.to_string());
+        Details {
+            instance_id: Some(id.to_string()),
+            region: region.to_string(),
+            state: Some(state.to_string()),
+            tags: tags,
+            ..Default::default()
+        }
+    }
+
+    fn inventory() -> Vec<Details> {
+        vec![
+            instance("i-1", "eu-west-1", "running", "prod"),
+            instance("i-2", "eu-west-1", "stopped", "prod"),
+            instance("i-3", "us-east-1", "running", "staging")
+        ]
+    }
+
+    fn ids(query: &str) -> Vec<String> {
+        narrowed(&inventory(), query).unwrap().into_iter().filter_map(|d| d.instance_id).collect()
+    }
+
+    fn shared(inventory: Option<Vec<Details>>) -> Shared {
+        Shared {
+            fields: None,
+            health: RwLock::new(Health::default()),
+            inventory: RwLock::new(inventory),
+            metrics: RwLock::new(None),
+            omit_null: false,
+            ready_max_failures: 3,
+            redact: Vec::new(),
+            redact_omit: false
+        }
+    }
+
+    fn header<'a>(response: &'a Response, name: &str) -> Option<&'a str> {
+        response.headers.iter().find(|(n, _)| n == name).map(|(_, v)| v.as_str())
+    }
+
+    #[test]
+    fn decodes_query_values() {
+        assert_eq!(decode("Env%3Aprod"), "Env:prod");
+        assert_eq!(decode("Cost+Centre%3A42"), "Cost Centre:42");
+        assert_eq!(decode("caf%C3%A9"), "café");
+        assert_eq!(decode("100%"), "100%");
+        assert_eq!(decode("%zz"), "%zz");
+    }
+
+    #[test]
+    fn narrows_by_region_state_and_tag() {
+        assert_eq!(ids(""), vec!["i-1", "i-2", "i-3"]);
+        assert_eq!(ids("region=eu-west-1"), vec!["i-1", "i-2"]);
+        assert_eq!(ids("state=running"), vec!["i-1", "i-3"]);
+        assert_eq!(ids("state=stopped,running&region=us-east-1"), vec!["i-3"]);
+        assert_eq!(ids("tag=Env:prod"), vec!["i-1", "i-2"]);
+        assert_eq!(ids("tag=Env%3Astaging"), vec!["i-3"]);
+        assert_eq!(ids("tag=Env:prod&state=running"), vec!["i-1"]);
+    }
+
+    #[test]
+    fn rejects_bad_queries() {
+        assert_eq!(narrowed(&inventory(), "tag=Env").unwrap_err(), "tag must be Key:Value, found 'Env'");
+        assert_eq!(narrowed(&inventory(), "zone=a").unwrap_err(), "unknown query parameter 'zone', expected region, state or tag");
+    }
+
+    #[test]
+    fn answers_503_with_retry_after_before_the_first_collection() {
+        let starting = shared(None);
+        for route in ["/instances", "/instances.csv?region=eu-west-1", "/metrics"].iter() {
+            let response = respond(&format!("GET {} HTTP/1.1", route), &starting);
+            assert_eq!(response.status, 503);
+            assert_eq!(header(&response, "Retry-After"), Some("30"));
+        }
+        assert_eq!(respond("GET /healthz HTTP/1.1", &starting).status, 200);
+        assert_eq!(respond("GET /readyz HTTP/1.1", &starting).status, 503);
+    }
+
+    #[test]
+    fn answers_the_narrowed_instances_once_collected() {
+        let ready = shared(Some(inventory()));
+        let response = respond("GET /instances?tag=Env:prod&state=stopped HTTP/1.1", &ready);
+        assert_eq!(response.status, 200);
+        assert_eq!(header(&response, "Retry-After"), None);
+        let records: Value = serde_json::from_str(&response.body).unwrap();
+        assert_eq!(records.as_array().unwrap().len(), 1);
+        assert_eq!(records[0]["instance_id"], "i-2");
+        assert_eq!(respond("GET /instances?tag=Env HTTP/1.1", &ready).status, 400);
+    }
+}
@@ -0,0 +1,137 @@
+use crate::fields;
+use crate::Details;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::cmp::Ordering;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SortKey {
+    pub field: String,
+    pub descending: bool
+}
+
+/// The order used when `--sort-by` isn't given, so repeated runs over the
+/// same inventory always produce identical output.
+pub fn default_keys() -> Vec<SortKey> {
+    vec![
+        SortKey { field: "region".to_string(), descending: false },
+        SortKey { field: "instance_id".to_string(), descending: false }
+    ]
+}
+
+/// Stable multi-key sort. Nulls always sort last whatever the direction,
+/// and numeric fields such as `age_days` compare numerically.
+pub fn sort(keys: &[SortKey], details: Vec<Details>, now: DateTime<Utc>) -> Vec<Details> {
+    let mut decorated: Vec<(Vec<Value>, Details)> = details.into_iter()
+        .map(|d| {
+            let record = fields::record(&d, now);
+            (keys.iter().map(|k| fields::lookup(&record, &k.field)).collect(), d)
+        })
+        .collect();
+    decorated.sort_by(|(a, _), (b, _)| {
+        keys.iter().zip(a.iter().zip(b.iter()))
+            .map(|(key, (x, y))| compare(x, y, key.descending))
+            .find(|o| *o != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    });
+    decorated.into_iter().map(|(_, d)| d).collect()
+}
+
+fn compare(a: &Value, b: &Value, descending: bool) -> Ordering {
+    let ordering = match (a, b) {
+        (Value::Null, Value::Null) => return Ordering::Equal,
+        (Value::Null, _) => return Ordering::Greater,
+        (_, Value::Null) => return Ordering::Less,
+        (Value::Number(x), Value::Number(y)) => x.as_f64().partial_cmp(&y.as_f64()).unwrap_or(Ordering::Equal),
+        (Value::String(x), Value::String(y)) => x.cmp(y),
+        (Value::Bool(x), Value::Bool(y)) => x.cmp(y),
+        (x, y) => x.to_string().cmp(&y.to_string())
+    };
+    match descending {
+        true => ordering.reverse(),
+        false => ordering
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::options;
+    use chrono::TimeZone;
+
+    fn instance(id: &str, name: Option<&str>, launch_time: Option<&str>) -> Details {
+        Details {
+            instance_id: Some(id.to_string()),
+            launch_time: launch_time.map(str::to_string),
+            name: name.map(str::to_string),
+            ..Default::default()
+        }
+    }
+
+    fn ids(details: &[Details]) -> Vec<String> {
+        details.iter().filter_map(|d| d.instance_id.clone()).collect()
+    }
+
+    fn keys(value: &str) -> Result<Vec<SortKey>, crate::error::AppError> {
+        let args: Vec<String> = ["list_servers", "eu-west-1", "--sort-by", value].iter().map(|a| a.to_string()).collect();
+        options::parse_args(&args).map(|o| o.sort_by)
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2026, 3, 20).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn sorts_by_several_keys_in_their_own_directions() {
+        let details = vec![
+            instance("i-1", Some("web"), Some("2026-03-01T08:00:00.000Z")),
+            instance("i-2", Some("api"), Some("2026-03-02T08:00:00.000Z")),
+            instance("i-3", Some("db"), Some("2026-03-01T08:00:00.000Z")),
+            instance("i-4", Some("cache"), Some("2026-03-02T08:00:00.000Z"))
+        ];
+        let sorted = sort(&keys("launch_time:desc,name:asc").unwrap(), details, now());
+        assert_eq!(ids(&sorted), vec!["i-2", "i-4", "i-3", "i-1"]);
+    }
+
+    #[test]
+    fn nulls_sort_last_in_either_direction() {
+        let details = vec![
+            instance("i-1", None, Some("2026-03-01T08:00:00.000Z")),
+            instance("i-2", None, None),
+            instance("i-3", None, Some("2026-03-02T08:00:00.000Z"))
+        ];
+        let ascending = sort(&keys("launch_time").unwrap(), details.clone(), now());
+        assert_eq!(ids(&ascending), vec!["i-1", "i-3", "i-2"]);
+        let descending = sort(&keys("launch_time:desc").unwrap(), details, now());
+        assert_eq!(ids(&descending), vec!["i-3", "i-1", "i-2"]);
+    }
+
+    #[test]
+    fn age_days_compares_as_a_number() {
+        let details = vec![
+            instance("i-10", None, Some("2026-03-10T12:00:00.000Z")),
+            instance("i-9", None, Some("2026-03-11T12:00:00.000Z"))
+        ];
+        let sorted = sort(&keys("age_days").unwrap(), details, now());
+        assert_eq!(ids(&sorted), vec!["i-9", "i-10"]);
+    }
+
+    #[test]
+    fn ties_keep_their_input_order() {
+        let details = vec![
+            instance("i-3", Some("web"), None),
+            instance("i-1", Some("web"), None),
+            instance("i-2", Some("api"), None)
+        ];
+        let sorted = sort(&keys("name").unwrap(), details, now());
+        assert_eq!(ids(&sorted), vec!["i-2", "i-3", "i-1"]);
+    }
+
+    #[test]
+    fn an_unknown_key_lists_the_valid_fields() {
+        let err = keys("launched:desc").unwrap_err();
+        assert!(err.message.starts_with("unknown field 'launched' in --sort-by, expected tags.<Key> or any of: "));
+        assert!(err.message.contains("launch_time"));
+        assert!(err.message.ends_with("age_days"));
+    }
+}
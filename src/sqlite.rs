@@ -0,0 +1,219 @@
+use crate::error::AppError;
+use crate::fields;
+use crate::options::{HistoryQuery, SqliteMode};
+use crate::summarize;
+use crate::Details;
+use chrono::{SecondsFormat, Utc};
+use rusqlite::types::Value as SqlValue;
+use rusqlite::{params, params_from_iter, Connection, Transaction};
+use serde_json::{json, Map, Value};
+use std::collections::{BTreeMap, BTreeSet};
+use std::path::Path;
+use std::time::Duration;
+
+/// Schema changes beyond adding field columns, applied in order to bring a
+/// database up to date. `PRAGMA user_version` records how many have run,
+/// so entries must never be edited or reordered, only appended.
+const MIGRATIONS: &[&str] = &[
+    // 1: run history kept alongside the current-state `instances` table.
+    "CREATE TABLE IF NOT EXISTS runs (run_id INTEGER PRIMARY KEY AUTOINCREMENT, started_at TEXT NOT NULL, instance_count INTEGER NOT NULL);
+     CREATE TABLE IF NOT EXISTS run_instances (run_id INTEGER NOT NULL REFERENCES runs(run_id), instance_id TEXT NOT NULL, PRIMARY KEY (run_id, instance_id));
+     CREATE INDEX IF NOT EXISTS run_instances_by_instance ON run_instances (instance_id)"
+];
+
+/// Writes the instances to the `instances` table of the database at
+/// `path`, one column per output field keyed by `instance_id`. Columns for
+/// fields added since the table was created are added as needed. Lists and
+/// maps such as `tags` are stored as JSON text.
+///
+/// Every call also records a run in `runs` and appends its instances to
+/// `run_instances`, keyed by `(run_id, instance_id)`, for `history`.
+pub fn write(path: &Path, mode: SqliteMode, details: &[Details]) -> Result<(), AppError> {
+    let failed = |why: rusqlite::Error| AppError::io(format!("couldn't write {}: {}", path.display(), why));
+    let mut connection = Connection::open(path).map_err(failed)?;
+    let columns: Vec<String> = fields::names();
+    let transaction = connection.transaction().map_err(failed)?;
+    transaction.execute("CREATE TABLE IF NOT EXISTS instances (instance_id TEXT PRIMARY KEY)", []).map_err(failed)?;
+    migrate(&transaction).map_err(failed)?;
+    add_columns(&transaction, "instances", &columns).map_err(failed)?;
+    add_columns(&transaction, "run_instances", &columns).map_err(failed)?;
+    if mode == SqliteMode::Replace {
+        transaction.execute("DELETE FROM instances", []).map_err(failed)?;
+    }
+    let quoted: Vec<String> = columns.iter().map(|c| format!("\"{}\"", c)).collect();
+    let updates: Vec<String> = quoted.iter().filter(|c| *c != "\"instance_id\"").map(|c| format!("{}=excluded.{}", c, c)).collect();
+    let current = format!(
+        "INSERT INTO instances ({}) VALUES ({}) ON CONFLICT(instance_id) DO UPDATE SET {}",
+        quoted.join(", "),
+        vec!["?"; quoted.len()].join(", "),
+        updates.join(", ")
+    );
+    insert(&transaction, &current, &columns, &[], details).map_err(failed)?;
+    let started_at = Utc::now().to_rfc3339_opts(SecondsFormat::Secs, true);
+    transaction.execute("INSERT INTO runs (started_at, instance_count) VALUES (?1, ?2)", params![started_at, details.len() as i64]).map_err(failed)?;
+    let run_id = transaction.last_insert_rowid();
+    let history = format!(
+        "INSERT OR REPLACE INTO run_instances (run_id, {}) VALUES (?, {})",
+        quoted.join(", "),
+        vec!["?"; quoted.len()].join(", ")
+    );
+    insert(&transaction, &history, &columns, &[SqlValue::Integer(run_id)], details).map_err(failed)?;
+    transaction.commit().map_err(failed)
+}
+
+/// Runs the migrations the database hasn't seen yet. Databases written
+/// before migrations existed report version 0 and get all of them.
+fn migrate(transaction: &Transaction) -> rusqlite::Result<()> {
+    let version: usize = transaction.query_row("PRAGMA user_version", [], |row| row.get::<_, i64>(0))? as usize;
+    for migration in MIGRATIONS.iter().skip(version) {
+        transaction.execute_batch(migration)?;
+    }
+    if version < MIGRATIONS.len() {
+        transaction.execute_batch(&format!("PRAGMA user_version = {}", MIGRATIONS.len()))?;
+    }
+    Ok(())
+}
+
+fn add_columns(transaction: &Transaction, table: &str, columns: &[String]) -> rusqlite::Result<()> {
+    let existing = existing_columns(transaction, table)?;
+    for column in columns.iter().filter(|c| !existing.contains(*c)) {
+        transaction.execute(&format!("ALTER TABLE {} ADD COLUMN \"{}\"", table, column), [])?;
+    }
+    Ok(())
+}
+
+/// Runs `statement` once per instance, binding `leading` before the fields.
+fn insert(transaction: &Transaction, statement: &str, columns: &[String], leading: &[SqlValue], details: &[Details]) -> rusqlite::Result<()> {
+    let mut insert = transaction.prepare(statement)?;
+    for d in details.iter().filter(|d| d.instance_id.is_some()) {
+        let record = serde_json::to_value(d).unwrap_or_default();
+        let values = leading.iter().cloned().chain(columns.iter().map(|c| sql_value(record.get(c))));
+        insert.execute(params_from_iter(values))?;
+    }
+    Ok(())
+}
+
+/// Answers a `history` query from the run history in the database at
+/// `path`. `by` and `since` only apply to `count`.
+pub fn query(path: &Path, query: &HistoryQuery, by: &[String], since: Option<Duration>) -> Result<Vec<Value>, AppError> {
+    let failed = |why: rusqlite::Error| AppError::io(format!("couldn't read {}: {}", path.display(), why));
+    if !path.exists() {
+        return Err(AppError::io(format!("couldn't read {}: no such database", path.display())))
+    }
+    let connection = Connection::open(path).map_err(failed)?;
+    let recorded: bool = connection.query_row("SELECT COUNT(*) > 0 FROM sqlite_master WHERE name = 'run_instances'", [], |row| row.get(0)).map_err(failed)?;
+    if !recorded {
+        return Ok(Vec::new())
+    }
+    match query {
+        HistoryQuery::Count => count(&connection, by, since).map_err(failed),
+        HistoryQuery::Seen(id) => seen(&connection, id).map_err(failed),
+        HistoryQuery::Timeline(id) => timeline(&connection, id).map_err(failed)
+    }
+}
+
+/// When the instance was first and last seen, and in how many runs.
+fn seen(connection: &Connection, instance_id: &str) -> rusqlite::Result<Vec<Value>> {
+    let row = connection.query_row(
+        "SELECT MIN(r.started_at), MAX(r.started_at), COUNT(*) FROM run_instances i JOIN runs r ON r.run_id = i.run_id WHERE i.instance_id = ?1",
+        params![instance_id],
+        |row| Ok((row.get::<_, Option<String>>(0)?, row.get::<_, Option<String>>(1)?, row.get::<_, i64>(2)?))
+    )?;
+    Ok(match row {
+        (Some(first), Some(last), runs) => vec![json!({ "first_seen": first, "instance_id": instance_id, "last_seen": last, "runs": runs })],
+        _ => Vec::new()
+    })
+}
+
+/// The runs in which the instance's state differed from the run before it
+/// was last seen in, starting with its first appearance.
+fn timeline(connection: &Connection, instance_id: &str) -> rusqlite::Result<Vec<Value>> {
+    let mut statement = connection.prepare(
+        "SELECT r.started_at, i.state FROM run_instances i JOIN runs r ON r.run_id = i.run_id WHERE i.instance_id = ?1 ORDER BY r.run_id"
+    )?;
+    let states = statement.query_map(params![instance_id], |row| Ok((row.get::<_, String>(0)?, row.get::<_, Option<String>>(1)?)))?;
+    let mut rows = Vec::new();
+    let mut previous: Option<Option<String>> = None;
+    for state in states {
+        let (at, state) = state?;
+        if previous.as_ref() != Some(&state) {
+            rows.push(json!({ "at": at, "previous_state": previous.clone().flatten(), "state": state }));
+            previous = Some(state);
+        }
+    }
+    Ok(rows)
+}
+
+/// Instances per day and distinct combination of the `by` keys, taken from
+/// each day's last run so repeated runs in a day aren't counted twice.
+fn count(connection: &Connection, by: &[String], since: Option<Duration>) -> rusqlite::Result<Vec<Value>> {
+    let by: Vec<String> = match by.is_empty() {
+        true => vec!["region".to_string()],
+        false => by.to_vec()
+    };
+    let since = match since.and_then(|s| chrono::Duration::from_std(s).ok()).and_then(|s| Utc::now().checked_sub_signed(s)) {
+        Some(cutoff) => cutoff.to_rfc3339_opts(SecondsFormat::Secs, true),
+        None => String::new()
+    };
+    let columns: BTreeSet<&str> = by.iter()
+        .map(|key| match key.starts_with("tags.") {
+            true => "tags",
+            false => key.as_str()
+        })
+        .collect();
+    let selected: Vec<String> = columns.iter().map(|c| format!("i.\"{}\"", c)).collect();
+    let mut statement = connection.prepare(&format!(
+        "SELECT date(r.started_at), {} FROM run_instances i JOIN runs r ON r.run_id = i.run_id
+         WHERE r.run_id IN (SELECT MAX(run_id) FROM runs WHERE started_at >= ?1 GROUP BY date(started_at))",
+        selected.join(", ")
+    ))?;
+    let mut counts: BTreeMap<(String, Vec<String>), usize> = BTreeMap::new();
+    let mut rows = statement.query(params![since])?;
+    while let Some(row) = rows.next()? {
+        let day: String = row.get(0)?;
+        let mut record = Map::new();
+        for (index, column) in columns.iter().enumerate() {
+            let value = match row.get::<_, SqlValue>(index + 1)? {
+                SqlValue::Text(text) if *column == "tags" => serde_json::from_str(&text).unwrap_or_default(),
+                SqlValue::Text(text) => Value::String(text),
+                SqlValue::Integer(i) => Value::from(i),
+                SqlValue::Real(r) => Value::from(r),
+                _ => Value::Null
+            };
+            record.insert(column.to_string(), value);
+        }
+        let record = Value::Object(record);
+        let group: Vec<String> = by.iter().map(|key| summarize::group_value(&record, key)).collect();
+        *counts.entry((day, group)).or_insert(0) += 1;
+    }
+    Ok(counts.into_iter()
+        .map(|((day, group), count)| {
+            let mut row = Map::new();
+            row.insert("day".to_string(), Value::String(day));
+            for (key, value) in by.iter().zip(group.into_iter()) {
+                row.insert(key.clone(), Value::String(value));
+            }
+            row.insert("count".to_string(), Value::from(count));
+            Value::Object(row)
+        })
+        .collect())
+}
+
+fn existing_columns(connection: &Connection, table: &str) -> rusqlite::Result<BTreeSet<String>> {
+    let mut statement = connection.prepare("SELECT name FROM pragma_table_info(?1)")?;
+    let names = statement.query_map(params![table], |row| row.get::<_, String>(0))?;
+    names.collect()
+}
+
+fn sql_value(value: Option<&Value>) -> SqlValue {
+    match value {
+        None | Some(Value::Null) => SqlValue::Null,
+        Some(Value::Bool(b)) => SqlValue::Integer(*b as i64),
+        Some(Value::Number(n)) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default())
+        },
+        Some(Value::String(s)) => SqlValue::Text(s.clone()),
+        Some(other) => SqlValue::Text(other.to_string())
+    }
+}
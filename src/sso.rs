@@ -0,0 +1,265 @@
+use crate::error::AppError;
+use async_trait::async_trait;
+use chrono::{DateTime, TimeZone, Utc};
+use rusoto_core::{Client, HttpClient, Region, RusotoError};
+use rusoto_credential::{AwsCredentials, CredentialsError, ProvideAwsCredentials};
+use rusoto_sso::{GetRoleCredentialsError, GetRoleCredentialsRequest, Sso, SsoClient};
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::str::FromStr;
+use std::{env, fs};
+
+/// An IAM Identity Center profile: the role to fetch credentials for and
+/// the portal whose cached login token pays for them.
+pub struct SsoProvider {
+    account_id: String,
+    profile: String,
+    region: Region,
+    role_name: String,
+    start_url: String
+}
+
+/// The SSO settings of `profile` in the shared config file, or `None` if it
+/// is an ordinary profile. Both the legacy `sso_start_url` keys and an
+/// `sso_session` pointing at an `[sso-session name]` section are read.
+pub fn provider(profile: &str) -> Result<Option<SsoProvider>, AppError> {
+    configured(&fs::read_to_string(crate::credentials::config_file()).unwrap_or_default(), profile)
+}
+
+fn configured(config: &str, profile: &str) -> Result<Option<SsoProvider>, AppError> {
+    let sections = sections(config);
+    let header = match profile {
+        "default" => "default".to_string(),
+        other => format!("profile {}", other)
+    };
+    let settings = match sections.get(&header) {
+        Some(s) => s,
+        None => return Ok(None)
+    };
+    let session = match settings.get("sso_session") {
+        Some(name) => match sections.get(&format!("sso-session {}", name)) {
+            Some(s) => s,
+            None => return Err(AppError::usage(format!("profile {} uses sso_session {}, which isn't defined", profile, name)))
+        },
+        None => settings
+    };
+    let start_url = match session.get("sso_start_url") {
+        Some(url) => url.clone(),
+        None => return Ok(None)
+    };
+    let setting = |key: &str| -> Result<String, AppError> {
+        match settings.get(key).or_else(|| session.get(key)) {
+            Some(value) => Ok(value.clone()),
+            None => Err(AppError::usage(format!("SSO profile {} has no {}", profile, key)))
+        }
+    };
+    let region = setting("sso_region")?;
+    let region = match Region::from_str(&region) {
+        Ok(r) => r,
+        Err(_) => return Err(AppError::usage(format!("SSO profile {} has an invalid sso_region {}", profile, region)))
+    };
+    Ok(Some(SsoProvider {
+        account_id: setting("sso_account_id")?,
+        profile: profile.to_string(),
+        region: region,
+        role_name: setting("sso_role_name")?,
+        start_url: start_url
+    }))
+}
+
+#[async_trait]
+impl ProvideAwsCredentials for SsoProvider {
+    async fn credentials(&self) -> Result<AwsCredentials, CredentialsError> {
+        let token = self.cached_token()?;
+        let http = match HttpClient::new() {
+            Ok(c) => c,
+            Err(why) => return Err(CredentialsError::new(format!("couldn't create HTTP client: {}", why)))
+        };
+        // GetRoleCredentials is authorized by the token, not by a signature.
+        let client = SsoClient::new_with_client(Client::new_not_signing(http), self.region.clone());
+        let request = GetRoleCredentialsRequest {
+            access_token: token,
+            account_id: self.account_id.clone(),
+            role_name: self.role_name.clone()
+        };
+        let role = match client.get_role_credentials(request).await {
+            Ok(result) => result.role_credentials,
+            Err(RusotoError::Service(GetRoleCredentialsError::Unauthorized(_))) => return Err(self.login_needed("has expired")),
+            Err(why) => return Err(CredentialsError::new(format!("couldn't get SSO role credentials for profile {}: {}", self.profile, why)))
+        };
+        match role {
+            Some(r) => match (r.access_key_id, r.secret_access_key) {
+                (Some(key), Some(secret)) => {
+                    let expires = r.expiration.and_then(|ms| Utc.timestamp_millis_opt(ms).single());
+                    Ok(AwsCredentials::new(key, secret, r.session_token, expires))
+                },
+                _ => Err(CredentialsError::new(format!("SSO returned incomplete credentials for profile {}", self.profile)))
+            },
+            None => Err(CredentialsError::new(format!("SSO returned no credentials for profile {}", self.profile)))
+        }
+    }
+}
+
+impl SsoProvider {
+    /// The newest unexpired token `aws sso login` cached for this portal.
+    /// The cache files are named by a hash of the session, so they are
+    /// matched on their `startUrl` instead.
+    fn cached_token(&self) -> Result<String, CredentialsError> {
+        let home = env::var("HOME").unwrap_or_default();
+        let entries = fs::read_dir(format!("{}/.aws/sso/cache", home)).map_err(|_| self.login_needed("has no cached login"))?;
+        let cache: Vec<String> = entries.flatten().filter_map(|entry| fs::read_to_string(entry.path()).ok()).collect();
+        self.usable_token(&cache, Utc::now())
+    }
+
+    /// The newest of the cache files' tokens for this portal, if it is
+    /// still valid at `now`.
+    fn usable_token(&self, cache: &[String], now: DateTime<Utc>) -> Result<String, CredentialsError> {
+        let mut newest: Option<(DateTime<Utc>, String)> = None;
+        for text in cache.iter() {
+            let cached: Value = match serde_json::from_str(text) {
+                Ok(v) => v,
+                Err(_) => continue
+            };
+            if cached.get("startUrl").and_then(Value::as_str) != Some(self.start_url.as_str()) {
+                continue;
+            }
+            let token = cached.get("accessToken").and_then(Value::as_str);
+            let expires = cached.get("expiresAt").and_then(Value::as_str).and_then(parse_expiry);
+            if let (Some(token), Some(expires)) = (token, expires) {
+                if newest.as_ref().map_or(true, |(t, _)| expires > *t) {
+                    newest = Some((expires, token.to_string()));
+                }
+            }
+        }
+        match newest {
+            Some((expires, token)) if expires > now => Ok(token),
+            Some(_) => Err(self.login_needed("has expired")),
+            None => Err(self.login_needed("has no cached login"))
+        }
+    }
+
+    fn login_needed(&self, problem: &str) -> CredentialsError {
+        CredentialsError::new(format!("the SSO session for profile {} {}, run `aws sso login --profile {}` first", self.profile, problem, self.profile))
+    }
+}
+
+/// Older CLI versions wrote `2021-01-01T00:00:00UTC` rather than RFC 3339.
+fn parse_expiry(text: &str) -> Option<DateTime<Utc>> {
+    let text = match text.strip_suffix("UTC") {
+        Some(t) => format!("{}Z", t),
+        None => text.to_string()
+    };
+    DateTime::parse_from_rfc3339(&text).ok().map(|t| t.with_timezone(&Utc))
+}
+
+/// The config file's `key = value` settings by section header.
+fn sections(text: &str) -> BTreeMap<String, BTreeMap<String, String>> {
+    let mut sections: BTreeMap<String, BTreeMap<String, String>> = BTreeMap::new();
+    let mut current = None;
+    for line in text.lines().map(str::trim) {
+        if let Some(header) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            current = Some(header.split_whitespace().collect::<Vec<&str>>().join(" "));
+            continue;
+        }
+        if let (Some(section), Some((key, value))) = (&current, line.split_once('=')) {
+            sections.entry(section.clone()).or_insert_with(BTreeMap::new).insert(key.trim().to_string(), value.trim().to_string());
+        }
+    }
+    sections
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = "
+[default]
+region = eu-west-1
+
+[profile legacy]
+sso_start_url = https://legacy.awsapps.com/start
+sso_region = us-east-1
+sso_account_id = 111111111111
+sso_role_name = ReadOnly
+
+[profile   modern]
+sso_session = corp
+sso_account_id = 222222222222
+sso_role_name = Inventory
+
+[sso-session corp]
+sso_start_url = https://corp.awsapps.com/start
+sso_region = eu-west-1
+
+[profile dangling]
+sso_session = missing
+
+[profile partial]
+sso_start_url = https://corp.awsapps.com/start
+sso_region = eu-west-1
+sso_account_id = 333333333333
+";
+
+    const START_URL: &str = "https://corp.awsapps.com/start";
+
+    fn provider() -> SsoProvider {
+        configured(CONFIG, "modern").unwrap().unwrap()
+    }
+
+    fn cached(start_url: &str, token: &str, expires: &str) -> String {
+        serde_json::json!({ "startUrl": start_url, "region": "eu-west-1", "accessToken": token, "expiresAt": expires }).to_string()
+    }
+
+    fn now() -> DateTime<Utc> {
+        Utc.ymd(2026, 3, 20).and_hms(12, 0, 0)
+    }
+
+    #[test]
+    fn reads_legacy_sso_profiles() {
+        let legacy = configured(CONFIG, "legacy").unwrap().unwrap();
+        assert_eq!(legacy.start_url, "https://legacy.awsapps.com/start");
+        assert_eq!(legacy.region, Region::UsEast1);
+        assert_eq!(legacy.account_id, "111111111111");
+        assert_eq!(legacy.role_name, "ReadOnly");
+    }
+
+    #[test]
+    fn reads_profiles_using_an_sso_session() {
+        let modern = provider();
+        assert_eq!(modern.start_url, START_URL);
+        assert_eq!(modern.region, Region::EuWest1);
+        assert_eq!(modern.account_id, "222222222222");
+        assert_eq!(modern.role_name, "Inventory");
+    }
+
+    #[test]
+    fn ordinary_and_unknown_profiles_are_not_sso() {
+        assert!(configured(CONFIG, "default").unwrap().is_none());
+        assert!(configured(CONFIG, "nowhere").unwrap().is_none());
+    }
+
+    #[test]
+    fn reports_incomplete_sso_profiles() {
+        assert_eq!(configured(CONFIG, "dangling").err().unwrap().message, "profile dangling uses sso_session missing, which isn't defined");
+        assert_eq!(configured(CONFIG, "partial").err().unwrap().message, "SSO profile partial has no sso_role_name");
+    }
+
+    #[test]
+    fn uses_the_newest_token_for_the_portal() {
+        let cache = vec![
+            cached(START_URL, "older", "2026-03-20T18:00:00Z"),
+            cached(START_URL, "newest", "2026-03-21T06:00:00UTC"),
+            cached("https://other.awsapps.com/start", "other", "2026-03-22T00:00:00Z"),
+            "not json".to_string()
+        ];
+        assert_eq!(provider().usable_token(&cache, now()).unwrap(), "newest");
+    }
+
+    #[test]
+    fn an_expired_or_missing_token_asks_for_sso_login() {
+        let expired = vec![cached(START_URL, "stale", "2026-03-20T11:59:00Z")];
+        assert_eq!(provider().usable_token(&expired, now()).unwrap_err().message, "the SSO session for profile modern has expired, run `aws sso login --profile modern` first");
+        let other = vec![cached("https://other.awsapps.com/start", "other", "2026-03-22T00:00:00Z")];
+        assert_eq!(provider().usable_token(&other, now()).unwrap_err().message, "the SSO session for profile modern has no cached login, run `aws sso login --profile modern` first");
+    }
+}
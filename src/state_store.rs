@@ -0,0 +1,91 @@
+use crate::error::AppError;
+use crate::options::Options;
+use crate::Details;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use std::collections::BTreeMap;
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+
+/// Where the state is kept without `--state-file`, beside the results file.
+const DEFAULT_PATH: &str = ".instance_state.json";
+
+/// Where `--incremental` keeps its inventories, beside the state file.
+const INVENTORY_FILE: &str = ".instance_inventory.json";
+
+/// The instances of the last run that changed since the one before it:
+/// those not in the state file, or in it with a different state. `None`
+/// when there is no state file yet, so the first run writes everything.
+pub fn changed(options: &Options, details: &[Details]) -> Result<Option<Vec<Details>>, AppError> {
+    let prior: BTreeMap<String, String> = match load(&path(options))? {
+        Some(prior) => prior,
+        None => return Ok(None)
+    };
+    let changed = details.iter()
+        .filter(|d| match d.instance_id.as_ref().and_then(|id| prior.get(id)) {
+            Some(state) => d.state.as_ref() != Some(state),
+            None => true
+        })
+        .cloned()
+        .collect();
+    Ok(Some(changed))
+}
+
+/// Records each instance's current state for the next run to compare with.
+pub fn save(options: &Options, details: &[Details]) -> Result<(), AppError> {
+    let path = path(options);
+    let states: BTreeMap<&str, &str> = details.iter()
+        .filter_map(|d| Some((d.instance_id.as_deref()?, d.state.as_deref().unwrap_or_default())))
+        .collect();
+    let text = serde_json::to_string(&states).unwrap_or_default();
+    match std::fs::write(&path, text) {
+        Ok(_) => Ok(()),
+        Err(why) => Err(AppError::io(format!("couldn't write {}: {}", path.display(), why)))
+    }
+}
+
+/// The `--incremental` inventory saved under `key`, one per `--watch` or
+/// `--schedule`. `None` when none has been saved yet.
+pub fn load_inventory<T: DeserializeOwned>(options: &Options, key: &str) -> Result<Option<T>, AppError> {
+    let path = inventory_path(options);
+    let mut saved: BTreeMap<String, Value> = load(&path)?.unwrap_or_default();
+    match saved.remove(key).map(serde_json::from_value) {
+        Some(Ok(inventory)) => Ok(Some(inventory)),
+        Some(Err(why)) => Err(AppError::usage(format!("couldn't parse {}: {}", path.display(), why))),
+        None => Ok(None)
+    }
+}
+
+/// Saves `inventory` under `key`, keeping the other keys' as they were. A
+/// file that can't be read is replaced.
+pub fn save_inventory<T: Serialize>(options: &Options, key: &str, inventory: &T) -> Result<(), AppError> {
+    let path = inventory_path(options);
+    let mut saved: BTreeMap<String, Value> = load(&path).ok().flatten().unwrap_or_default();
+    saved.insert(key.to_string(), serde_json::to_value(inventory).unwrap_or_default());
+    let text = serde_json::to_string(&saved).unwrap_or_default();
+    match std::fs::write(&path, text) {
+        Ok(_) => Ok(()),
+        Err(why) => Err(AppError::io(format!("couldn't write {}: {}", path.display(), why)))
+    }
+}
+
+fn path(options: &Options) -> PathBuf {
+    options.state_file.clone().unwrap_or_else(|| PathBuf::from(DEFAULT_PATH))
+}
+
+fn inventory_path(options: &Options) -> PathBuf {
+    path(options).with_file_name(INVENTORY_FILE)
+}
+
+fn load<T: DeserializeOwned>(path: &Path) -> Result<Option<T>, AppError> {
+    let text = match std::fs::read_to_string(path) {
+        Ok(t) => t,
+        Err(why) if why.kind() == ErrorKind::NotFound => return Ok(None),
+        Err(why) => return Err(AppError::io(format!("couldn't read {}: {}", path.display(), why)))
+    };
+    match serde_json::from_str(&text) {
+        Ok(states) => Ok(Some(states)),
+        Err(why) => Err(AppError::usage(format!("couldn't parse {}: {}", path.display(), why)))
+    }
+}
@@ -0,0 +1,41 @@
+use crate::Details;
+use serde_json::{Map, Value};
+use std::collections::BTreeMap;
+
+/// Label for instances with no value for a grouping key.
+const NONE_BUCKET: &str = "(none)";
+
+/// Counts instances per distinct combination of the `--by` keys. Keys are
+/// output field names or `tags.<Key>` for any tag.
+pub fn summarize(by: &[String], details: &[Details]) -> Vec<Value> {
+    let mut counts: BTreeMap<Vec<String>, usize> = BTreeMap::new();
+    for d in details.iter() {
+        let record = serde_json::to_value(d).unwrap_or_default();
+        let group: Vec<String> = by.iter().map(|key| group_value(&record, key)).collect();
+        *counts.entry(group).or_insert(0) += 1;
+    }
+    counts.into_iter()
+        .map(|(group, count)| {
+            let mut row = Map::new();
+            for (key, value) in by.iter().zip(group.into_iter()) {
+                row.insert(key.clone(), Value::String(value));
+            }
+            row.insert("count".to_string(), Value::from(count));
+            Value::Object(row)
+        })
+        .collect()
+}
+
+/// The bucket `record` falls in for one `--by` key.
+pub fn group_value(record: &Value, key: &str) -> String {
+    let value = match key.strip_prefix("tags.") {
+        Some(tag) => record.get("tags").and_then(|t| t.get(tag)),
+        None => record.get(key)
+    };
+    match value {
+        None | Some(Value::Null) => NONE_BUCKET.to_string(),
+        Some(Value::String(s)) if s.is_empty() => NONE_BUCKET.to_string(),
+        Some(Value::String(s)) => s.clone(),
+        Some(other) => other.to_string()
+    }
+}
@@ -0,0 +1,97 @@
+use log::debug;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+/// Set once READY=1 has been sent; systemd only needs it the first time.
+static READY: AtomicBool = AtomicBool::new(false);
+
+/// Tells systemd how a collection went: the summary as STATUS=, and
+/// READY=1 after the first one that succeeded. Does nothing unless run
+/// by systemd with `Type=notify`, which sets `NOTIFY_SOCKET`.
+pub fn collected(summary: &str, succeeded: bool) {
+    let mut state = format!("STATUS={}", summary);
+    if succeeded && !READY.swap(true, Ordering::SeqCst) {
+        state.push_str("\nREADY=1");
+    }
+    notify(&state);
+}
+
+pub fn stopping() {
+    notify("STOPPING=1");
+}
+
+/// Waits out `wait` between collections, sending WATCHDOG=1 often enough
+/// to satisfy `WatchdogSec=`. Nothing is sent while a collection runs, so
+/// one that hangs for longer than that gets the service restarted.
+///
+/// With several `--schedule`s, one waiting for its next tick keeps pinging
+/// while another's collection runs.
+pub async fn idle(wait: Duration) {
+    let every = match watchdog_interval() {
+        Some(every) => every,
+        None => return tokio::time::sleep(wait).await
+    };
+    let until = Instant::now() + wait;
+    loop {
+        notify("WATCHDOG=1");
+        let left = until.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            return;
+        }
+        tokio::time::sleep(every.min(left)).await;
+    }
+}
+
+/// Half the `WATCHDOG_USEC` systemd asks for, as sd_watchdog_enabled
+/// recommends, when the watchdog is meant for this process.
+fn watchdog_interval() -> Option<Duration> {
+    let usec: u64 = std::env::var("WATCHDOG_USEC").ok()?.parse().ok()?;
+    if let Ok(pid) = std::env::var("WATCHDOG_PID") {
+        if pid.parse::<u32>().ok() != Some(std::process::id()) {
+            return None
+        }
+    }
+    match usec {
+        0 => None,
+        usec => Some(Duration::from_micros(usec / 2))
+    }
+}
+
+/// Sends one datagram to `NOTIFY_SOCKET`, a path or, with a leading `@`,
+/// an abstract socket name. Failures are only logged; the service runs the
+/// same whether or not systemd heard.
+#[cfg(unix)]
+fn notify(state: &str) {
+    use std::os::unix::net::UnixDatagram;
+    let path = match std::env::var("NOTIFY_SOCKET") {
+        Ok(path) if !path.is_empty() => path,
+        _ => return
+    };
+    let socket = match UnixDatagram::unbound() {
+        Ok(socket) => socket,
+        Err(why) => return debug!("couldn't open a socket to notify systemd: {}", why)
+    };
+    let sent = match path.strip_prefix('@') {
+        Some(name) => send_abstract(&socket, name, state),
+        None => socket.send_to(state.as_bytes(), &path).map(|_| ())
+    };
+    if let Err(why) = sent {
+        debug!("couldn't notify systemd at {}: {}", path, why);
+    }
+}
+
+#[cfg(not(unix))]
+fn notify(_state: &str) {}
+
+#[cfg(target_os = "linux")]
+fn send_abstract(socket: &std::os::unix::net::UnixDatagram, name: &str, state: &str) -> std::io::Result<()> {
+    use std::os::linux::net::SocketAddrExt;
+    let address = std::os::unix::net::SocketAddr::from_abstract_name(name.as_bytes())?;
+    socket.send_to_addr(state.as_bytes(), &address).map(|_| ())
+}
+
+#[cfg(all(unix, not(target_os = "linux")))]
+fn send_abstract(_socket: &std::os::unix::net::UnixDatagram, name: &str, _state: &str) -> std::io::Result<()> {
+    Err(std::io::Error::new(std::io::ErrorKind::Unsupported, format!("abstract socket @{} is Linux only", name)))
+}
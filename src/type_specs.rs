@@ -0,0 +1,90 @@
+use crate::credentials::CredentialContext;
+use crate::error::{self, AppError, ErrorFormat};
+use crate::{aws_error, identity, retry, Details};
+use rusoto_ec2::{DescribeInstanceTypesRequest, Ec2, Ec2Client, InstanceTypeInfo};
+use std::collections::{BTreeMap, BTreeSet, HashMap};
+
+/// DescribeInstanceTypes accepts at most 100 types per call.
+const TYPES_PER_CALL: usize = 100;
+
+#[derive(Debug, Clone)]
+pub struct TypeSpec {
+    default_vcpus: Option<i64>,
+    memory_mib: Option<i64>,
+    network_performance: Option<String>
+}
+
+/// Specs already fetched, by instance type, so each type is looked up once
+/// per run whichever region or profile it turns up in.
+pub type SpecCache = HashMap<String, TypeSpec>;
+
+/// Instance types in `details` that aren't cached yet, by region.
+pub fn missing(cache: &SpecCache, details: &[Details]) -> BTreeMap<String, BTreeSet<String>> {
+    let mut wanted: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for d in details.iter() {
+        if let Some(t) = &d.instance_type {
+            if !cache.contains_key(t) {
+                wanted.entry(d.region.clone()).or_insert_with(BTreeSet::new).insert(t.clone());
+            }
+        }
+    }
+    wanted
+}
+
+/// Looks the types up in the region they were found in, as not every
+/// region offers every type.
+pub async fn fetch(ctx: &CredentialContext, cache: &mut SpecCache, wanted: BTreeMap<String, BTreeSet<String>>, error_format: ErrorFormat) {
+    for (region, types) in wanted.into_iter() {
+        let types: Vec<String> = types.into_iter().filter(|t| !cache.contains_key(t)).collect();
+        let client = Ec2Client::new_with_client(ctx.client_for(&region), identity::ec2_region(&region));
+        for chunk in types.chunks(TYPES_PER_CALL) {
+            let mut request = DescribeInstanceTypesRequest {
+                instance_types: Some(chunk.to_vec()),
+                ..Default::default()
+            };
+            loop {
+                let result = retry::with_retry(&region, request.clone(), |req| {
+                    let c = client.clone();
+                    async move { c.describe_instance_types(req).await }
+                }).await;
+                match result {
+                    Ok(page) => {
+                        for info in page.instance_types.unwrap_or_default() {
+                            if let Some(t) = info.instance_type.clone() {
+                                cache.insert(t, spec(info));
+                            }
+                        }
+                        match page.next_token {
+                            Some(token) => request.next_token = Some(token),
+                            None => break
+                        }
+                    },
+                    Err(why) => {
+                        let failure = AppError::aws(&region, format!("failed to describe instance types: {}", aws_error::describe(&why)));
+                        error::report(&failure.with_request_id(aws_error::request_id(&why)), error_format);
+                        break;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Joins cached specs onto every instance of a known type.
+pub fn apply(cache: &SpecCache, details: &mut [Details]) {
+    for d in details.iter_mut() {
+        if let Some(spec) = d.instance_type.as_ref().and_then(|t| cache.get(t)) {
+            d.default_vcpus = spec.default_vcpus;
+            d.memory_mib = spec.memory_mib;
+            d.network_performance = spec.network_performance.clone();
+        }
+    }
+}
+
+fn spec(info: InstanceTypeInfo) -> TypeSpec {
+    TypeSpec {
+        default_vcpus: info.v_cpu_info.and_then(|v| v.default_v_cpus),
+        memory_mib: info.memory_info.and_then(|m| m.size_in_mi_b),
+        network_performance: info.network_info.and_then(|n| n.network_performance)
+    }
+}
@@ -0,0 +1,72 @@
+use crate::changes::Changes;
+use hyper::{Body, Client, Method, Request};
+use hyper_tls::HttpsConnector;
+use log::{info, warn};
+use serde_json::{json, Value};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+use tokio::time::Instant;
+
+const MAX_ATTEMPTS: u32 = 4;
+const BASE_DELAY_MS: u64 = 500;
+
+/// Deliveries started and not yet succeeded or given up on.
+static PENDING: AtomicUsize = AtomicUsize::new(0);
+
+/// POSTs `changes` to `url` in the background, retrying failed deliveries
+/// with backoff. Failures are only logged, so a dead endpoint never holds up
+/// or breaks the next collection.
+///
+/// The payload carries a `text` summary alongside the details, which is
+/// all a Slack incoming webhook needs to post it to a channel.
+pub fn send(url: &str, changes: &Changes) {
+    let payload = json!({
+        "added": changes.added,
+        "removed": changes.removed,
+        "state_changes": changes.state_changes,
+        "text": format!("EC2 inventory changed: {}", changes.summary())
+    });
+    let url = url.to_string();
+    PENDING.fetch_add(1, Ordering::SeqCst);
+    tokio::spawn(async move {
+        deliver(&url, &payload).await;
+        PENDING.fetch_sub(1, Ordering::SeqCst);
+    });
+}
+
+/// Waits up to `within` for deliveries in flight, returning how many are
+/// still unfinished.
+pub async fn flush(within: Duration) -> usize {
+    let deadline = Instant::now() + within;
+    while PENDING.load(Ordering::SeqCst) > 0 && Instant::now() < deadline {
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+    PENDING.load(Ordering::SeqCst)
+}
+
+async fn deliver(url: &str, payload: &Value) {
+    let client = Client::builder().build::<_, Body>(HttpsConnector::new());
+    for attempt in 1..=MAX_ATTEMPTS {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(url)
+            .header("content-type", "application/json")
+            .body(Body::from(payload.to_string()));
+        let request = match request {
+            Ok(r) => r,
+            Err(why) => return warn!("couldn't build the notification for {}: {}", url, why)
+        };
+        let failure = match client.request(request).await {
+            Ok(response) if response.status().is_success() => return info!("notified {}", url),
+            Ok(response) => format!("status {}", response.status()),
+            Err(why) => why.to_string()
+        };
+        match attempt < MAX_ATTEMPTS {
+            true => {
+                warn!("notifying {} failed ({}), retrying", url, failure);
+                tokio::time::sleep(Duration::from_millis(BASE_DELAY_MS * 2u64.pow(attempt - 1))).await;
+            },
+            false => warn!("notifying {} failed after {} attempts: {}", url, MAX_ATTEMPTS, failure)
+        }
+    }
+}